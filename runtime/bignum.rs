@@ -0,0 +1,304 @@
+//! Arbitrary-precision integers, for numbers that don't fit in a 63-bit fixnum. Only what the
+//! runtime needs is here: the four arithmetic operations, remainders, bitwise operations and
+//! shifts, comparison, and conversion to decimal and to floats.
+
+use std::{cmp::Ordering, fmt};
+
+/// A sign and a magnitude in little-endian 64-bit limbs, without trailing zero limbs. Zero has an
+/// empty magnitude and is never negative.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigInt {
+    pub neg: bool,
+    pub mag: Vec<u64>,
+}
+
+impl BigInt {
+    pub fn new(neg: bool, mut mag: Vec<u64>) -> BigInt {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        let neg = neg && !mag.is_empty();
+        BigInt { neg, mag }
+    }
+
+    pub fn from_i64(n: i64) -> BigInt {
+        BigInt::new(n < 0, vec![n.unsigned_abs()])
+    }
+
+    /// The value as an `i64`, if it is in `min..=max`.
+    pub fn to_i64_in(&self, min: i64, max: i64) -> Option<i64> {
+        let n: i128 = match self.mag[..] {
+            [] => 0,
+            [limb] => limb as i128,
+            _ => return None,
+        };
+        let n = if self.neg { -n } else { n };
+        if (min as i128..=max as i128).contains(&n) {
+            Some(n as i64)
+        } else {
+            None
+        }
+    }
+
+    /// The nearest double, or an infinity if the value is out of range.
+    pub fn to_f64(&self) -> f64 {
+        let mag = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &limb| acc * 18446744073709551616.0 + limb as f64);
+        if self.neg {
+            -mag
+        } else {
+            mag
+        }
+    }
+
+    /// Parses an optionally signed decimal number.
+    pub fn parse(s: &str) -> Option<BigInt> {
+        let (neg, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if digits.is_empty() {
+            return None;
+        }
+        let mut mag = vec![];
+        for c in digits.chars() {
+            let digit = c.to_digit(10)?;
+            mul_small(&mut mag, 10, digit as u64);
+        }
+        Some(BigInt::new(neg, mag))
+    }
+
+    pub fn add(&self, other: &BigInt) -> BigInt {
+        if self.neg == other.neg {
+            return BigInt::new(self.neg, add_mag(&self.mag, &other.mag));
+        }
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::new(other.neg, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::new(self.neg, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+
+    pub fn sub(&self, other: &BigInt) -> BigInt {
+        self.add(&other.negate())
+    }
+
+    pub fn mul(&self, other: &BigInt) -> BigInt {
+        let mut mag = vec![0; self.mag.len() + other.mag.len()];
+        for (i, &a) in self.mag.iter().enumerate() {
+            let mut carry = 0u128;
+            for (j, &b) in other.mag.iter().enumerate() {
+                let t = a as u128 * b as u128 + mag[i + j] as u128 + carry;
+                mag[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            mag[i + other.mag.len()] = carry as u64;
+        }
+        BigInt::new(self.neg != other.neg, mag)
+    }
+
+    /// The quotient rounded toward zero, or `None` when dividing by zero.
+    pub fn div(&self, other: &BigInt) -> Option<BigInt> {
+        Some(self.div_rem(other)?.0)
+    }
+
+    /// The quotient rounded toward zero and the remainder, which has the sign of `self`, or
+    /// `None` when dividing by zero.
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.mag.is_empty() {
+            return None;
+        }
+        // Shift-and-subtract long division, one bit of the quotient at a time.
+        let mut quot = vec![0; self.mag.len()];
+        let mut rem: Vec<u64> = vec![];
+        for i in (0..self.mag.len() * 64).rev() {
+            mul_small(&mut rem, 2, self.mag[i / 64] >> (i % 64) & 1);
+            if cmp_mag(&rem, &other.mag) != Ordering::Less {
+                rem = sub_mag(&rem, &other.mag);
+                quot[i / 64] |= 1 << (i % 64);
+            }
+        }
+        let quot = BigInt::new(self.neg != other.neg, quot);
+        Some((quot, BigInt::new(self.neg, rem)))
+    }
+
+    /// The remainder of the quotient rounded down, which has the sign of `other`, or `None` when
+    /// dividing by zero.
+    pub fn modulo(&self, other: &BigInt) -> Option<BigInt> {
+        let (_, rem) = self.div_rem(other)?;
+        if !rem.mag.is_empty() && rem.neg != other.neg {
+            Some(rem.add(other))
+        } else {
+            Some(rem)
+        }
+    }
+
+    /// Combines the two's complement bits of the values with `f`, which is applied a limb at a
+    /// time, sign extension included.
+    pub fn bitwise(&self, other: &BigInt, f: impl Fn(u64, u64) -> u64) -> BigInt {
+        let len = self.mag.len().max(other.mag.len()) + 1;
+        let (a, b) = (self.to_twos(len), other.to_twos(len));
+        BigInt::from_twos(a.iter().zip(&b).map(|(&x, &y)| f(x, y)).collect())
+    }
+
+    /// The value times `2^k`.
+    pub fn shl(&self, k: usize) -> BigInt {
+        let (limbs, bits) = (k / 64, k % 64);
+        let mut mag = vec![0; limbs];
+        let mut carry = 0;
+        for &limb in &self.mag {
+            mag.push(limb << bits | carry);
+            carry = if bits == 0 { 0 } else { limb >> (64 - bits) };
+        }
+        mag.push(carry);
+        BigInt::new(self.neg, mag)
+    }
+
+    /// The value divided by `2^k`, rounded down.
+    pub fn shr(&self, k: usize) -> BigInt {
+        if self.neg {
+            // Rounding down -n is rounding (n - 1) down and subtracting the result from -1.
+            let one = BigInt::from_i64(1);
+            return self.negate().sub(&one).shr(k).negate().sub(&one);
+        }
+        let (limbs, bits) = (k / 64, k % 64);
+        let mag = (limbs..self.mag.len())
+            .map(|i| {
+                let high = match self.mag.get(i + 1) {
+                    Some(&next) if bits != 0 => next << (64 - bits),
+                    _ => 0,
+                };
+                self.mag[i] >> bits | high
+            })
+            .collect();
+        BigInt::new(false, mag)
+    }
+
+    /// The value in two's complement in `len` limbs, which must leave room for the sign bit.
+    fn to_twos(&self, len: usize) -> Vec<u64> {
+        let mut limbs = self.mag.clone();
+        limbs.resize(len, 0);
+        if self.neg {
+            // -n is the complement of n - 1.
+            let mut borrow = true;
+            for limb in &mut limbs {
+                let (d, b) = limb.overflowing_sub(borrow as u64);
+                *limb = !d;
+                borrow = b;
+            }
+        }
+        limbs
+    }
+
+    /// The value of `limbs` in two's complement.
+    fn from_twos(mut limbs: Vec<u64>) -> BigInt {
+        let neg = limbs.last().is_some_and(|&limb| limb >> 63 == 1);
+        if neg {
+            // The magnitude is the complement plus one.
+            let mut carry = true;
+            for limb in &mut limbs {
+                let (s, c) = (!*limb).overflowing_add(carry as u64);
+                *limb = s;
+                carry = c;
+            }
+        }
+        BigInt::new(neg, limbs)
+    }
+
+    pub fn negate(&self) -> BigInt {
+        BigInt::new(!self.neg, self.mag.clone())
+    }
+
+    pub fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.neg, other.neg) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Peel off 18 decimal digits at a time.
+        const CHUNK: u64 = 1_000_000_000_000_000_000;
+        let mut mag = self.mag.clone();
+        let mut chunks = vec![];
+        while !mag.is_empty() {
+            chunks.push(div_small(&mut mag, CHUNK));
+        }
+        if self.neg {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap_or(0))?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{chunk:018}")?;
+        }
+        Ok(())
+    }
+}
+
+fn cmp_mag(a: &[u64], b: &[u64]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let mut sum = vec![];
+    let mut carry = false;
+    for i in 0..a.len().max(b.len()) {
+        let (s, c1) = a.get(i).unwrap_or(&0).overflowing_add(*b.get(i).unwrap_or(&0));
+        let (s, c2) = s.overflowing_add(carry as u64);
+        sum.push(s);
+        carry = c1 || c2;
+    }
+    sum.push(carry as u64);
+    sum
+}
+
+/// `a - b`, where `a >= b`.
+fn sub_mag(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let mut diff = vec![];
+    let mut borrow = false;
+    for (i, &limb) in a.iter().enumerate() {
+        let (d, b1) = limb.overflowing_sub(*b.get(i).unwrap_or(&0));
+        let (d, b2) = d.overflowing_sub(borrow as u64);
+        diff.push(d);
+        borrow = b1 || b2;
+    }
+    while diff.last() == Some(&0) {
+        diff.pop();
+    }
+    diff
+}
+
+/// Sets `mag` to `mag * m + add`.
+fn mul_small(mag: &mut Vec<u64>, m: u64, add: u64) {
+    let mut carry = add as u128;
+    for limb in mag.iter_mut() {
+        let t = *limb as u128 * m as u128 + carry;
+        *limb = t as u64;
+        carry = t >> 64;
+    }
+    if carry != 0 {
+        mag.push(carry as u64);
+    }
+}
+
+/// Divides `mag` by `d` in place, returning the remainder.
+fn div_small(mag: &mut Vec<u64>, d: u64) -> u64 {
+    let mut rem = 0u128;
+    for limb in mag.iter_mut().rev() {
+        let t = rem << 64 | *limb as u128;
+        *limb = (t / d as u128) as u64;
+        rem = t % d as u128;
+    }
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    rem as u64
+}
@@ -0,0 +1,366 @@
+//! Dictionaries: hash tables on the snek heap keyed by numbers, booleans, nil and vectors.
+//!
+//! A dictionary is an object of kind `DICT_KIND` whose payload is four snek values: its number of
+//! entries, whether it compares vector keys by structure rather than by identity, its table, and
+//! the collection count when the table was last hashed. The table is an ordinary vector of
+//! key/value pairs, probed linearly, whose empty slots hold `EMPTY`.
+//!
+//! A vector compared by identity hashes by its address, which changes when the collector moves it,
+//! so a dictionary rehashes its table the first time it is used after a collection.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+use super::{
+    equal, is_ref, len, obj_kind, reserve, try_to_num, untag, write_raw_object, Allocated, Num, SnekVal,
+    DICT_KIND, FALSE, GC_COUNT, NIL, TRUE, VEC_KIND,
+};
+
+const COUNT: usize = 2;
+const STRUCTURAL: usize = 3;
+const TABLE: usize = 4;
+const EPOCH: usize = 5;
+
+/// Marks an empty slot of a table. It isn't a snek value, and the collector doesn't trace it.
+const EMPTY: SnekVal = 0b1011;
+/// The number of slots in a new dictionary's table. Capacities are powers of two.
+const INITIAL_CAPACITY: usize = 8;
+/// How deep hashing a vector compared by structure looks into nested vectors.
+const MAX_HASH_DEPTH: usize = 4;
+
+/// The `i`th operand of a dictionary operation. The compiled code passes the address of the stack
+/// slot holding the first one, and the others are in the slots below it. They're read from there
+/// again after allocating, since the collector may have moved them.
+unsafe fn arg(args: *const u64, i: usize) -> SnekVal {
+    *args.sub(i)
+}
+
+/// The dictionary `val` points to, if it is one.
+unsafe fn dict(val: SnekVal) -> Option<*mut u64> {
+    if obj_kind(val) == Some(DICT_KIND) {
+        Some(untag(val))
+    } else {
+        None
+    }
+}
+
+unsafe fn structural(d: *mut u64) -> bool {
+    *d.add(STRUCTURAL) == TRUE
+}
+
+unsafe fn count(d: *mut u64) -> usize {
+    (*d.add(COUNT) >> 1) as usize
+}
+
+unsafe fn set_count(d: *mut u64, count: usize) {
+    *d.add(COUNT) = (count as u64) << 1;
+}
+
+/// The first slot of `d`'s table and the table's capacity.
+unsafe fn table(d: *mut u64) -> (*mut u64, usize) {
+    let table = untag(*d.add(TABLE));
+    (table.add(2), *table.add(1) as usize / 2)
+}
+
+/// The hash of `key` in a dictionary that compares vectors by structure if `structural` is set, or
+/// `None` if `key` can't be a key.
+unsafe fn hash_key(key: SnekVal, structural: bool) -> Option<u64> {
+    let is_vec = key & 0b111 == 0b001;
+    if !(is_vec || key == TRUE || key == FALSE || try_to_num(key).is_some()) {
+        return None;
+    }
+    let mut hasher = DefaultHasher::new();
+    hash_value(key, structural, 0, &mut hasher);
+    Some(hasher.finish())
+}
+
+/// Feeds `val` to `hasher` so that keys that compare equal hash the same. Numbers hash as the
+/// double nearest to them, since `=` compares an integer and a float that way. Inside a vector
+/// compared by structure, heap objects other than numbers and vectors only hash by their header,
+/// so that moving them doesn't change the hash.
+unsafe fn hash_value(val: SnekVal, structural: bool, depth: usize, hasher: &mut DefaultHasher) {
+    if let Some(n) = try_to_num(val) {
+        // Adding 0.0 turns -0.0 into 0.0, which compares equal to it.
+        (n.to_f64() + 0.0).to_bits().hash(hasher);
+    } else if structural && val & 0b111 == 0b001 && val != NIL {
+        let vec = untag(val);
+        let len = len(*vec.add(1));
+        len.hash(hasher);
+        if depth < MAX_HASH_DEPTH {
+            for i in 0..len {
+                hash_value(*vec.add(2 + i), structural, depth + 1, hasher);
+            }
+        }
+    } else if depth > 0 && is_ref(val) {
+        (*untag(val).add(1)).hash(hasher);
+    } else {
+        val.hash(hasher);
+    }
+}
+
+/// Whether `a` and `b` are the same key: numbers are compared like `=` does, and vectors by
+/// structure or identity.
+unsafe fn keys_equal(a: SnekVal, b: SnekVal, structural: bool) -> bool {
+    match (try_to_num(a), try_to_num(b)) {
+        (Some(Num::Int(x)), Some(Num::Int(y))) => x == y,
+        (Some(x), Some(y)) => x.to_f64() == y.to_f64(),
+        (None, None) => a == b || structural && equal(a, b, &mut HashSet::new()),
+        _ => false,
+    }
+}
+
+/// The slot holding `key` in `d`'s table, or else the empty slot where it would go.
+unsafe fn find(d: *mut u64, key: SnekVal, hash: u64) -> Result<usize, usize> {
+    let (slots, cap) = table(d);
+    let mut i = hash as usize & (cap - 1);
+    loop {
+        let k = *slots.add(2 * i);
+        if k == EMPTY {
+            return Err(i);
+        }
+        if keys_equal(k, key, structural(d)) {
+            return Ok(i);
+        }
+        i = (i + 1) & (cap - 1);
+    }
+}
+
+/// The entries of the dictionary at `d`, in table order.
+pub unsafe fn entries(d: *mut u64) -> Vec<(SnekVal, SnekVal)> {
+    let (slots, cap) = table(d);
+    (0..cap)
+        .map(|i| (*slots.add(2 * i), *slots.add(2 * i + 1)))
+        .filter(|&(k, _)| k != EMPTY)
+        .collect()
+}
+
+/// Empties `d`'s table and inserts `entries` into it again.
+unsafe fn rehash(d: *mut u64, entries: Vec<(SnekVal, SnekVal)>) {
+    let (slots, cap) = table(d);
+    for i in 0..2 * cap {
+        *slots.add(i) = EMPTY;
+    }
+    for (k, v) in entries {
+        let hash = hash_key(k, structural(d)).unwrap();
+        let i = find(d, k, hash).unwrap_err();
+        *slots.add(2 * i) = k;
+        *slots.add(2 * i + 1) = v;
+    }
+    *d.add(EPOCH) = GC_COUNT << 1;
+}
+
+/// Rehashes `d` if it compares vectors by identity and keys may have moved since it was hashed.
+unsafe fn ensure_hashed(d: *mut u64) {
+    if !structural(d) && *d.add(EPOCH) != GC_COUNT << 1 {
+        rehash(d, entries(d));
+    }
+}
+
+/// Writes a new table with room for `cap` entries at `obj`, returning it as a snek value.
+unsafe fn write_table(obj: *mut u64, cap: usize) -> SnekVal {
+    write_raw_object(obj, VEC_KIND, &vec![EMPTY; 2 * cap]);
+    obj as u64 | 0b001
+}
+
+fn table_words(cap: usize) -> usize {
+    2 + 2 * cap
+}
+
+/// The result of an operation whose operands aren't valid: a null heap pointer, for the compiled
+/// code to raise an invalid argument error. Any value could be a valid result.
+fn invalid() -> Allocated {
+    Allocated {
+        val: NIL,
+        heap_ptr: std::ptr::null(),
+    }
+}
+
+/// `(make-dict by-structure)`: a new empty dictionary. See [`super::snek_try_gc`] for the rest of
+/// the arguments, here and below.
+#[export_name = "\x01snek_make_dict"]
+pub unsafe extern "C" fn snek_make_dict(
+    args: *const u64,
+    heap_ptr: *const u64,
+    stack_base: *const u64,
+    curr_rbp: *const u64,
+    curr_rsp: *const u64,
+) -> Allocated {
+    let structural = if arg(args, 0) != FALSE { TRUE } else { FALSE };
+    let words = table_words(INITIAL_CAPACITY) + 6;
+    let obj = reserve(words, heap_ptr, stack_base, curr_rbp, curr_rsp);
+    let table = write_table(obj, INITIAL_CAPACITY);
+    let d = obj.add(table_words(INITIAL_CAPACITY));
+    write_raw_object(d, DICT_KIND, &[0, structural, table, GC_COUNT << 1]);
+    Allocated {
+        val: d as u64 | 0b101,
+        heap_ptr: obj.add(words),
+    }
+}
+
+/// `(dict-set! d k v)`, which grows the table once it is three quarters full.
+#[export_name = "\x01snek_dict_set"]
+pub unsafe extern "C" fn snek_dict_set(
+    args: *const u64,
+    heap_ptr: *const u64,
+    stack_base: *const u64,
+    curr_rbp: *const u64,
+    curr_rsp: *const u64,
+) -> Allocated {
+    let Some(d) = dict(arg(args, 0)) else {
+        return invalid();
+    };
+    let Some(hash) = hash_key(arg(args, 1), structural(d)) else {
+        return invalid();
+    };
+    ensure_hashed(d);
+    if let Ok(i) = find(d, arg(args, 1), hash) {
+        *table(d).0.add(2 * i + 1) = arg(args, 2);
+        return Allocated {
+            val: arg(args, 0),
+            heap_ptr,
+        };
+    }
+
+    let mut heap_ptr = heap_ptr;
+    let (_, cap) = table(d);
+    if 4 * (count(d) + 1) > 3 * cap {
+        let words = table_words(2 * cap);
+        let obj = reserve(words, heap_ptr, stack_base, curr_rbp, curr_rsp);
+        heap_ptr = obj.add(words);
+        let d = untag(arg(args, 0));
+        let old = entries(d);
+        *d.add(TABLE) = write_table(obj, 2 * cap);
+        rehash(d, old);
+    }
+    let (d, key) = (untag(arg(args, 0)), arg(args, 1));
+    ensure_hashed(d);
+    let i = find(d, key, hash_key(key, structural(d)).unwrap()).unwrap_err();
+    let (slots, _) = table(d);
+    *slots.add(2 * i) = key;
+    *slots.add(2 * i + 1) = arg(args, 2);
+    set_count(d, count(d) + 1);
+    Allocated {
+        val: arg(args, 0),
+        heap_ptr,
+    }
+}
+
+/// `(dict-get d k default)`.
+#[export_name = "\x01snek_dict_get"]
+pub unsafe extern "C" fn snek_dict_get(
+    args: *const u64,
+    heap_ptr: *const u64,
+    _stack_base: *const u64,
+    _curr_rbp: *const u64,
+    _curr_rsp: *const u64,
+) -> Allocated {
+    let val = match lookup(args) {
+        Some(Ok(i)) => *table(untag(arg(args, 0))).0.add(2 * i + 1),
+        Some(Err(_)) => arg(args, 2),
+        None => return invalid(),
+    };
+    Allocated { val, heap_ptr }
+}
+
+/// `(dict-has? d k)`.
+#[export_name = "\x01snek_dict_has"]
+pub unsafe extern "C" fn snek_dict_has(
+    args: *const u64,
+    heap_ptr: *const u64,
+    _stack_base: *const u64,
+    _curr_rbp: *const u64,
+    _curr_rsp: *const u64,
+) -> Allocated {
+    let val = match lookup(args) {
+        Some(Ok(_)) => TRUE,
+        Some(Err(_)) => FALSE,
+        None => return invalid(),
+    };
+    Allocated { val, heap_ptr }
+}
+
+/// `(dict-remove! d k)`. The entries after the removed one in its probe sequence are shifted back,
+/// so lookups never have to skip over removed slots.
+#[export_name = "\x01snek_dict_remove"]
+pub unsafe extern "C" fn snek_dict_remove(
+    args: *const u64,
+    heap_ptr: *const u64,
+    _stack_base: *const u64,
+    _curr_rbp: *const u64,
+    _curr_rsp: *const u64,
+) -> Allocated {
+    let mut hole = match lookup(args) {
+        Some(Ok(i)) => i,
+        Some(Err(_)) => {
+            return Allocated {
+                val: arg(args, 0),
+                heap_ptr,
+            }
+        }
+        None => return invalid(),
+    };
+    let d = untag(arg(args, 0));
+    let (slots, cap) = table(d);
+    let mut i = hole;
+    loop {
+        i = (i + 1) & (cap - 1);
+        let k = *slots.add(2 * i);
+        if k == EMPTY {
+            break;
+        }
+        // The entry at `i` can fill the hole unless its probe sequence starts after the hole.
+        let home = hash_key(k, structural(d)).unwrap() as usize & (cap - 1);
+        let starts_after_hole = if hole <= i {
+            hole < home && home <= i
+        } else {
+            hole < home || home <= i
+        };
+        if !starts_after_hole {
+            *slots.add(2 * hole) = k;
+            *slots.add(2 * hole + 1) = *slots.add(2 * i + 1);
+            hole = i;
+        }
+    }
+    *slots.add(2 * hole) = EMPTY;
+    *slots.add(2 * hole + 1) = NIL;
+    set_count(d, count(d) - 1);
+    Allocated {
+        val: arg(args, 0),
+        heap_ptr,
+    }
+}
+
+/// `(dict-keys d)`.
+#[export_name = "\x01snek_dict_keys"]
+pub unsafe extern "C" fn snek_dict_keys(
+    args: *const u64,
+    heap_ptr: *const u64,
+    stack_base: *const u64,
+    curr_rbp: *const u64,
+    curr_rsp: *const u64,
+) -> Allocated {
+    let Some(d) = dict(arg(args, 0)) else {
+        return invalid();
+    };
+    let words = 2 + count(d);
+    let obj = reserve(words, heap_ptr, stack_base, curr_rbp, curr_rsp);
+    let keys: Vec<SnekVal> = entries(untag(arg(args, 0)))
+        .into_iter()
+        .map(|(k, _)| k)
+        .collect();
+    write_raw_object(obj, VEC_KIND, &keys);
+    Allocated {
+        val: obj as u64 | 0b001,
+        heap_ptr: obj.add(words),
+    }
+}
+
+/// Looks up the key `arg(args, 1)` in the dictionary `arg(args, 0)`, or returns `None` if either
+/// isn't valid.
+unsafe fn lookup(args: *const u64) -> Option<Result<usize, usize>> {
+    let d = dict(arg(args, 0))?;
+    let hash = hash_key(arg(args, 1), structural(d))?;
+    ensure_hashed(d);
+    Some(find(d, arg(args, 1), hash))
+}
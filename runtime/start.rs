@@ -1,4 +1,16 @@
-use std::{collections::HashSet, env, convert::TryInto};
+use std::{
+    collections::HashSet,
+    convert::TryInto,
+    env,
+    ffi::CStr,
+    io::{self, BufRead, Write},
+    os::raw::c_char,
+};
+
+mod bignum;
+mod dict;
+
+use bignum::BigInt;
 
 type SnekVal = u64;
 
@@ -10,13 +22,56 @@ pub enum ErrCode {
     IndexOutOfBounds = 3,
     InvalidVecSize = 4,
     OutOfMemory = 5,
+    WrongArity = 6,
+    WrongRecordType = 7,
+    DivideByZero = 8,
+    UncaughtException = 9,
+    NoMatch = 10,
+    InvalidInput = 11,
 }
 
 const TRUE: u64 = 7;
 const FALSE: u64 = 3;
+const NIL: u64 = 1;
+
+/// Heap objects are laid out as `[gc word][header][payload...]`. The header holds the object's kind
+/// in its top byte and its payload length in words in its low 32 bits. Vectors are kind 0 and
+/// tagged with `0b001`; every other kind is tagged with `0b101`. Kinds must match src/compiler.rs,
+/// which also uses kind 2 for the boxes holding captured variables that get assigned.
+const VEC_KIND: u64 = 0;
+const CLOSURE_KIND: u64 = 1;
+/// A record's header also holds its struct's type id in bits 32 to 55.
+const RECORD_KIND: u64 = 3;
+/// A bignum's payload is its sign (1 if negative) followed by its magnitude in little-endian
+/// limbs, all raw words. Bignums are only used for values outside the fixnum range.
+const BIGNUM_KIND: u64 = 4;
+/// A float's payload is the raw bits of a double.
+const FLOAT_KIND: u64 = 5;
+/// An error's payload is its `ErrCode`, as a fixnum. Errors are static objects in the data section
+/// that the compiled code raises, except that a `match` no arm matches raises a new error whose
+/// payload also holds the value.
+const ERROR_KIND: u64 = 6;
+/// A dictionary; see runtime/dict.rs.
+const DICT_KIND: u64 = 7;
+
+/// The range of numbers that fit in a tagged fixnum.
+const FIXNUM_MIN: i64 = -(1 << 62);
+const FIXNUM_MAX: i64 = (1 << 62) - 1;
 
 static mut HEAP_START: *const u64 = std::ptr::null();
 static mut HEAP_END: *const u64 = std::ptr::null();
+/// The input and the arguments after the heap size, which the compiled code reads as `input` and
+/// `(args)`. They are parsed onto the heap before the program starts.
+#[export_name = "\x01snek_input"]
+pub static mut INPUT: SnekVal = FALSE;
+#[export_name = "\x01snek_args"]
+pub static mut ARGS: SnekVal = NIL;
+/// What `(args)` gives when there are none, so that programs written for a given heap size still
+/// fit. Pushing to it copies it to the heap like any full vector.
+static mut NO_ARGS: [u64; 2] = [0, VEC_KIND << 56];
+/// The number of times objects have moved so far, which dictionaries check to notice that keys may
+/// have moved.
+static mut GC_COUNT: u64 = 0;
 
 #[link(name = "our_code")]
 extern "C" {
@@ -24,25 +79,73 @@ extern "C" {
     // it does not add an underscore in front of the name.
     // Courtesy of Max New (https://maxsnew.com/teaching/eecs-483-fa22/hw_adder_assignment.html)
     #[link_name = "\x01our_code_starts_here"]
-    fn our_code_starts_here(input: u64, heap_start: *const u64, heap_end: *const u64) -> u64;
+    fn our_code_starts_here(heap_ptr: *const u64, heap_end: *const u64) -> u64;
+
+    /// For each struct type id, a pointer to the struct's name followed by its field names, as
+    /// consecutive NUL-terminated strings.
+    #[link_name = "\x01snek_structs"]
+    static SNEK_STRUCTS: [*const c_char; 0];
+
+    /// The slots of the program's globals, `SNEK_GLOBAL_COUNT` of them. They are roots for the
+    /// collector just like the stack.
+    #[link_name = "\x01snek_globals"]
+    static mut SNEK_GLOBALS: [u64; 0];
+    #[link_name = "\x01snek_global_count"]
+    static SNEK_GLOBAL_COUNT: u64;
 }
 
-#[export_name = "\x01snek_error"]
-pub extern "C" fn snek_error(errcode: i64) {
+fn error_message(errcode: i64) -> String {
     if errcode == ErrCode::InvalidArgument as i64 {
-        eprintln!("invalid argument");
+        "invalid argument".to_string()
     } else if errcode == ErrCode::Overflow as i64 {
-        eprintln!("overflow");
+        "overflow".to_string()
     } else if errcode == ErrCode::IndexOutOfBounds as i64 {
-        eprintln!("index out of bounds");
+        "index out of bounds".to_string()
     } else if errcode == ErrCode::InvalidVecSize as i64 {
-        eprintln!("vector size must be non-negative");
+        "vector size must be non-negative".to_string()
+    } else if errcode == ErrCode::WrongArity as i64 {
+        "wrong number of arguments".to_string()
+    } else if errcode == ErrCode::WrongRecordType as i64 {
+        "wrong record type".to_string()
+    } else if errcode == ErrCode::DivideByZero as i64 {
+        "division by zero".to_string()
+    } else if errcode == ErrCode::NoMatch as i64 {
+        "no match".to_string()
+    } else if errcode == ErrCode::InvalidInput as i64 {
+        "invalid input".to_string()
     } else {
-        eprintln!("an error ocurred {}", errcode);
+        format!("an error ocurred {}", errcode)
     }
+}
+
+#[export_name = "\x01snek_error"]
+pub extern "C" fn snek_error(errcode: i64) {
+    eprintln!("{}", error_message(errcode));
     std::process::exit(errcode as i32);
 }
 
+/// Called when `val` is raised outside of any `try`. A runtime error exits like it always has;
+/// any other value is reported as an uncaught exception.
+#[export_name = "\x01snek_uncaught"]
+pub unsafe extern "C" fn snek_uncaught(val: SnekVal) {
+    if obj_kind(val) == Some(ERROR_KIND) {
+        eprintln!("{}", error_str(untag(val), &mut HashSet::new()));
+        std::process::exit((*untag(val).add(2) as i64 >> 1) as i32);
+    }
+    eprintln!("uncaught exception: {}", snek_str(val, &mut HashSet::new()));
+    std::process::exit(ErrCode::UncaughtException as i32);
+}
+
+/// The message of the error object at `obj`, followed by the value it carries if it has one.
+unsafe fn error_str(obj: *const u64, seen: &mut HashSet<SnekVal>) -> String {
+    let message = error_message(*obj.add(2) as i64 >> 1);
+    if len(*obj.add(1)) > 1 {
+        format!("{message}: {}", snek_str(*obj.add(3), seen))
+    } else {
+        message
+    }
+}
+
 #[export_name = "\x01snek_print"]
 pub unsafe extern "C" fn snek_print(val: SnekVal) -> SnekVal {
     println!("{}", snek_str(val, &mut HashSet::new()));
@@ -90,69 +193,168 @@ pub unsafe fn snek_try_gc(
     }
 }
 
-/// Marks the current vectors as live and recurses for any of its potential sub-values that
-/// happen to also be vecs and live.
-pub unsafe fn mark_vec(vec: *mut u64) {
-    let mut gc_word = *vec;
-    let size_word = *(vec.add(1));
-    // If marked already, just skip.
-    if gc_word & 1 == 1 { return; }
-    gc_word = 1;
-    *vec = gc_word;
+/// Whether `val` is a pointer to a heap object.
+fn is_ref(val: SnekVal) -> bool {
+    val & 0b11 == 0b01 && val != NIL
+}
+
+/// Whether the object at `obj` lives in the collected heap. Objects outside it, like float
+/// literals and error objects, live for the whole run and are never marked or moved.
+unsafe fn in_heap(obj: *const u64) -> bool {
+    HEAP_START <= obj && obj < HEAP_END
+}
+
+/// The address of the object `val` points to.
+fn untag(val: SnekVal) -> *mut u64 {
+    (val & !0b111) as *mut u64
+}
 
-    // For every child, mark as live if a vec and not nil.
-    for i in 2..(2 + size_word) {
-        let val = *(vec.add(i.try_into().expect("Unable to cast size offset to usize")));
-        if val & 1 == 1 && val != 1 && val != TRUE && val != FALSE {
-            mark_vec(((val as u64) - 1) as *mut u64);
+fn kind(header: u64) -> u64 {
+    header >> 56
+}
+
+/// The number of elements or payload words in an object.
+fn len(header: u64) -> usize {
+    (header & 0xffff_ffff) as usize
+}
+
+/// The number of unused words a vector has after its elements to grow into, which hold nil.
+fn slack(header: u64) -> usize {
+    if kind(header) == VEC_KIND {
+        (header >> 32 & 0xff_ffff) as usize
+    } else {
+        0
+    }
+}
+
+/// The number of words an object takes up in the heap, including its GC word and header.
+fn object_words(header: u64) -> usize {
+    len(header) + slack(header) + 2
+}
+
+/// The number of payload words at the start of an object that hold raw machine words rather than
+/// snek values, and so must not be traced. A closure starts with its code pointer.
+fn raw_words(header: u64) -> usize {
+    match kind(header) {
+        CLOSURE_KIND => 1,
+        BIGNUM_KIND | FLOAT_KIND => object_words(header) - 2,
+        _ => 0,
+    }
+}
+
+/// Calls `f` on every word of the object at `obj` that holds a snek value.
+unsafe fn for_each_field(obj: *mut u64, mut f: impl FnMut(*mut u64)) {
+    let header = *obj.add(1);
+    for i in 2 + raw_words(header)..object_words(header) {
+        f(obj.add(i));
+    }
+}
+
+/// Marks every object reachable from `roots` as live by setting its GC word to 1.
+pub unsafe fn mark(roots: Vec<*mut u64>) {
+    let mut worklist = roots;
+    while let Some(obj) = worklist.pop() {
+        // If marked already, or never collected, just skip.
+        if !in_heap(obj) || *obj & 1 == 1 {
+            continue;
         }
+        *obj = 1;
+        for_each_field(obj, |field| {
+            if is_ref(*field) {
+                worklist.push(untag(*field));
+            }
+        });
     }
 }
 
-/// Goes through the current stack frame and finds all used vecs, adding them to the "roots" vector.
-/// Also recursively iterates through each stack frame to do the same.
+/// The new value of a reference to an object that is about to move, keeping its tag.
+unsafe fn forward(val: SnekVal) -> SnekVal {
+    let gc_word = *untag(val);
+    if gc_word != 0 {
+        gc_word | (val & 0b111)
+    } else {
+        val
+    }
+}
+
+/// The slots outside the stack that hold roots: the input, the arguments and the program's
+/// globals.
+unsafe fn static_roots() -> Vec<*mut u64> {
+    let mut slots = vec![std::ptr::addr_of_mut!(INPUT), std::ptr::addr_of_mut!(ARGS)];
+    let globals = std::ptr::addr_of_mut!(SNEK_GLOBALS) as *mut u64;
+    slots.extend((0..SNEK_GLOBAL_COUNT as usize).map(|i| globals.add(i)));
+    slots
+}
+
+/// Goes through the current stack frame and finds all used heap objects, adding them to the "roots"
+/// vector. Also recursively iterates through each stack frame to do the same, and once at the
+/// bottom of the stack adds the objects the static roots reference.
 pub unsafe fn find_stack_marks(stack_base: *const u64, curr_rsp: *const u64, curr_rbp: *const u64, roots: &mut Vec<*mut u64>) {
     let mut ptr = curr_rsp;
     while ptr < curr_rbp {
         let val = *ptr;
-        // If vec and not nil, true or false, remove tag and pass as root.
-        if val & 1 == 1 && val != 1 && val != TRUE && val != FALSE {
-            roots.push(((val as u64) - 1) as *mut u64);
+        if is_ref(val) {
+            roots.push(untag(val));
         }
         ptr = ptr.add(1);
     }
     if ptr == stack_base {
+        for slot in static_roots() {
+            if is_ref(*slot) {
+                roots.push(untag(*slot));
+            }
+        }
         return;
     } else {
         find_stack_marks(stack_base, ptr.add(2), *curr_rbp as *const u64, roots);
     }
 }
 
-/// Updates all references to vecs in the current stack frame to point to the new heap
+/// Updates all references to heap objects in the current stack frame to point to the new heap
 /// location after forwarding calculation. Also recursively iterates through all stack frames
-/// beneath this one to do the same.
+/// beneath this one to do the same, and then updates the static roots.
 pub unsafe fn update_stack_references(stack_base: *const u64, curr_rsp: *const u64, curr_rbp: *const u64) {
     let mut ptr: *mut u64 = curr_rsp as *mut u64;
     while (ptr as *const u64) < curr_rbp {
-        let val = *ptr;
-        // Check if value is not true, false or nil and then check tag bit.
-        // If it's a reference, update to new reference from GC word (if any),
-        // with tag bit set.
-        if val & 1 == 1 && val != 1 && val != TRUE && val != FALSE {
-            let gc_word = *((val - 1) as *const u64);
-            if gc_word != 0 {
-                *ptr = gc_word + 1;
-            }
+        if is_ref(*ptr) {
+            *ptr = forward(*ptr);
         }
         ptr = ptr.add(1);
     }
     if (ptr as *const u64) == stack_base {
+        for slot in static_roots() {
+            if is_ref(*slot) {
+                *slot = forward(*slot);
+            }
+        }
         return;
     } else {
         update_stack_references(stack_base, ptr.add(2), *curr_rbp as *const u64);
     }
 }
 
+/// Points every reference in the heap and the stack to an object with a nonzero GC word at the
+/// address in it. In the heap only the fields of an object hold references; its GC word, header
+/// and raw words don't. Counts in `GC_COUNT`, since objects are about to move.
+unsafe fn update_references(
+    heap_ptr: *const u64,
+    stack_base: *const u64,
+    curr_rbp: *const u64,
+    curr_rsp: *const u64,
+) {
+    GC_COUNT += 1;
+    let mut obj: *mut u64 = HEAP_START as *mut u64;
+    while (obj as *const u64) < heap_ptr {
+        for_each_field(obj, |field| {
+            if is_ref(*field) {
+                *field = forward(*field);
+            }
+        });
+        obj = obj.add(object_words(*obj.add(1)));
+    }
+    update_stack_references(stack_base, curr_rsp, curr_rbp);
+}
+
 /// This function should trigger garbage collection and return the updated heap pointer (i.e., the new
 /// value of `%r15`). See [`snek_try_gc`] for a description of the meaning of the arguments.
 #[export_name = "\x01snek_gc"]
@@ -162,84 +364,44 @@ pub unsafe fn snek_gc(
     curr_rbp: *const u64,
     curr_rsp: *const u64,
 ) -> *const u64 {
-    // First off, we need to mark.
-    // Traverse the entire stack, and for every value that is a tuple and is still live,
-    // mark it in the heap with 1. We also have to recurse to make sure any other tuple value
-    // is also marked.
+    // First off, we need to mark. Every heap object referenced from the stack is a root, and
+    // everything reachable from a root is live.
     let mut roots: Vec<*mut u64> = Vec::new();
     find_stack_marks(stack_base, curr_rsp, curr_rbp, &mut roots);
-
-    // Mark all roots recursively.
-    for root in roots {
-        mark_vec(root);
-    }
+    mark(roots);
 
     // Now all the values are marked, we need to begin compacting.
-    // We start with computing all the forwarding addresses.
-    // To do this, we iterate through our heap, by iterating through our vecs.
+    // We start with computing all the forwarding addresses: walk the heap object by object and,
+    // for every marked one, replace its GC word with the address it will move to.
     let mut move_to = HEAP_START;
     let mut move_from: *mut u64 = HEAP_START as *mut u64;
     while (move_from as *const u64) < heap_ptr {
-        // Check GC word for mark. If we find one, use the current
-        // move_to and replace the GC word with the new address.
-        // Adjust move_to and move_from according to the size
-        // of the vec.
-        let gc_word = *move_from;
-        let size_word = *(move_from.add(1));
-        if gc_word & 1 == 1 {
+        let words = object_words(*move_from.add(1));
+        if *move_from & 1 == 1 {
             *move_from = move_to as u64;
-            move_to = move_to.add((size_word + 2).try_into().expect("Unable to increment move_to when computing forwarding address"));
+            move_to = move_to.add(words);
         }
-        move_from = move_from.add((size_word + 2).try_into().expect("Unable to increment move_from when computing forwarding address"));
+        move_from = move_from.add(words);
     }
 
-    // Now that we've calculated the forwarding addresses, we need to update
-    // the address everywhere we find it in the heap and the stack. We'll do
-    // the heap first, since it's easier.
-    //
-    // Iterate linearly throughout entire heap and whenever we find a reference
-    // for an address, check whether that specific address has anything other than
-    // zero in its GC word. If so, replace the current value we are looking at with
-    // the one in the GC word.
-    let mut ptr: *mut u64 = HEAP_START as *mut u64;
-    while (ptr as *const u64) < heap_ptr {
-        let val = *ptr;
-        // Check if value is not true, false or nil and then check tag bit.
-        // If it's a reference, update to new reference from GC word (if any),
-        // with tag bit set.
-        if val & 1 == 1 && val != 1 && val != TRUE && val != FALSE {
-            let gc_word = *((val - 1) as *const u64);
-            if gc_word != 0 {
-                *ptr = gc_word + 1;
-            }
-        }
-        ptr = ptr.add(1);
-    }
-
-    // Now we need to do the same thing over the stack. Use the stack traversal
-    // we used for marking, except process values just like above.
-    update_stack_references(stack_base, curr_rsp, curr_rbp);
+    // Now that we've calculated the forwarding addresses, we need to update every reference in
+    // the heap and the stack.
+    update_references(heap_ptr, stack_base, curr_rbp, curr_rsp);
 
+    // Now all that's left is to move the objects in the heap. For every object with a forwarding
+    // address, copy it there and clear its GC word.
     let mut new_heap_ptr: *mut u64 = HEAP_START as *mut u64;
-    // Now all that's left is to move the objects in the heap. Linearly iterate
-    // the tuples in the heap and, for every GC word that is not 0, forward the vec
-    // to its proper address, mark that spot with 0 and then adjust the new heap pointer
-    // to include that vec.
     let mut ptr: *mut u64 = HEAP_START as *mut u64;
     while (ptr as *const u64) < heap_ptr {
-        let gc_word = *ptr;
-        let size_word = *(ptr.add(1));
-        // If there is an address to forward to, move our entire tuple
-        // to that address.
-        if gc_word != 0 {
+        let words = object_words(*ptr.add(1));
+        if *ptr != 0 {
             *new_heap_ptr = 0;
-            *(new_heap_ptr.add(1)) = size_word;
-            for i in 2..(size_word + 2) {
-                *new_heap_ptr.add(i as usize) = *ptr.add(i as usize);
+            for i in 1..words {
+                *new_heap_ptr.add(i) = *ptr.add(i);
             }
-            new_heap_ptr = new_heap_ptr.add((size_word + 2).try_into().expect("Unable to increment new_heap_ptr when moving objects"));
+            new_heap_ptr = new_heap_ptr.add(words);
         }
-        ptr = ptr.add((size_word + 2).try_into().expect("Unable to increment linear scan pointer when moving objects"));
+        ptr = ptr.add(words);
     }
     new_heap_ptr
 }
@@ -273,6 +435,393 @@ pub unsafe fn snek_print_heap(heap_ptr: *const u64) {
     println!("--------------------HEAP END--------------------");
 }
 
+/// Whether `a` and `b` are structurally equal: the same value, or vectors or records of the same
+/// type whose elements are pairwise equal. Comparing a pair again while it is already being
+/// compared assumes it is equal, so cyclic structures compare equal if they unfold the same way.
+#[export_name = "\x01snek_equal"]
+pub unsafe extern "C" fn snek_equal(a: SnekVal, b: SnekVal) -> SnekVal {
+    if equal(a, b, &mut HashSet::new()) {
+        TRUE
+    } else {
+        FALSE
+    }
+}
+
+unsafe fn equal(a: SnekVal, b: SnekVal, seen: &mut HashSet<(SnekVal, SnekVal)>) -> bool {
+    if a == b {
+        return true;
+    }
+    if !is_ref(a) || !is_ref(b) || a & 0b111 != b & 0b111 {
+        return false;
+    }
+    let (a_obj, b_obj) = (untag(a), untag(b));
+    // Vectors of the same length are comparable however much slack they have.
+    let without_slack = |header: u64| header - ((slack(header) as u64) << 32);
+    let header = without_slack(*a_obj.add(1));
+    if header != without_slack(*b_obj.add(1)) {
+        return false;
+    }
+    match kind(header) {
+        VEC_KIND | RECORD_KIND => {}
+        BIGNUM_KIND | FLOAT_KIND => {
+            return (2..object_words(header)).all(|i| *a_obj.add(i) == *b_obj.add(i))
+        }
+        _ => return false,
+    }
+    if !seen.insert((a, b)) {
+        return true;
+    }
+    (2..2 + len(header)).all(|i| equal(*a_obj.add(i), *b_obj.add(i), seen))
+}
+
+/// A value computed by the runtime along with the heap pointer after allocating it, returned in
+/// `%rax` and `%rdx`.
+#[repr(C)]
+pub struct Allocated {
+    val: SnekVal,
+    heap_ptr: *const u64,
+}
+
+/// The kind of the object `val` points to, if it is a `0b101`-tagged pointer.
+unsafe fn obj_kind(val: SnekVal) -> Option<u64> {
+    if val & 0b111 == 0b101 {
+        Some(kind(*untag(val).add(1)))
+    } else {
+        None
+    }
+}
+
+/// A number as the runtime's arithmetic sees it.
+enum Num {
+    Int(BigInt),
+    Float(f64),
+}
+
+impl Num {
+    fn to_f64(&self) -> f64 {
+        match self {
+            Num::Int(n) => n.to_f64(),
+            Num::Float(x) => *x,
+        }
+    }
+}
+
+/// The number `val` holds, or `None` if it isn't a number.
+unsafe fn try_to_num(val: SnekVal) -> Option<Num> {
+    if val & 1 == 0 {
+        return Some(Num::Int(BigInt::from_i64(val as i64 >> 1)));
+    }
+    let obj = untag(val);
+    match obj_kind(val)? {
+        BIGNUM_KIND => {
+            let limbs = object_words(*obj.add(1)) - 3;
+            let mag = (0..limbs).map(|i| *obj.add(3 + i)).collect();
+            Some(Num::Int(BigInt::new(*obj.add(2) == 1, mag)))
+        }
+        FLOAT_KIND => Some(Num::Float(f64::from_bits(*obj.add(2)))),
+        _ => None,
+    }
+}
+
+/// The number `val` holds, exiting with an invalid argument error if it isn't a number.
+unsafe fn to_num(val: SnekVal) -> Num {
+    match try_to_num(val) {
+        Some(n) => n,
+        None => {
+            snek_error(ErrCode::InvalidArgument as i64);
+            unreachable!()
+        }
+    }
+}
+
+/// Writes an object of kind `kind` with the raw `payload` into the words starting at `obj`.
+unsafe fn write_raw_object(obj: *mut u64, kind: u64, payload: &[u64]) {
+    *obj = 0;
+    *obj.add(1) = kind << 56 | payload.len() as u64;
+    for (i, word) in payload.iter().enumerate() {
+        *obj.add(2 + i) = *word;
+    }
+}
+
+/// The payload of a bignum: its sign followed by its magnitude.
+fn bignum_payload(n: &BigInt) -> Vec<u64> {
+    std::iter::once(n.neg as u64).chain(n.mag.iter().copied()).collect()
+}
+
+/// The heap pointer to allocate `words` words at, collecting garbage first if there's no room
+/// after `heap_ptr`. See [`snek_try_gc`] for the rest of the arguments.
+unsafe fn reserve(
+    words: usize,
+    heap_ptr: *const u64,
+    stack_base: *const u64,
+    curr_rbp: *const u64,
+    curr_rsp: *const u64,
+) -> *mut u64 {
+    if (HEAP_END as u64 - heap_ptr as u64) < 8 * words as u64 {
+        snek_try_gc(words as isize, heap_ptr, stack_base, curr_rbp, curr_rsp) as *mut u64
+    } else {
+        heap_ptr as *mut u64
+    }
+}
+
+/// Allocates an object of kind `kind` with the raw `payload` at `heap_ptr`, collecting garbage
+/// first if there's no room. See [`snek_try_gc`] for the rest of the arguments.
+unsafe fn alloc_raw_object(
+    kind: u64,
+    payload: &[u64],
+    heap_ptr: *const u64,
+    stack_base: *const u64,
+    curr_rbp: *const u64,
+    curr_rsp: *const u64,
+) -> Allocated {
+    let words = 2 + payload.len();
+    let heap_ptr = reserve(words, heap_ptr, stack_base, curr_rbp, curr_rsp);
+    write_raw_object(heap_ptr, kind, payload);
+    Allocated {
+        val: heap_ptr as u64 | 0b101,
+        heap_ptr: heap_ptr.add(words),
+    }
+}
+
+/// `n` as a snek value: a fixnum if it fits, or else a newly allocated bignum or float.
+unsafe fn alloc_number(
+    n: Num,
+    heap_ptr: *const u64,
+    stack_base: *const u64,
+    curr_rbp: *const u64,
+    curr_rsp: *const u64,
+) -> Allocated {
+    let (kind, payload) = match n {
+        Num::Int(n) => match n.to_i64_in(FIXNUM_MIN, FIXNUM_MAX) {
+            Some(i) => return Allocated { val: (i << 1) as u64, heap_ptr },
+            None => (BIGNUM_KIND, bignum_payload(&n)),
+        },
+        Num::Float(x) => (FLOAT_KIND, vec![x.to_bits()]),
+    };
+    alloc_raw_object(kind, &payload, heap_ptr, stack_base, curr_rbp, curr_rsp)
+}
+
+/// Boxes the float with the given bits when the compiled code runs out of heap. See
+/// [`snek_try_gc`] for the rest of the arguments.
+#[export_name = "\x01snek_alloc_float"]
+pub unsafe extern "C" fn snek_alloc_float(
+    bits: u64,
+    heap_ptr: *const u64,
+    stack_base: *const u64,
+    curr_rbp: *const u64,
+    curr_rsp: *const u64,
+) -> Allocated {
+    alloc_raw_object(FLOAT_KIND, &[bits], heap_ptr, stack_base, curr_rbp, curr_rsp)
+}
+
+/// Makes room to push onto the full vector in the stack slot `vec`, doubling its capacity. A vector
+/// at the end of the heap grows in place; any other is copied to a new vector that every reference
+/// to the old one is pointed at. See [`snek_try_gc`] for the rest of the arguments.
+#[export_name = "\x01snek_vec_grow"]
+pub unsafe extern "C" fn snek_vec_grow(
+    vec: *const SnekVal,
+    heap_ptr: *const u64,
+    stack_base: *const u64,
+    curr_rbp: *const u64,
+    curr_rsp: *const u64,
+) -> Allocated {
+    let len = len(*untag(*vec).add(1));
+    let cap = (2 * len).max(4).min(len + 0xff_ffff);
+    let header = VEC_KIND << 56 | ((cap - len) as u64) << 32 | len as u64;
+
+    let obj = untag(*vec);
+    if obj.add(2 + len) as *const u64 == heap_ptr
+        && (HEAP_END as u64 - heap_ptr as u64) >= 8 * (cap - len) as u64
+    {
+        *obj.add(1) = header;
+        for i in len..cap {
+            *obj.add(2 + i) = NIL;
+        }
+        return Allocated {
+            val: *vec,
+            heap_ptr: obj.add(2 + cap),
+        };
+    }
+
+    let new = reserve(2 + cap, heap_ptr, stack_base, curr_rbp, curr_rsp);
+    // Collecting garbage may have moved the vector.
+    let obj = untag(*vec);
+    *new = 0;
+    *new.add(1) = header;
+    for i in 0..cap {
+        *new.add(2 + i) = if i < len { *obj.add(2 + i) } else { NIL };
+    }
+    let heap_ptr = new.add(2 + cap);
+    *obj = new as u64;
+    update_references(heap_ptr, stack_base, curr_rbp, curr_rsp);
+    *obj = 0;
+    Allocated {
+        val: new as u64 | 0b001,
+        heap_ptr,
+    }
+}
+
+/// The slow paths of `+`, `-`, `*` and `/`, taken when an operand is a bignum or the fixnum
+/// operation overflows. If either operand is a float, both are converted to floats. The remaining
+/// arguments are as for [`snek_try_gc`].
+macro_rules! number_op {
+    ($name:ident, $link:literal, |$a:ident, $b:ident| $int:expr, |$x:ident, $y:ident| $float:expr) => {
+        #[export_name = $link]
+        pub unsafe extern "C" fn $name(
+            a: SnekVal,
+            b: SnekVal,
+            heap_ptr: *const u64,
+            stack_base: *const u64,
+            curr_rbp: *const u64,
+            curr_rsp: *const u64,
+        ) -> Allocated {
+            let result = match (to_num(a), to_num(b)) {
+                (Num::Int($a), Num::Int($b)) => Num::Int($int),
+                (a, b) => {
+                    let ($x, $y) = (a.to_f64(), b.to_f64());
+                    Num::Float($float)
+                }
+            };
+            alloc_number(result, heap_ptr, stack_base, curr_rbp, curr_rsp)
+        }
+    };
+}
+
+number_op!(snek_add, "\x01snek_add", |a, b| a.add(&b), |x, y| x + y);
+number_op!(snek_sub, "\x01snek_sub", |a, b| a.sub(&b), |x, y| x - y);
+number_op!(snek_mul, "\x01snek_mul", |a, b| a.mul(&b), |x, y| x * y);
+number_op!(
+    snek_div,
+    "\x01snek_div",
+    |a, b| a.div(&b).unwrap_or_else(|| divide_by_zero()),
+    |x, y| x / y
+);
+
+/// The slow paths of the operations on integers, taken when an operand is a bignum or the result
+/// doesn't fit in a fixnum. Both operands are integers, and `bitwise-not` and `abs` ignore the
+/// second. A result too big to allocate gives a null heap pointer, which the compiled code raises
+/// as an overflow. The remaining arguments are as for [`snek_try_gc`].
+macro_rules! int_op {
+    ($name:ident, $link:literal, |$a:ident, $b:ident| $int:expr) => {
+        #[export_name = $link]
+        pub unsafe extern "C" fn $name(
+            a: SnekVal,
+            b: SnekVal,
+            heap_ptr: *const u64,
+            stack_base: *const u64,
+            curr_rbp: *const u64,
+            curr_rsp: *const u64,
+        ) -> Allocated {
+            let (Num::Int($a), Num::Int($b)) = (to_num(a), to_num(b)) else {
+                snek_error(ErrCode::InvalidArgument as i64);
+                unreachable!()
+            };
+            match $int {
+                Some(n) => alloc_number(Num::Int(n), heap_ptr, stack_base, curr_rbp, curr_rsp),
+                None => Allocated {
+                    val: 0,
+                    heap_ptr: std::ptr::null(),
+                },
+            }
+        }
+    };
+}
+
+int_op!(snek_remainder, "\x01snek_remainder", |a, b| Some(
+    a.div_rem(&b).unwrap_or_else(|| divide_by_zero()).1
+));
+int_op!(snek_mod, "\x01snek_mod", |a, b| Some(
+    a.modulo(&b).unwrap_or_else(|| divide_by_zero())
+));
+int_op!(snek_bit_and, "\x01snek_bit_and", |a, b| Some(a.bitwise(&b, |x, y| x & y)));
+int_op!(snek_bit_or, "\x01snek_bit_or", |a, b| Some(a.bitwise(&b, |x, y| x | y)));
+int_op!(snek_bit_xor, "\x01snek_bit_xor", |a, b| Some(a.bitwise(&b, |x, y| x ^ y)));
+int_op!(snek_bit_not, "\x01snek_bit_not", |a, _b| Some(
+    a.negate().sub(&BigInt::from_i64(1))
+));
+int_op!(snek_shift, "\x01snek_shift", |a, b| shift(&a, &b));
+int_op!(snek_abs, "\x01snek_abs", |a, _b| Some(BigInt::new(false, a.mag)));
+int_op!(snek_min, "\x01snek_min", |a, b| Some(if a.cmp(&b).is_le() { a } else { b }));
+int_op!(snek_max, "\x01snek_max", |a, b| Some(if a.cmp(&b).is_ge() { a } else { b }));
+
+fn divide_by_zero() -> ! {
+    snek_error(ErrCode::DivideByZero as i64);
+    unreachable!()
+}
+
+/// `n` shifted left by `k` bits, or right by `-k` bits rounding down, or `None` if shifting left
+/// gives more bits than the whole heap holds.
+unsafe fn shift(n: &BigInt, k: &BigInt) -> Option<BigInt> {
+    let heap_bits = (HEAP_END as i64 - HEAP_START as i64) * 8;
+    match k.to_i64_in(-heap_bits, heap_bits) {
+        _ if n.mag.is_empty() => Some(BigInt::new(false, vec![])),
+        Some(k) if k >= 0 => Some(n.shl(k as usize)),
+        Some(k) => Some(n.shr(k.unsigned_abs() as usize)),
+        // Shifting right by more bits than any bignum has leaves just the sign.
+        None if k.neg => Some(BigInt::from_i64(if n.neg { -1 } else { 0 })),
+        None => None,
+    }
+}
+
+/// The slow path of `<` (op 0), `<=` (1), `>` (2) and `>=` (3), taken when an operand is a
+/// bignum.
+#[export_name = "\x01snek_compare"]
+pub unsafe extern "C" fn snek_compare(a: SnekVal, b: SnekVal, op: i64) -> SnekVal {
+    let ord = match (to_num(a), to_num(b)) {
+        (Num::Int(a), Num::Int(b)) => Some(a.cmp(&b)),
+        (a, b) => a.to_f64().partial_cmp(&b.to_f64()),
+    };
+    let result = match (op, ord) {
+        (_, None) => false,
+        (0, Some(ord)) => ord.is_lt(),
+        (1, Some(ord)) => ord.is_le(),
+        (2, Some(ord)) => ord.is_gt(),
+        (_, Some(ord)) => ord.is_ge(),
+    };
+    if result {
+        TRUE
+    } else {
+        FALSE
+    }
+}
+
+/// `=` when either operand is a `0b101`-tagged object. Bignums and floats are equal to numbers
+/// with the same value; anything else follows the usual rule of comparing values with the same
+/// tag. Returns 0 if the operands can't be compared, for the caller to raise an invalid argument
+/// error.
+#[export_name = "\x01snek_eq"]
+pub unsafe extern "C" fn snek_eq(a: SnekVal, b: SnekVal) -> SnekVal {
+    let is_boxed_num = |val| matches!(obj_kind(val), Some(BIGNUM_KIND | FLOAT_KIND));
+    let equal = if is_boxed_num(a) || is_boxed_num(b) {
+        match (try_to_num(a), try_to_num(b)) {
+            (Some(Num::Int(a)), Some(Num::Int(b))) => a == b,
+            (Some(a), Some(b)) => a.to_f64() == b.to_f64(),
+            _ => return 0,
+        }
+    } else if (a ^ b) & 0b11 == 0 {
+        a == b
+    } else {
+        return 0;
+    };
+    if equal {
+        TRUE
+    } else {
+        FALSE
+    }
+}
+
+/// The name of the struct with type id `id` followed by the names of its `fields` fields.
+unsafe fn struct_names(id: usize, fields: usize) -> Vec<String> {
+    let mut ptr = *SNEK_STRUCTS.as_ptr().add(id);
+    let mut names = Vec::new();
+    for _ in 0..=fields {
+        let name = CStr::from_ptr(ptr);
+        names.push(name.to_string_lossy().into_owned());
+        ptr = ptr.add(name.to_bytes().len() + 1);
+    }
+    names
+}
+
 unsafe fn snek_str(val: SnekVal, seen: &mut HashSet<SnekVal>) -> String {
     if val == TRUE {
         format!("true")
@@ -280,14 +829,49 @@ unsafe fn snek_str(val: SnekVal, seen: &mut HashSet<SnekVal>) -> String {
         format!("false")
     } else if val & 1 == 0 {
         format!("{}", (val as i64) >> 1)
-    } else if val == 1 {
+    } else if val == NIL {
         format!("nil")
+    } else if val & 0b111 == 0b101 {
+        let addr = untag(val);
+        let header = *addr.add(1);
+        match kind(header) {
+            CLOSURE_KIND => "<function>".to_string(),
+            DICT_KIND => {
+                if !seen.insert(val) {
+                    return "#dict{...}".to_string();
+                }
+                let entries: Vec<String> = dict::entries(addr)
+                    .into_iter()
+                    .map(|(k, v)| format!("{}: {}", snek_str(k, seen), snek_str(v, seen)))
+                    .collect();
+                seen.remove(&val);
+                format!("#dict{{{}}}", entries.join(", "))
+            }
+            ERROR_KIND => format!("#error{{{}}}", error_str(addr, seen)),
+            BIGNUM_KIND | FLOAT_KIND => match to_num(val) {
+                Num::Int(n) => n.to_string(),
+                Num::Float(x) => format!("{x:?}"),
+            },
+            RECORD_KIND => {
+                let len = object_words(header) - 2;
+                let names = struct_names((header >> 32 & 0xff_ffff) as usize, len);
+                if !seen.insert(val) {
+                    return format!("#{}{{...}}", names[0]);
+                }
+                let fields: Vec<String> = (0..len)
+                    .map(|i| format!("{}: {}", names[i + 1], snek_str(*addr.add(2 + i), seen)))
+                    .collect();
+                seen.remove(&val);
+                format!("#{}{{{}}}", names[0], fields.join(", "))
+            }
+            kind => format!("unknown object of kind {kind}"),
+        }
     } else if val & 1 == 1 {
         if !seen.insert(val) {
             return "[...]".to_string();
         }
         let addr = (val - 1) as *const u64;
-        let size = addr.add(1).read() as usize;
+        let size = len(addr.add(1).read());
         let mut res = "[".to_string();
         for i in 0..size {
             let elem = addr.add(2 + i).read();
@@ -303,23 +887,248 @@ unsafe fn snek_str(val: SnekVal, seen: &mut HashSet<SnekVal>) -> String {
     }
 }
 
-fn parse_input(input: &str) -> u64 {
-    match input {
-        "true" => TRUE,
-        "false" => FALSE,
-        _ => (input.parse::<i64>().unwrap() << 1) as u64,
+/// A command-line argument or a line of stdin, parsed but not yet written to the heap.
+enum InputValue {
+    /// A value that isn't a heap object: a boolean, nil or a fixnum.
+    Imm(SnekVal),
+    /// A bignum or a float, as its kind and payload.
+    Object(u64, Vec<u64>),
+    Vec(Vec<InputValue>),
+}
+
+/// Parses a command-line argument: `true`, `false`, `nil`, a number, or a vector of values like
+/// `[1, [2, 3], nil, true]`.
+fn parse_input(input: &str) -> Result<InputValue, String> {
+    let mut parser = InputParser { rest: input };
+    let val = parser.value()?;
+    parser.skip_space();
+    if parser.rest.is_empty() {
+        Ok(val)
+    } else {
+        Err(format!("unexpected `{}` after the value", parser.rest))
+    }
+}
+
+struct InputParser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> InputParser<'a> {
+    fn value(&mut self) -> Result<InputValue, String> {
+        self.skip_space();
+        if self.eat('[') {
+            let mut elems = vec![];
+            self.skip_space();
+            if self.eat(']') {
+                return Ok(InputValue::Vec(elems));
+            }
+            loop {
+                elems.push(self.value()?);
+                self.skip_space();
+                if self.eat(']') {
+                    return Ok(InputValue::Vec(elems));
+                }
+                if !self.eat(',') {
+                    return match self.rest.chars().next() {
+                        Some(c) => Err(format!("expected `,` or `]`, found `{c}`")),
+                        None => Err("unclosed `[`".to_string()),
+                    };
+                }
+            }
+        }
+        let end = self
+            .rest
+            .find(|c: char| c == ',' || c == '[' || c == ']' || c.is_whitespace())
+            .unwrap_or(self.rest.len());
+        let (atom, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        parse_atom(atom)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn skip_space(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+}
+
+fn parse_atom(atom: &str) -> Result<InputValue, String> {
+    match atom {
+        "true" => Ok(InputValue::Imm(TRUE)),
+        "false" => Ok(InputValue::Imm(FALSE)),
+        "nil" => Ok(InputValue::Imm(NIL)),
+        "" => Err("expected a value".to_string()),
+        _ => {
+            if let Some(n) = BigInt::parse(atom) {
+                Ok(match n.to_i64_in(FIXNUM_MIN, FIXNUM_MAX) {
+                    Some(i) => InputValue::Imm((i << 1) as u64),
+                    None => InputValue::Object(BIGNUM_KIND, bignum_payload(&n)),
+                })
+            } else if let Ok(x) = atom.parse::<f64>() {
+                Ok(InputValue::Object(FLOAT_KIND, vec![x.to_bits()]))
+            } else {
+                Err(format!("`{atom}` is not a value"))
+            }
+        }
     }
 }
 
+/// The number of words `val` takes on the heap.
+fn input_words(val: &InputValue) -> usize {
+    match val {
+        InputValue::Imm(_) => 0,
+        InputValue::Object(_, payload) => 2 + payload.len(),
+        InputValue::Vec(elems) => 2 + elems.len() + elems.iter().map(input_words).sum::<usize>(),
+    }
+}
+
+/// Writes `val` to the heap at `heap_ptr`, moving it past what was written, and returns the value
+/// written. It doesn't collect garbage if the heap is full: that's either because the program
+/// hasn't started yet, or because the caller reserved [`input_words`] first.
+unsafe fn write_input(val: &InputValue, heap_ptr: &mut *mut u64) -> SnekVal {
+    let (kind, payload) = match val {
+        InputValue::Imm(val) => return *val,
+        InputValue::Object(kind, payload) => (*kind, payload.clone()),
+        InputValue::Vec(elems) => {
+            let elems = elems.iter().map(|elem| write_input(elem, heap_ptr)).collect();
+            (VEC_KIND, elems)
+        }
+    };
+    let obj = *heap_ptr;
+    if ((HEAP_END as u64 - obj as u64) / 8) < 2 + payload.len() as u64 {
+        eprintln!("out of memory");
+        std::process::exit(ErrCode::OutOfMemory as i32);
+    }
+    write_raw_object(obj, kind, &payload);
+    *heap_ptr = obj.add(2 + payload.len());
+    let tag = if kind == VEC_KIND { 0b001 } else { 0b101 };
+    obj as u64 | tag
+}
+
+/// Reports a command-line argument or a line of stdin that can't be parsed and exits.
+fn invalid_input(arg: &str, err: String) -> ! {
+    eprintln!("invalid input: {err} in `{arg}`");
+    std::process::exit(ErrCode::InvalidInput as i32);
+}
+
 fn parse_heap_size(input: &str) -> usize {
-    input.parse::<usize>().unwrap()
+    input
+        .parse::<usize>()
+        .unwrap_or_else(|_| invalid_input(input, "expected a heap size in words".to_string()))
+}
+
+/// The next line of stdin without its line ending, or `None` at the end of input or if it can't be
+/// read.
+fn read_line() -> Option<String> {
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+    }
+}
+
+/// The result of a read with nothing to read or that can't be parsed: a null heap pointer, for the
+/// compiled code to raise an invalid input error.
+fn invalid_read() -> Allocated {
+    Allocated {
+        val: NIL,
+        heap_ptr: std::ptr::null(),
+    }
+}
+
+/// Reads an integer from the next line of stdin for `read-int`. The console operations take the
+/// same arguments as the dictionary operations; see [`snek_try_gc`] for the rest of them.
+#[export_name = "\x01snek_read_int"]
+pub unsafe extern "C" fn snek_read_int(
+    _args: *const u64,
+    heap_ptr: *const u64,
+    stack_base: *const u64,
+    curr_rbp: *const u64,
+    curr_rsp: *const u64,
+) -> Allocated {
+    match read_line().and_then(|line| BigInt::parse(line.trim())) {
+        Some(n) => alloc_number(Num::Int(n), heap_ptr, stack_base, curr_rbp, curr_rsp),
+        None => invalid_read(),
+    }
+}
+
+/// Reads a value written like a command-line argument from the next line of stdin for
+/// `read-value`.
+#[export_name = "\x01snek_read_value"]
+pub unsafe extern "C" fn snek_read_value(
+    _args: *const u64,
+    heap_ptr: *const u64,
+    stack_base: *const u64,
+    curr_rbp: *const u64,
+    curr_rsp: *const u64,
+) -> Allocated {
+    let Some(val) = read_line().and_then(|line| parse_input(&line).ok()) else {
+        return invalid_read();
+    };
+    let mut heap_ptr = reserve(input_words(&val), heap_ptr, stack_base, curr_rbp, curr_rsp);
+    let val = write_input(&val, &mut heap_ptr);
+    Allocated { val, heap_ptr }
+}
+
+/// Whether stdin has nothing left to read, for `eof?`.
+#[export_name = "\x01snek_eof"]
+pub unsafe extern "C" fn snek_eof(
+    _args: *const u64,
+    heap_ptr: *const u64,
+    _stack_base: *const u64,
+    _curr_rbp: *const u64,
+    _curr_rsp: *const u64,
+) -> Allocated {
+    let eof = io::stdin().lock().fill_buf().map_or(true, |buf| buf.is_empty());
+    Allocated {
+        val: if eof { TRUE } else { FALSE },
+        heap_ptr,
+    }
+}
+
+/// Prints the operand of `display` without a newline and returns it.
+#[export_name = "\x01snek_display"]
+pub unsafe extern "C" fn snek_display(
+    args: *const u64,
+    heap_ptr: *const u64,
+    _stack_base: *const u64,
+    _curr_rbp: *const u64,
+    _curr_rsp: *const u64,
+) -> Allocated {
+    let val = *args;
+    print!("{}", snek_str(val, &mut HashSet::new()));
+    let _ = io::stdout().flush();
+    Allocated { val, heap_ptr }
+}
+
+/// Prints a newline for `newline` and returns nil.
+#[export_name = "\x01snek_newline"]
+pub unsafe extern "C" fn snek_newline(
+    _args: *const u64,
+    heap_ptr: *const u64,
+    _stack_base: *const u64,
+    _curr_rbp: *const u64,
+    _curr_rsp: *const u64,
+) -> Allocated {
+    println!();
+    Allocated { val: NIL, heap_ptr }
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let input = if args.len() >= 2 { &args[1] } else { "false" };
     let heap_size = if args.len() >= 3 { &args[2] } else { "10000" };
-    let input = parse_input(&input);
+    let parse = |arg: &str| parse_input(arg).unwrap_or_else(|err| invalid_input(arg, err));
+    let input = parse(input);
+    let extra: Vec<InputValue> = args.iter().skip(3).map(|arg| parse(arg)).collect();
     let heap_size = parse_heap_size(&heap_size);
 
     // Initialize heap
@@ -329,6 +1138,15 @@ fn main() {
         HEAP_END = HEAP_START.add(heap_size);
     }
 
-    let i: u64 = unsafe { our_code_starts_here(input, HEAP_START, HEAP_END) };
+    let i: u64 = unsafe {
+        let mut heap_ptr = HEAP_START as *mut u64;
+        INPUT = write_input(&input, &mut heap_ptr);
+        ARGS = if extra.is_empty() {
+            std::ptr::addr_of_mut!(NO_ARGS) as u64 | 0b001
+        } else {
+            write_input(&InputValue::Vec(extra), &mut heap_ptr)
+        };
+        our_code_starts_here(heap_ptr, HEAP_END)
+    };
     unsafe { snek_print(i) };
 }
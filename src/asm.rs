@@ -22,6 +22,13 @@ pub enum Reg {
     R15,
 }
 
+/// The SSE registers used for float arithmetic.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Xmm {
+    Xmm0,
+    Xmm1,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct MemRef {
     pub reg: Reg,
@@ -117,6 +124,9 @@ pub enum CMov {
     GE(Reg, Arg64),
     L(Reg, Arg64),
     LE(Reg, Arg64),
+    /// Unsigned above, as set by `ucomisd`, which is also false for unordered (NaN) operands.
+    A(Reg, Arg64),
+    AE(Reg, Arg64),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -135,6 +145,10 @@ pub enum Instr {
     Sar(BinArgs),
     Sal(BinArgs),
     Shl(BinArgs),
+    /// Shifts a register by the count in `%cl`.
+    SalCl(Reg),
+    SarCl(Reg),
+    Neg(Loc),
     Cmp(BinArgs),
     Not(Loc),
     Test(BinArgs),
@@ -143,11 +157,17 @@ pub enum Instr {
     Pop(Loc),
 
     Label(String),
+    /// Pads the code so the next instruction starts at a multiple of the given number of bytes.
+    Align(u32),
 
     Call(String),
+    CallIndirect(MemRef),
     Ret,
+    /// Returns and pops the given number of bytes of arguments.
+    RetN(u16),
 
     Jmp(String),
+    JmpIndirect(MemRef),
     Je(String),
     Jne(String),
     Jl(String),
@@ -163,8 +183,26 @@ pub enum Instr {
     Jno(String), // jump if last arith operation didn't overflow
 
     Lea(Reg, MemRef),
+    LeaLabel(Reg, String),
     Rep(StrOp),
     Cqo,
+    /// Set and clear the direction flag, which makes string operations go backwards.
+    Std,
+    Cld,
+
+    /// Moves the bits of a general-purpose register into an SSE register, or back.
+    MovqToXmm(Xmm, Reg),
+    MovqFromXmm(Reg, Xmm),
+    /// Loads a double from memory.
+    Movsd(Xmm, MemRef),
+    /// Converts a signed integer to a double.
+    Cvtsi2sd(Xmm, Reg),
+    Addsd(Xmm, Xmm),
+    Subsd(Xmm, Xmm),
+    Mulsd(Xmm, Xmm),
+    Divsd(Xmm, Xmm),
+    /// Compares two doubles, setting the flags like an unsigned comparison.
+    Ucomisd(Xmm, Xmm),
 
     Comment(String),
 }
@@ -172,6 +210,7 @@ pub enum Instr {
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum StrOp {
     Stosq,
+    Movsq,
 }
 
 pub fn reg_to_string(r: Reg) -> String {
@@ -195,6 +234,13 @@ pub fn reg_to_string(r: Reg) -> String {
     }
 }
 
+pub fn xmm_to_string(x: Xmm) -> String {
+    match x {
+        Xmm::Xmm0 => String::from("xmm0"),
+        Xmm::Xmm1 => String::from("xmm1"),
+    }
+}
+
 impl PartialEq<Loc> for Arg64 {
     fn eq(&self, other: &Loc) -> bool {
         match (self, other) {
@@ -332,15 +378,22 @@ pub fn instr_to_string(i: &Instr) -> String {
         Instr::Shl(args) => format!("  shl {}", bin_args_to_string(*args)),
         Instr::Sar(args) => format!("  sar {}", bin_args_to_string(*args)),
         Instr::Sal(args) => format!("  sal {}", bin_args_to_string(*args)),
+        Instr::SalCl(reg) => format!("  sal {}, cl", reg_to_string(*reg)),
+        Instr::SarCl(reg) => format!("  sar {}, cl", reg_to_string(*reg)),
+        Instr::Neg(loc) => format!("  neg {}", loc_to_string(*loc)),
         Instr::Cmp(args) => format!("  cmp {}", bin_args_to_string(*args)),
         Instr::Test(args) => format!("  test {}", bin_args_to_string(*args)),
         Instr::Push(arg) => format!("  push {}", arg32_to_string(*arg)),
         Instr::Pop(loc) => format!("  pop {}", loc_to_string(*loc)),
         Instr::Label(s) => format!("{}:", s),
+        Instr::Align(n) => format!("  align {n}"),
 
         Instr::Call(s) => format!("  call {s}"),
+        Instr::CallIndirect(mem) => format!("  call {}", mem_ref_to_string(*mem)),
         Instr::Ret => format!("  ret"),
+        Instr::RetN(n) => format!("  ret {n}"),
         Instr::Jmp(s) => format!("  jmp {s}"),
+        Instr::JmpIndirect(mem) => format!("  jmp {}", mem_ref_to_string(*mem)),
         Instr::Je(s) => format!("  je {s}"),
         Instr::Jne(s) => format!("  jne {s}"),
         Instr::Jle(s) => format!("  jle {s}"),
@@ -377,18 +430,47 @@ pub fn instr_to_string(i: &Instr) -> String {
             CMov::LE(reg, arg) => {
                 format!("  cmovle {}, {}", reg_to_string(*reg), arg64_to_string(arg))
             }
+            CMov::A(reg, arg) => {
+                format!("  cmova {}, {}", reg_to_string(*reg), arg64_to_string(arg))
+            }
+            CMov::AE(reg, arg) => {
+                format!("  cmovae {}, {}", reg_to_string(*reg), arg64_to_string(arg))
+            }
         },
         Instr::Lea(reg, mem) => {
             format!("  lea {}, {}", reg_to_string(*reg), mem_ref_to_string(*mem))
         }
+        Instr::LeaLabel(reg, lbl) => format!("  lea {}, [rel {lbl}]", reg_to_string(*reg)),
         Instr::Rep(op) => format!("  rep {}", str_op_to_string(*op)),
         Instr::Cqo => format!("  cqo"),
+        Instr::Std => "  std".to_string(),
+        Instr::Cld => "  cld".to_string(),
+        Instr::MovqToXmm(x, reg) => {
+            format!("  movq {}, {}", xmm_to_string(*x), reg_to_string(*reg))
+        }
+        Instr::MovqFromXmm(reg, x) => {
+            format!("  movq {}, {}", reg_to_string(*reg), xmm_to_string(*x))
+        }
+        Instr::Movsd(x, mem) => {
+            format!("  movsd {}, {}", xmm_to_string(*x), mem_ref_to_string(*mem))
+        }
+        Instr::Cvtsi2sd(x, reg) => {
+            format!("  cvtsi2sd {}, {}", xmm_to_string(*x), reg_to_string(*reg))
+        }
+        Instr::Addsd(x1, x2) => format!("  addsd {}, {}", xmm_to_string(*x1), xmm_to_string(*x2)),
+        Instr::Subsd(x1, x2) => format!("  subsd {}, {}", xmm_to_string(*x1), xmm_to_string(*x2)),
+        Instr::Mulsd(x1, x2) => format!("  mulsd {}, {}", xmm_to_string(*x1), xmm_to_string(*x2)),
+        Instr::Divsd(x1, x2) => format!("  divsd {}, {}", xmm_to_string(*x1), xmm_to_string(*x2)),
+        Instr::Ucomisd(x1, x2) => {
+            format!("  ucomisd {}, {}", xmm_to_string(*x1), xmm_to_string(*x2))
+        }
     }
 }
 
 fn str_op_to_string(op: StrOp) -> String {
     match op {
         StrOp::Stosq => format!("stosq"),
+        StrOp::Movsq => "movsq".to_string(),
     }
 }
 
@@ -0,0 +1,373 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use crate::{
+    error::CompileError,
+    syntax::{Binding, Expr, ExprKind, FunDecl, GlobalDecl, Prog, Symbol},
+};
+
+/// Checks scoping, arity, duplicate names and `break` placement over the whole program, reporting
+/// every error found sorted by position. Code generation assumes a program that passed this check.
+pub fn check(prg: &Prog) -> Result<(), Vec<CompileError>> {
+    let mut checker = Checker {
+        funs: HashMap::new(),
+        globals: HashSet::new(),
+        errors: vec![],
+    };
+    let struct_funs = prg.struct_funs();
+    checker.declare_funs(&struct_funs);
+    checker.declare_funs(&prg.funs);
+    checker.declare_globals(&prg.globals);
+    for fun in struct_funs.iter().chain(&prg.funs) {
+        checker.check_fun(fun);
+    }
+    // Each initializer only sees the globals defined before it.
+    checker.globals.clear();
+    for global in &prg.globals {
+        checker.check_expr(&Scope::default(), &global.init);
+        checker.globals.insert(global.name);
+    }
+    checker.check_global_order(&prg.globals);
+    checker.check_expr(&Scope::default(), &prg.main);
+
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        checker.errors.sort_by_key(|err| err.span().start);
+        Err(checker.errors)
+    }
+}
+
+struct Checker<'a> {
+    funs: HashMap<Symbol, &'a FunDecl>,
+    /// The globals in scope, which are variables wherever a local one doesn't shadow them.
+    globals: HashSet<Symbol>,
+    errors: Vec<CompileError>,
+}
+
+#[derive(Clone, Default)]
+struct Scope<'a> {
+    vars: im::HashSet<Symbol>,
+    /// The local functions in scope that aren't shadowed by a variable.
+    local_funs: im::HashMap<Symbol, &'a FunDecl>,
+    /// The variables from outside the innermost enclosing local function, which it can't assign.
+    captured: im::HashSet<Symbol>,
+    in_loop: bool,
+}
+
+impl<'a> Scope<'a> {
+    fn with_params(params: &[Binding]) -> Scope<'a> {
+        Scope {
+            vars: params.iter().map(|param| param.name).collect(),
+            ..Scope::default()
+        }
+    }
+
+    fn add_binding(&self, x: Symbol) -> Scope<'a> {
+        Scope {
+            vars: self.vars.update(x),
+            local_funs: self.local_funs.without(&x),
+            captured: self.captured.without(&x),
+            ..self.clone()
+        }
+    }
+
+    /// The scope of a lambda body: it sees every enclosing variable as well as its own parameters,
+    /// but can't `break` out of a loop around the lambda.
+    fn in_lambda(&self, params: &[Binding]) -> Scope<'a> {
+        let scope = Scope {
+            in_loop: false,
+            ..self.clone()
+        };
+        params
+            .iter()
+            .fold(scope, |scope, param| scope.add_binding(param.name))
+    }
+
+    /// The scope of the expression a group of local functions is defined for, and of their bodies.
+    fn with_funs(&self, funs: &'a [FunDecl]) -> Scope<'a> {
+        let mut scope = self.clone();
+        for fun in funs {
+            scope.vars.remove(&fun.name);
+            scope.local_funs.insert(fun.name, fun);
+        }
+        scope
+    }
+
+    /// The scope of the body of a local function, which can read but not assign the variables
+    /// around it.
+    fn in_local_fun(&self, params: &[Binding]) -> Scope<'a> {
+        let scope = Scope {
+            captured: self.vars.clone(),
+            ..self.clone()
+        };
+        scope.in_lambda(params)
+    }
+
+    fn in_loop(&self) -> Scope<'a> {
+        Scope {
+            in_loop: true,
+            ..self.clone()
+        }
+    }
+}
+
+impl<'a> Checker<'a> {
+    fn declare_funs(&mut self, funs: &'a [FunDecl]) {
+        for fun in funs {
+            match self.funs.entry(fun.name) {
+                Entry::Occupied(_) => self.errors.push(CompileError::DuplicateFunction {
+                    name: fun.name,
+                    span: fun.span,
+                }),
+                Entry::Vacant(entry) => {
+                    entry.insert(fun);
+                }
+            }
+        }
+    }
+
+    /// Declares the globals, which share a namespace with the top-level functions.
+    fn declare_globals(&mut self, globals: &[GlobalDecl]) {
+        for global in globals {
+            if self.funs.contains_key(&global.name) || !self.globals.insert(global.name) {
+                self.errors.push(CompileError::DuplicateBinding {
+                    name: global.name,
+                    span: global.span,
+                });
+            }
+        }
+    }
+
+    /// Reports each global whose initializer refers to a top-level function that can read a global
+    /// not defined yet, whose slot still holds nil. Functions are followed through the functions
+    /// they refer to and through the initializers of the earlier globals they read, which may hold
+    /// lambdas. Any reference counts, since a function value can be called later on.
+    fn check_global_order(&mut self, globals: &[GlobalDecl]) {
+        let index: HashMap<Symbol, usize> = globals
+            .iter()
+            .enumerate()
+            .map(|(i, global)| (global.name, i))
+            .collect();
+        for (i, global) in globals.iter().enumerate() {
+            let mut direct = vec![];
+            global.init.free_vars(&im::HashSet::new(), &mut direct);
+            'through: for through in direct {
+                let mut seen = HashSet::from([through]);
+                let mut stack = vec![through];
+                while let Some(x) = stack.pop() {
+                    let mut next = vec![];
+                    match (self.funs.get(&x), index.get(&x)) {
+                        (Some(fun), _) => {
+                            let bound = fun.all_params().iter().map(|param| param.name).collect();
+                            fun.body.free_vars(&bound, &mut next);
+                        }
+                        (None, Some(&j)) if j < i => {
+                            globals[j].init.free_vars(&im::HashSet::new(), &mut next);
+                        }
+                        // Reading a later global directly is already reported as unbound.
+                        (None, Some(_)) if x != through => {
+                            self.errors.push(CompileError::GlobalReadEarly {
+                                name: x,
+                                through,
+                                span: global.init.span,
+                            });
+                            break 'through;
+                        }
+                        _ => {}
+                    }
+                    for y in next {
+                        if seen.insert(y) {
+                            stack.push(y);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_fun(&mut self, fun: &'a FunDecl) {
+        let params = fun.all_params();
+        self.check_dup_bindings(&params);
+        self.check_expr(&Scope::with_params(&params), &fun.body);
+    }
+
+    fn check_expr(&mut self, scope: &Scope<'a>, e: &'a Expr) {
+        match &e.kind {
+            ExprKind::Number(_)
+            | ExprKind::Float(_)
+            | ExprKind::Boolean(_)
+            | ExprKind::Nil
+            | ExprKind::Input
+            | ExprKind::Args
+            | ExprKind::PrintStack
+            | ExprKind::PrintHeap
+            | ExprKind::Gc => {}
+            ExprKind::Var(x) => {
+                // A function name that isn't shadowed is a function value.
+                match self.fun_named(scope, *x) {
+                    _ if self.is_var(scope, *x) => {}
+                    None => self.check_bound(scope, *x, e),
+                    Some(fun) if fun.rest.is_some() => {
+                        self.errors.push(CompileError::VariadicAsValue {
+                            name: *x,
+                            span: e.span,
+                        })
+                    }
+                    Some(_) => {}
+                }
+            }
+            ExprKind::Set(x, rhs) => {
+                self.check_bound(scope, *x, e);
+                if scope.captured.contains(x) {
+                    self.errors.push(CompileError::AssignCaptured {
+                        name: *x,
+                        span: e.span,
+                    });
+                }
+                self.check_expr(scope, rhs);
+            }
+            ExprKind::Let(bindings, body) => {
+                self.check_dup_bindings(bindings.iter().map(|(binding, _)| binding));
+                let mut scope = scope.clone();
+                for (binding, rhs) in bindings {
+                    self.check_expr(&scope, rhs);
+                    scope = scope.add_binding(binding.name);
+                }
+                self.check_expr(&scope, body);
+            }
+            ExprKind::Loop(body) => self.check_expr(&scope.in_loop(), body),
+            ExprKind::Break(body) => {
+                if !scope.in_loop {
+                    self.errors
+                        .push(CompileError::BreakOutsideLoop { span: e.span });
+                }
+                self.check_expr(scope, body);
+            }
+            ExprKind::Call(fun, args) => {
+                match &fun.kind {
+                    ExprKind::Var(name) if !self.is_var(scope, *name) => {
+                        match self.fun_named(scope, *name) {
+                            None => self.errors.push(CompileError::UndefinedFunction {
+                                name: *name,
+                                span: e.span,
+                            }),
+                            Some(fun) => {
+                                let expected = fun.params.len();
+                                let variadic = fun.rest.is_some();
+                                if args.len() < expected || (!variadic && args.len() > expected) {
+                                    self.errors.push(CompileError::WrongArity {
+                                        name: *name,
+                                        expected,
+                                        variadic,
+                                        got: args.len(),
+                                        span: e.span,
+                                    })
+                                }
+                            }
+                        }
+                    }
+                    _ => self.check_expr(scope, fun),
+                }
+                self.check_exprs(scope, args);
+            }
+            ExprKind::Lambda(params, body) => {
+                self.check_dup_bindings(params);
+                self.check_expr(&scope.in_lambda(params), body);
+            }
+            ExprKind::LocalFuns(funs, body) => {
+                let mut seen = HashSet::new();
+                for fun in funs {
+                    if !seen.insert(fun.name) {
+                        self.errors.push(CompileError::DuplicateFunction {
+                            name: fun.name,
+                            span: fun.span,
+                        });
+                    }
+                }
+                let scope = scope.with_funs(funs);
+                for fun in funs {
+                    let params = fun.all_params();
+                    self.check_dup_bindings(&params);
+                    self.check_expr(&scope.in_local_fun(&params), &fun.body);
+                }
+                self.check_expr(&scope, body);
+            }
+            ExprKind::UnOp(_, e) | ExprKind::VecLen(e) | ExprKind::Raise(e) => {
+                self.check_expr(scope, e)
+            }
+            ExprKind::Try(body, binding, handler) => {
+                self.check_expr(scope, body);
+                self.check_expr(&scope.add_binding(binding.name), handler);
+            }
+            ExprKind::Match(e, arms) => {
+                self.check_expr(scope, e);
+                for arm in arms {
+                    let bindings = arm.pattern.bindings();
+                    self.check_dup_bindings(bindings.iter().map(|(binding, _)| binding));
+                    let scope = bindings.iter().fold(scope.clone(), |scope, (binding, _)| {
+                        scope.add_binding(binding.name)
+                    });
+                    self.check_expr(&scope, &arm.body);
+                }
+            }
+            ExprKind::BinOp(_, e1, e2) | ExprKind::MakeVec(e1, e2) | ExprKind::VecGet(e1, e2) => {
+                self.check_expr(scope, e1);
+                self.check_expr(scope, e2);
+            }
+            ExprKind::If(e1, e2, e3) | ExprKind::VecSet(e1, e2, e3) => {
+                self.check_expr(scope, e1);
+                self.check_expr(scope, e2);
+                self.check_expr(scope, e3);
+            }
+            ExprKind::Vec(es)
+            | ExprKind::Block(es)
+            | ExprKind::Record(_, es)
+            | ExprKind::Dict(_, es)
+            | ExprKind::VecOp(_, es)
+            | ExprKind::Io(_, es) => self.check_exprs(scope, es),
+        }
+    }
+
+    /// The function `x` names where it isn't a variable: the innermost local function of that name,
+    /// or else the top-level one.
+    fn fun_named(&self, scope: &Scope<'a>, x: Symbol) -> Option<&'a FunDecl> {
+        scope
+            .local_funs
+            .get(&x)
+            .copied()
+            .or_else(|| self.funs.get(&x).copied())
+    }
+
+    /// Whether `x` is a variable in `scope`: a local one, or a global that no local function
+    /// shadows.
+    fn is_var(&self, scope: &Scope<'a>, x: Symbol) -> bool {
+        scope.vars.contains(&x) || (self.globals.contains(&x) && !scope.local_funs.contains_key(&x))
+    }
+
+    fn check_exprs(&mut self, scope: &Scope<'a>, es: &'a [Expr]) {
+        for e in es {
+            self.check_expr(scope, e);
+        }
+    }
+
+    fn check_bound(&mut self, scope: &Scope<'a>, x: Symbol, e: &Expr) {
+        if !self.is_var(scope, x) {
+            self.errors.push(CompileError::UnboundIdentifier {
+                name: x,
+                span: e.span,
+            });
+        }
+    }
+
+    fn check_dup_bindings<'b>(&mut self, bindings: impl IntoIterator<Item = &'b Binding>) {
+        let mut seen = HashSet::new();
+        for binding in bindings {
+            if !seen.insert(binding.name) {
+                self.errors.push(CompileError::DuplicateBinding {
+                    name: binding.name,
+                    span: binding.span,
+                });
+            }
+        }
+    }
+}
@@ -5,25 +5,82 @@ use crate::{
         instrs_to_string, Arg32, Arg64, BinArgs, CMov, Instr, Loc, MemRef, MovArgs, Offset,
         Reg::{self, *},
         Reg32,
-        StrOp::Stosq,
+        StrOp::{Movsq, Stosq},
+        Xmm::{self, *},
     },
-    mref,
-    syntax::{Expr, FunDecl, Op1, Op2, Prog, Symbol},
+    check,
+    error::{CompileError, Warning},
+    infer, lift, mref,
+    syntax::{
+        Binding, DictOp, Expr, ExprKind, FunDecl, GlobalDecl, IoOp, MatchArm, Op1, Op2, Pattern,
+        Prog, RecordOp, StructDecl, Symbol, Type, VecOp,
+    },
+    typeck::{self, Types},
 };
 
 struct Session {
     tag: u32,
     instrs: Vec<Instr>,
-    funs: HashMap<Symbol, usize>,
+    /// The code of every lambda compiled so far, emitted after the top-level functions.
+    lambdas: Vec<Instr>,
+    /// The number of fixed parameters of every top-level function and whether it takes a rest
+    /// parameter, for packing extra arguments and turning function names into closures.
+    funs: HashMap<Symbol, (usize, bool)>,
+    /// The top-level functions with annotated parameters. Their closures enter them at
+    /// [`checked_label`], which checks the arguments first; direct calls check them at the call
+    /// site where needed.
+    typed_funs: HashSet<Symbol>,
+    types: Types,
+    /// Every float literal, emitted as a static float object `snek_float_{index}`.
+    floats: Vec<f64>,
+    /// The index of each global's slot in [`GLOBALS`].
+    globals: HashMap<Symbol, usize>,
 }
 
 const INVALID_ARG: &str = "invalid_argument";
 const OVERFLOW: &str = "overflow";
 const INDEX_OUT_OF_BOUNDS: &str = "index_out_of_bounds";
 const INVALID_SIZE: &str = "invalid_vec_size";
+const WRONG_ARITY: &str = "wrong_arity";
+const WRONG_RECORD_TYPE: &str = "wrong_record_type";
+const DIVIDE_BY_ZERO: &str = "divide_by_zero";
+const INVALID_INPUT: &str = "invalid_input";
+
+/// The label of each runtime error and its `ErrCode` in runtime/start.rs. Each error raises a
+/// static error object holding its code.
+const ERRORS: [(&str, i32); 8] = [
+    (INVALID_ARG, 1),
+    (OVERFLOW, 2),
+    (INDEX_OUT_OF_BOUNDS, 3),
+    (INVALID_SIZE, 4),
+    (WRONG_ARITY, 6),
+    (WRONG_RECORD_TYPE, 7),
+    (DIVIDE_BY_ZERO, 8),
+    (INVALID_INPUT, 11),
+];
+
+/// The label of the globals' slots in the data section, which the collector treats as roots.
+const GLOBALS: &str = "snek_globals";
+/// The runtime's slots holding `input` and `(args)`, which it parses onto the heap before the
+/// program starts and treats as roots.
+const INPUT: &str = "snek_input";
+const ARGS: &str = "snek_args";
+
+/// The code of the error a `match` raises when no arm matches, whose payload also holds the value.
+const NO_MATCH: i32 = 10;
+
+/// Raises the value in `%rax`.
+const RAISE: &str = "snek_raise";
+/// The innermost `try` handler, or 0 if there is none. A handler is four stack slots, at
+/// increasing addresses: the enclosing handler, the `%rbp` and `%rsp` of the frame the `try` is
+/// in, and the address of its `catch` code.
+const HANDLER: &str = "snek_handler";
+const HANDLER_PREV: i32 = 0;
+const HANDLER_RBP: i32 = 8;
+const HANDLER_RSP: i32 = 16;
+const HANDLER_CATCH: i32 = 24;
 
 const STACK_BASE: Reg = Rbx;
-const INPUT_REG: Reg = R13;
 const HEAP_END: Reg = R14;
 const HEAP_PTR: Reg = R15;
 
@@ -31,12 +88,135 @@ const NIL: i32 = 0b001;
 const MEM_SET_VAL: i32 = NIL;
 const GC_WORD_VAL: i32 = 0;
 
+/// Pointers to vectors are tagged with `0b001`, pointers to every other heap object with `0b101`.
+const VEC_TAG: i32 = 0b001;
+const OBJ_TAG: i32 = 0b101;
+
+/// Heap objects are laid out as `[gc word][header][payload...]`. The header holds the object's
+/// kind in its top byte and its payload length in words in its low 32 bits; vectors are kind 0, so
+/// their header is just their length. Kinds must match runtime/start.rs.
+///
+/// A vector's header also holds its slack in bits 32 to 55: the number of unused slots, holding
+/// nil, after its elements. `vec-push!` fills them before the vector has to grow.
+const CLOSURE_KIND: i64 = 1;
+const BOX_KIND: i64 = 2;
+/// A record's header also holds its struct's type id, in bits 32 to 55.
+const RECORD_KIND: i64 = 3;
+/// A bignum's payload is its sign and magnitude as raw words. Only the runtime creates bignums.
+const BIGNUM_KIND: i64 = 4;
+/// A float's payload is the raw bits of a double.
+const FLOAT_KIND: i64 = 5;
+/// An error's payload is its `ErrCode`, as a fixnum.
+const ERROR_KIND: i64 = 6;
+
+/// A closure's payload is `[code pointer][arity][captured values...]`.
+const CLOSURE_CODE: i32 = 16 - OBJ_TAG;
+const CLOSURE_ARITY: i32 = 24 - OBJ_TAG;
+const CLOSURE_CAPTURES: i32 = 32 - OBJ_TAG;
+/// A box's payload is the single value it holds.
+const BOX_VAL: i32 = 16 - OBJ_TAG;
+/// A record's payload is its fields in declaration order.
+const RECORD_FIELDS: i32 = 16 - OBJ_TAG;
+const FLOAT_VAL: i32 = 16 - OBJ_TAG;
+/// One unit of slack in a vector's header.
+const VEC_SLACK_ONE: i64 = 1 << 32;
+const ERROR_CODE: i32 = 16 - OBJ_TAG;
+
+/// Where a variable's value lives. A variable that is both captured by a lambda and assigned with
+/// `set!` is boxed: its slot holds a pointer to a heap box with the value, so the lambda and the
+/// scope it was created in see each other's assignments.
+#[derive(Debug, Clone, Copy)]
+struct VarLoc {
+    mem: MemRef,
+    boxed: bool,
+}
+
+/// The shape of the function being compiled, which a tail call replaces: the number of slots in
+/// its incoming argument area and in its frame.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    arg_slots: i32,
+    size: i32,
+}
+
+/// What the type checker knows about the operands of an arithmetic operation or comparison.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operands {
+    Unknown,
+    /// Both are numbers, so the runtime needn't check them.
+    Numbers,
+    /// Both are ints, so they can't be floats either.
+    Ints,
+}
+
+impl Operands {
+    fn of(t1: Type, t2: Type) -> Operands {
+        match (t1, t2) {
+            (Type::Int, Type::Int) => Operands::Ints,
+            _ if t1.is_number() && t2.is_number() => Operands::Numbers,
+            _ => Operands::Unknown,
+        }
+    }
+}
+
+/// An arm of a `match` being compiled into a decision tree, with the tests left before it is
+/// known to match: each a path of vector indices from the scrutinee to a value, and the pattern
+/// that value must match.
+#[derive(Clone)]
+struct MatchRow<'a> {
+    tests: Vec<(Vec<usize>, &'a Pattern)>,
+    arm: usize,
+}
+
+/// What a pattern that can fail checks about a value, regardless of its subpatterns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PatternHead {
+    Number(i64),
+    Boolean(bool),
+    Nil,
+    /// A vector of this length.
+    Vec(usize),
+}
+
+impl<'a> MatchRow<'a> {
+    /// What is left of the row once the value at `path` is known to have `head`, or, if `head` is
+    /// `None`, to have none of the heads tested for there; `None` if the row can't match then.
+    fn specialize(&self, path: &[usize], head: Option<PatternHead>) -> Option<MatchRow<'a>> {
+        let Some(i) = self.tests.iter().position(|(p, _)| p == path) else {
+            return Some(self.clone());
+        };
+        let pat = self.tests[i].1;
+        let subtests = match (pattern_head(pat), head) {
+            (None, _) => vec![],
+            (Some(h), Some(head)) if h == head => match pat {
+                Pattern::Vec(pats) => pats
+                    .iter()
+                    .enumerate()
+                    .map(|(j, pat)| ([path, &[j]].concat(), pat))
+                    .collect(),
+                _ => vec![],
+            },
+            _ => return None,
+        };
+        let mut tests = self.tests.clone();
+        tests.splice(i..=i, subtests);
+        Some(MatchRow {
+            tests,
+            arm: self.arm,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Ctxt<'a> {
-    env: im::HashMap<Symbol, MemRef>,
+    env: im::HashMap<Symbol, VarLoc>,
     si: u32,
     curr_lbl: Option<&'a str>,
-    in_fun: bool,
+    /// The slot holding the handler a `break` restores, if it jumps out of any `try` bodies: the
+    /// one the outermost `try` inside the innermost loop replaced.
+    break_handler: Option<MemRef>,
+    /// `None` in the main expression, whose frame can't be replaced.
+    frame: Option<Frame>,
 }
 
 impl<'a> Ctxt<'a> {
@@ -44,35 +224,26 @@ impl<'a> Ctxt<'a> {
         Ctxt {
             si: 0,
             curr_lbl: None,
+            break_handler: None,
             env: im::HashMap::default(),
-            in_fun: false,
+            frame: None,
         }
     }
 
-    fn with_params(params: &[Symbol]) -> Ctxt<'a> {
-        let env = params
-            .iter()
-            .enumerate()
-            .map(|(i, param)| (*param, mref![Rbp + %(8 * (i + 2))]))
-            .collect();
+    fn in_frame(locals: u32, arity: usize) -> Ctxt<'a> {
         Ctxt {
-            si: 0,
-            curr_lbl: None,
-            env,
-            in_fun: true,
+            frame: Some(Frame {
+                arg_slots: arg_slots(arity),
+                size: frame_size(locals, &[Rbp]) as i32,
+            }),
+            ..Ctxt::new()
         }
     }
 
-    fn lookup(&self, x: Symbol) -> MemRef {
-        *self
-            .env
-            .get(&x)
-            .unwrap_or_else(|| raise_unbound_identifier(x))
-    }
-
     fn set_curr_lbl(&self, lbl: &'a str) -> Ctxt<'a> {
         Ctxt {
             curr_lbl: Some(lbl),
+            break_handler: None,
             ..self.clone()
         }
     }
@@ -88,70 +259,242 @@ impl<'a> Ctxt<'a> {
         )
     }
 
-    fn add_binding(&self, x: Symbol, mem: MemRef) -> Ctxt<'a> {
+    fn add_binding(&self, x: Symbol, var: VarLoc) -> Ctxt<'a> {
         Ctxt {
-            env: self.env.update(x, mem),
-            ..*self
-        }
-    }
-}
-
-pub fn compile(prg: &Prog) -> String {
-    match fun_arity_map(prg) {
-        Ok(funs) => {
-            let mut sess = Session::new(funs);
-            let locals = depth(&prg.main);
-            sess.compile_funs(&prg.funs);
-            sess.emit_instr(Instr::Label("our_code_starts_here".to_string()));
-            let callee_saved = [Rbp, STACK_BASE, INPUT_REG, HEAP_END, HEAP_PTR];
-            sess.fun_entry(locals, &callee_saved);
-            sess.emit_instrs([
-                Instr::Mov(MovArgs::ToReg(STACK_BASE, Arg64::Reg(Rbp))),
-                Instr::Mov(MovArgs::ToReg(INPUT_REG, Arg64::Reg(Rdi))),
-                Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rsi))),
-                Instr::Mov(MovArgs::ToReg(HEAP_END, Arg64::Reg(Rdx))),
-            ]);
-            sess.compile_expr(&Ctxt::new(), Loc::Reg(Rax), &prg.main);
-            sess.fun_exit(locals, &callee_saved);
+            env: self.env.update(x, var),
+            ..self.clone()
+        }
+    }
+}
+
+/// Ways to change how a program is compiled.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Infer the types of unannotated code with [`infer::infer`], leaving out the checks they make
+    /// redundant. A program that doesn't type-check is compiled as usual, with a warning.
+    pub infer_types: bool,
+}
+
+pub fn compile(prg: &Prog) -> Result<String, Vec<CompileError>> {
+    compile_with(prg, Options::default()).map(|(asm, _)| asm)
+}
+
+/// Compiles `prg` with `options`, returning the assembly and any warnings.
+pub fn compile_with(
+    prg: &Prog,
+    options: Options,
+) -> Result<(String, Vec<Warning>), Vec<CompileError>> {
+    check::check(prg)?;
+    let prg = &lift::lift(prg);
+
+    let struct_funs = prg.struct_funs();
+    let mut types = typeck::check(prg, &struct_funs)?;
+    let mut warnings = vec![];
+    if options.infer_types {
+        match infer::infer(prg, &struct_funs) {
+            Ok(inferred) => types.refine(inferred),
+            Err(warning) => warnings.push(warning),
+        }
+    }
+    let mut sess = Session::new(struct_funs.iter().chain(&prg.funs), &prg.globals, types);
+    let locals = prg
+        .globals
+        .iter()
+        .map(|global| depth(&global.init))
+        .fold(depth(&prg.main), u32::max);
+    sess.compile_funs(&struct_funs);
+    sess.compile_funs(&prg.funs);
+    sess.emit_instr(Instr::Label("our_code_starts_here".to_string()));
+    let callee_saved = [Rbp, STACK_BASE, HEAP_END, HEAP_PTR];
+    sess.fun_entry(locals, &callee_saved);
+    sess.emit_instrs([
+        Instr::Mov(MovArgs::ToReg(STACK_BASE, Arg64::Reg(Rbp))),
+        Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rdi))),
+        Instr::Mov(MovArgs::ToReg(HEAP_END, Arg64::Reg(Rsi))),
+    ]);
+    for global in &prg.globals {
+        sess.compile_expr(&Ctxt::new(), Loc::Reg(Rax), &global.init);
+        let slot = sess.global_slot(global.name);
+        sess.move_to(Loc::Mem(slot), Arg64::Reg(Rax));
+    }
+    sess.compile_expr(&Ctxt::new(), Loc::Reg(Rax), &prg.main);
+    sess.fun_exit(locals, &callee_saved, 0);
 
-            format!(
-                "
+    let asm = format!(
+        "
 section .text
-extern snek_error
 extern snek_print
 extern snek_alloc_vec
 extern snek_print_stack
 extern snek_print_heap
 extern snek_try_gc
 extern snek_gc
+extern snek_equal
+extern snek_add
+extern snek_sub
+extern snek_mul
+extern snek_div
+extern snek_mod
+extern snek_remainder
+extern snek_bit_and
+extern snek_bit_or
+extern snek_bit_xor
+extern snek_bit_not
+extern snek_shift
+extern snek_abs
+extern snek_min
+extern snek_max
+extern snek_compare
+extern snek_eq
+extern snek_alloc_float
+extern snek_uncaught
+extern snek_make_dict
+extern snek_dict_set
+extern snek_dict_get
+extern snek_dict_has
+extern snek_dict_remove
+extern snek_dict_keys
+extern snek_vec_grow
+extern snek_read_int
+extern snek_read_value
+extern snek_eof
+extern snek_display
+extern snek_newline
+extern snek_input
+extern snek_args
 global our_code_starts_here
 {}
-{INVALID_ARG}:
-  mov edi, 1
-  call snek_error
-{OVERFLOW}:
-  mov edi, 2
-  call snek_error
-{INDEX_OUT_OF_BOUNDS}:
-  mov edi, 3
-  call snek_error
-{INVALID_SIZE}:
-  mov edi, 4
-  call snek_error
+{}
+{}
+section .data
+global snek_structs
+global snek_globals
+global snek_global_count
+{}{}{}{}
 ",
-                instrs_to_string(&sess.instrs)
-            )
+        instrs_to_string(&sess.instrs),
+        instrs_to_string(&sess.lambdas),
+        error_stubs(),
+        struct_names(&prg.structs),
+        float_literals(&sess.floats),
+        error_objects(),
+        global_slots(&prg.globals),
+    );
+    Ok((asm, warnings))
+}
+
+/// The table the runtime prints records with: `snek_structs` points, for each type id, to the
+/// struct's name followed by its field names, as consecutive NUL-terminated strings.
+fn struct_names(structs: &[StructDecl]) -> String {
+    let mut table = "snek_structs:\n".to_string();
+    let mut names = String::new();
+    for (id, decl) in structs.iter().enumerate() {
+        table.push_str(&format!("  dq snek_struct_{id}\n"));
+        let mut bytes = vec![];
+        for name in [decl.name]
+            .into_iter()
+            .chain(decl.fields.iter().map(|f| f.name))
+        {
+            bytes.extend(name.to_string().bytes().chain([0]).map(|b| b.to_string()));
         }
-        Err(dup) => raise_duplicate_function(dup),
+        names.push_str(&format!("snek_struct_{id}:\n  db {}\n", bytes.join(", ")));
+    }
+    table + &names
+}
+
+/// A slot for each global, holding nil until its `define` runs, and their number.
+fn global_slots(globals: &[GlobalDecl]) -> String {
+    let mut slots = format!("snek_global_count:\n  dq {}\n{GLOBALS}:\n", globals.len());
+    for _ in globals {
+        slots.push_str(&format!("  dq {NIL}\n"));
+    }
+    slots
+}
+
+/// A static float object for each float literal. They live outside the heap, so the collector
+/// leaves them alone.
+fn float_literals(floats: &[f64]) -> String {
+    let mut objects = "align 8\n".to_string();
+    for (i, x) in floats.iter().enumerate() {
+        objects.push_str(&format!(
+            "snek_float_{i}:\n  dq 0, {}, 0x{:x}\n",
+            header(FLOAT_KIND, 1),
+            x.to_bits()
+        ));
+    }
+    objects
+}
+
+/// The code every runtime error jumps to, and `snek_raise`, which unwinds to the innermost
+/// handler with the raised value in `%rax`. The handler is popped before its `catch` code runs. With
+/// no handler left the runtime reports the value and exits, from a stack aligned for the call since
+/// a raise can come before a function has set up its frame.
+fn error_stubs() -> String {
+    let mut stubs = String::new();
+    for (label, code) in ERRORS {
+        stubs.push_str(&format!(
+            "{label}:\n  lea rax, [rel snek_error_{code} + {OBJ_TAG}]\n  jmp {RAISE}\n"
+        ));
+    }
+    stubs
+        + &format!(
+            "{RAISE}:
+  lea rcx, [rel {HANDLER}]
+  mov rdx, QWORD [rcx]
+  test rdx, rdx
+  jz {RAISE}_uncaught
+  mov r8, QWORD [rdx + {HANDLER_PREV}]
+  mov QWORD [rcx], r8
+  mov rbp, QWORD [rdx + {HANDLER_RBP}]
+  mov rsp, QWORD [rdx + {HANDLER_RSP}]
+  jmp QWORD [rdx + {HANDLER_CATCH}]
+{RAISE}_uncaught:
+  mov rdi, rax
+  and rsp, -16
+  call snek_uncaught
+"
+        )
+}
+
+/// The handler pointer, and a static error object for each runtime error, which lives outside the
+/// heap like the float literals.
+fn error_objects() -> String {
+    let mut objects = format!("{HANDLER}:\n  dq 0\n");
+    for (_, code) in ERRORS {
+        objects.push_str(&format!(
+            "snek_error_{code}:\n  dq 0, {}, {}\n",
+            header(ERROR_KIND, 1),
+            code << 1
+        ));
     }
+    objects
 }
 
 impl Session {
-    fn new(funs: HashMap<Symbol, usize>) -> Session {
+    fn new<'a>(
+        funs: impl Iterator<Item = &'a FunDecl> + Clone,
+        globals: &[GlobalDecl],
+        types: Types,
+    ) -> Session {
         Session {
             tag: 0,
             instrs: vec![],
-            funs,
+            lambdas: vec![],
+            funs: funs
+                .clone()
+                .map(|fun| (fun.name, (fun.params.len(), fun.rest.is_some())))
+                .collect(),
+            typed_funs: funs
+                .filter(|fun| fun.params.iter().any(|param| param.ty.is_some()))
+                .map(|fun| fun.name)
+                .collect(),
+            types,
+            floats: vec![],
+            globals: globals
+                .iter()
+                .enumerate()
+                .map(|(i, global)| (global.name, i))
+                .collect(),
         }
     }
 
@@ -167,7 +510,8 @@ impl Session {
         self.memset(0, size, Reg32::Imm(MEM_SET_VAL));
     }
 
-    fn fun_exit(&mut self, locals: u32, calle_saved: &[Reg]) {
+    /// Tears down the frame and returns, popping `arg_slots` slots of arguments.
+    fn fun_exit(&mut self, locals: u32, calle_saved: &[Reg], arg_slots: i32) {
         let size = frame_size(locals, calle_saved);
         self.emit_instrs([Instr::Add(BinArgs::ToReg(
             Rsp,
@@ -176,7 +520,10 @@ impl Session {
         for reg in calle_saved.iter().rev() {
             self.emit_instr(Instr::Pop(Loc::Reg(*reg)));
         }
-        self.emit_instr(Instr::Ret);
+        match arg_slots {
+            0 => self.emit_instr(Instr::Ret),
+            n => self.emit_instr(Instr::RetN((8 * n).try_into().unwrap())),
+        }
     }
 
     fn compile_funs(&mut self, funs: &[FunDecl]) {
@@ -186,34 +533,148 @@ impl Session {
     }
 
     fn compile_fun(&mut self, fun: &FunDecl) {
-        check_dup_bindings(&fun.params);
         let locals = depth(&fun.body);
+        if self.typed_funs.contains(&fun.name) {
+            // The arguments are above the return address and the closure.
+            self.emit_instr(Instr::Label(checked_label(fun.name)));
+            for (i, param) in fun.params.iter().enumerate() {
+                let mem = mref![Rsp + %(16 + 8 * i as i32)];
+                self.emit_instr(Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(mem))));
+                self.check_type(Rax, param.ty.unwrap_or(Type::Any));
+            }
+        }
         self.emit_instr(Instr::Label(fun_label(fun.name)));
         self.fun_entry(locals, &[Rbp]);
-        self.compile_expr(&Ctxt::with_params(&fun.params), Loc::Reg(Rax), &fun.body);
-        self.fun_exit(locals, &[Rbp]);
+        let params = fun.all_params();
+        let cx = Ctxt::in_frame(locals, params.len());
+        let cx = self.bind_params(cx, &params, &fun.body);
+        self.compile_expr_at(&cx, Loc::Reg(Rax), &fun.body, true);
+        self.fun_exit(locals, &[Rbp], arg_slots(params.len()));
+    }
+
+    /// Compiles a lambda's body as a function at `label`. On entry the captured values are copied
+    /// from the closure into the first local slots, where the body finds them.
+    fn compile_lambda(
+        &mut self,
+        label: &str,
+        captures: &[(Symbol, VarLoc)],
+        params: &[Binding],
+        body: &Expr,
+    ) {
+        let outer = std::mem::take(&mut self.instrs);
+        let locals = captures.len() as u32 + depth(body);
+        self.emit_instr(Instr::Label(label.to_string()));
+        self.fun_entry(locals, &[Rbp]);
+        self.emit_instr(Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(mref![Rbp + 16]))));
+        let mut cx = Ctxt::in_frame(locals, params.len());
+        for (i, &(x, var)) in captures.iter().enumerate() {
+            let (nextcx, mem) = cx.next_local();
+            self.emit_instrs([
+                Instr::Mov(MovArgs::ToReg(
+                    Rcx,
+                    Arg64::Mem(mref![Rax + %(CLOSURE_CAPTURES + 8 * i as i32)]),
+                )),
+                Instr::Mov(MovArgs::ToMem(mem, Reg32::Reg(Rcx))),
+            ]);
+            cx = nextcx.add_binding(x, VarLoc { mem, ..var });
+        }
+        let cx = self.bind_params(cx, params, body);
+        self.compile_expr_at(&cx, Loc::Reg(Rax), body, true);
+        self.fun_exit(locals, &[Rbp], arg_slots(params.len()));
+        let code = std::mem::replace(&mut self.instrs, outer);
+        self.lambdas.extend(code);
+    }
+
+    /// Adds a function's parameters to `cx`, boxing the ones that need it. Every function takes the
+    /// closure it was called through as a hidden first argument, so the parameters start at
+    /// `[rbp + 24]`.
+    fn bind_params<'a>(&mut self, mut cx: Ctxt<'a>, params: &[Binding], body: &Expr) -> Ctxt<'a> {
+        for (i, param) in params.iter().enumerate() {
+            let mem = mref![Rbp + %(8 * (i + 3))];
+            let boxed = needs_box(param.name, [body]);
+            if boxed {
+                self.box_slot(mem);
+            }
+            cx = cx.add_binding(param.name, VarLoc { mem, boxed });
+        }
+        cx
     }
 
     fn compile_expr(&mut self, cx: &Ctxt, dst: Loc, e: &Expr) {
-        match e {
-            Expr::Number(n) => self.move_to(dst, n.repr64()),
-            Expr::Boolean(b) => self.move_to(dst, b.repr64()),
-            Expr::Var(x) => self.move_to(dst, Arg32::Mem(cx.lookup(*x))),
-            Expr::Let(bindings, body) => {
-                check_dup_bindings(bindings.iter().map(|(id, _)| id));
+        self.compile_expr_at(cx, dst, e, false)
+    }
+
+    /// Compiles `e`, which is in tail position of a function body if `tail` is set: the last
+    /// expression of the body, of a `block` or `let` in tail position, or a branch of an `if` in
+    /// tail position, or the body of a `match` arm in tail position. A call there replaces the
+    /// current frame instead of pushing a new one.
+    ///
+    /// A value that the type checker found must be cast is checked once computed, which takes the
+    /// expression out of tail position.
+    fn compile_expr_at(&mut self, cx: &Ctxt, dst: Loc, e: &Expr, tail: bool) {
+        match self.types.cast(e) {
+            Some(ty) => {
+                self.compile_uncast(cx, Loc::Reg(Rax), e, false);
+                self.check_type(Rax, ty);
+                self.move_to(dst, Arg64::Reg(Rax));
+            }
+            None => self.compile_uncast(cx, dst, e, tail),
+        }
+    }
+
+    fn compile_uncast(&mut self, cx: &Ctxt, dst: Loc, e: &Expr, tail: bool) {
+        match &e.kind {
+            ExprKind::LocalFuns(..) => unreachable!("local functions are lifted before compiling"),
+            ExprKind::Number(n) => self.move_to(dst, n.repr64()),
+            ExprKind::Float(x) => {
+                let label = format!("snek_float_{}", self.floats.len());
+                self.floats.push(*x);
+                self.emit_instr(Instr::LeaLabel(Rax, format!("{label} + {OBJ_TAG}")));
+                self.move_to(dst, Arg64::Reg(Rax));
+            }
+            ExprKind::Boolean(b) => self.move_to(dst, b.repr64()),
+            ExprKind::Var(x) => match cx.env.get(x) {
+                Some(var) if var.boxed => {
+                    self.emit_instrs([
+                        Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(var.mem))),
+                        Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(mref![Rax + %(BOX_VAL)]))),
+                    ]);
+                    self.move_to(dst, Arg64::Reg(Rax));
+                }
+                Some(var) => self.move_to(dst, Arg32::Mem(var.mem)),
+                None if self.globals.contains_key(x) => {
+                    let slot = self.global_slot(*x);
+                    self.move_to(dst, Arg32::Mem(slot));
+                }
+                // Anything else is a top-level function used as a value.
+                None => {
+                    let label = match self.typed_funs.contains(x) {
+                        true => checked_label(*x),
+                        false => fun_label(*x),
+                    };
+                    self.alloc_closure(&label, self.funs[x].0, &[]);
+                    self.move_to(dst, Arg64::Reg(Rax));
+                }
+            },
+            ExprKind::Let(bindings, body) => {
                 let mut currcx = cx.clone();
-                for (var, rhs) in bindings {
+                for (i, (binding, rhs)) in bindings.iter().enumerate() {
                     let (nextcx, mem) = currcx.next_local();
                     self.compile_expr(&currcx, Loc::Mem(mem), rhs);
-                    currcx = nextcx.add_binding(*var, mem);
+                    let scope = bindings[i + 1..].iter().map(|(_, rhs)| rhs);
+                    let boxed = needs_box(binding.name, scope.chain([&**body]));
+                    if boxed {
+                        self.box_slot(mem);
+                    }
+                    currcx = nextcx.add_binding(binding.name, VarLoc { mem, boxed });
                 }
-                self.compile_expr(&currcx, Loc::Reg(Rax), body);
+                self.compile_expr_at(&currcx, Loc::Reg(Rax), body, tail);
                 self.memset(cx.si, bindings.len() as u32, Reg32::Imm(MEM_SET_VAL));
                 self.move_to(dst, Arg64::Reg(Rax))
             }
-            Expr::UnOp(op, e) => self.compile_un_op(cx, dst, *op, e),
-            Expr::BinOp(op, e1, e2) => self.compile_bin_op(cx, dst, *op, e1, e2),
-            Expr::If(e1, e2, e3) => {
+            ExprKind::UnOp(op, e) => self.compile_un_op(cx, dst, *op, e),
+            ExprKind::BinOp(op, e1, e2) => self.compile_bin_op(cx, dst, *op, e1, e2),
+            ExprKind::If(e1, e2, e3) => {
                 let tag = self.next_tag();
                 let else_lbl = format!("if_else_{tag}");
                 let end_lbl = format!("if_end_{tag}");
@@ -223,104 +684,257 @@ impl Session {
                     Instr::Cmp(BinArgs::ToReg(Rax, false.repr32().into())),
                     Instr::Je(else_lbl.clone()),
                 ]);
-                self.compile_expr(cx, dst, e2);
+                self.compile_expr_at(cx, dst, e2, tail);
                 self.emit_instrs([Instr::Jmp(end_lbl.clone()), Instr::Label(else_lbl)]);
-                self.compile_expr(cx, dst, e3);
+                self.compile_expr_at(cx, dst, e3, tail);
                 self.emit_instr(Instr::Label(end_lbl))
             }
-            Expr::Loop(e) => {
+            ExprKind::Loop(body) => {
                 let tag = self.next_tag();
                 let loop_start_lbl = format!("loop_start_{tag}");
                 let loop_end_lbl = format!("loop_end_{tag}");
 
                 self.emit_instr(Instr::Label(loop_start_lbl.clone()));
-                self.compile_expr(&cx.set_curr_lbl(&loop_end_lbl), dst, e);
+                self.compile_expr(&cx.set_curr_lbl(&loop_end_lbl), dst, body);
                 self.emit_instrs([Instr::Jmp(loop_start_lbl), Instr::Label(loop_end_lbl)])
             }
-            Expr::Break(e) => {
-                if let Some(lbl) = cx.curr_lbl {
-                    self.compile_expr(cx, dst, e);
-                    self.emit_instr(Instr::Jmp(lbl.to_string()));
-                } else {
-                    raise_break_outside_loop()
+            ExprKind::Break(e) => {
+                let lbl = cx.curr_lbl.expect("break outside loop");
+                self.compile_expr(cx, Loc::Reg(Rax), e);
+                // Leaving `try` bodies uninstalls their handlers, like finishing them would.
+                if let Some(prev) = cx.break_handler {
+                    self.emit_instrs([
+                        Instr::LeaLabel(Rcx, HANDLER.to_string()),
+                        Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Mem(prev))),
+                        Instr::Mov(MovArgs::ToMem(mref![Rcx + 0], Reg32::Reg(Rdx))),
+                    ]);
                 }
+                self.move_to(dst, Arg64::Reg(Rax));
+                self.emit_instr(Instr::Jmp(lbl.to_string()));
+            }
+            ExprKind::Raise(e) => {
+                self.compile_expr(cx, Loc::Reg(Rax), e);
+                self.emit_instr(Instr::Jmp(RAISE.to_string()));
             }
-            Expr::Set(var, e) => {
-                let mem = cx.lookup(*var);
-                self.compile_expr(cx, Loc::Mem(mem), e);
-                self.move_to(dst, Arg32::Mem(mem));
+            ExprKind::Try(body, binding, handler) => {
+                let tag = self.next_tag();
+                let catch_lbl = format!("try_catch_{tag}");
+                let end_lbl = format!("try_end_{tag}");
+
+                // The handler takes the next four slots, starting from the lowest one.
+                let handler_at = |field: i32| mref![Rbp + %(field - 8 * (cx.si + 4) as i32)];
+                let bodycx = Ctxt {
+                    si: cx.si + 4,
+                    break_handler: cx.break_handler.or(Some(handler_at(HANDLER_PREV))),
+                    ..cx.clone()
+                };
+                self.emit_instrs([
+                    Instr::LeaLabel(Rcx, HANDLER.to_string()),
+                    Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(mref![Rcx + 0]))),
+                    Instr::Mov(MovArgs::ToMem(handler_at(HANDLER_PREV), Reg32::Reg(Rax))),
+                    Instr::Mov(MovArgs::ToMem(handler_at(HANDLER_RBP), Reg32::Reg(Rbp))),
+                    Instr::Mov(MovArgs::ToMem(handler_at(HANDLER_RSP), Reg32::Reg(Rsp))),
+                    Instr::LeaLabel(Rax, catch_lbl.clone()),
+                    Instr::Mov(MovArgs::ToMem(handler_at(HANDLER_CATCH), Reg32::Reg(Rax))),
+                    Instr::Lea(Rax, handler_at(HANDLER_PREV)),
+                    Instr::Mov(MovArgs::ToMem(mref![Rcx + 0], Reg32::Reg(Rax))),
+                ]);
+                self.compile_expr(&bodycx, Loc::Reg(Rax), body);
+                self.emit_instrs([
+                    Instr::LeaLabel(Rcx, HANDLER.to_string()),
+                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Mem(handler_at(HANDLER_PREV)))),
+                    Instr::Mov(MovArgs::ToMem(mref![Rcx + 0], Reg32::Reg(Rdx))),
+                    Instr::Jmp(end_lbl.clone()),
+                    // The handler's slots are scanned by the collector, so the catch code is
+                    // aligned to make its address look like a number.
+                    Instr::Align(8),
+                    Instr::Label(catch_lbl),
+                ]);
+                let (nextcx, mem) = cx.next_local();
+                self.move_to(Loc::Mem(mem), Arg64::Reg(Rax));
+                let boxed = needs_box(binding.name, [&**handler]);
+                if boxed {
+                    self.box_slot(mem);
+                }
+                let handlercx = nextcx.add_binding(binding.name, VarLoc { mem, boxed });
+                self.compile_expr_at(&handlercx, Loc::Reg(Rax), handler, tail);
+                self.emit_instr(Instr::Label(end_lbl));
+                self.memset(cx.si, 4, Reg32::Imm(MEM_SET_VAL));
+                self.move_to(dst, Arg64::Reg(Rax));
             }
-            Expr::Block(es) => {
+            ExprKind::Match(e, arms) => self.compile_match(cx, dst, e, arms, tail),
+            ExprKind::Set(x, rhs) => match cx.env.get(x) {
+                Some(&var) if var.boxed => {
+                    self.compile_expr(cx, Loc::Reg(Rax), rhs);
+                    self.emit_instrs([
+                        Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Mem(var.mem))),
+                        Instr::Mov(MovArgs::ToMem(mref![Rcx + %(BOX_VAL)], Reg32::Reg(Rax))),
+                    ]);
+                    self.move_to(dst, Arg64::Reg(Rax));
+                }
+                Some(&var) => {
+                    self.compile_expr(cx, Loc::Mem(var.mem), rhs);
+                    self.move_to(dst, Arg32::Mem(var.mem));
+                }
+                None => {
+                    self.compile_expr(cx, Loc::Reg(Rax), rhs);
+                    let slot = self.global_slot(*x);
+                    self.move_to(Loc::Mem(slot), Arg64::Reg(Rax));
+                    self.move_to(dst, Arg64::Reg(Rax));
+                }
+            },
+            ExprKind::Block(es) => {
                 for e in &es[..es.len() - 1] {
                     self.compile_expr(cx, Loc::Reg(Rcx), e);
                 }
-                self.compile_expr(cx, dst, &es[es.len() - 1]);
+                self.compile_expr_at(cx, dst, &es[es.len() - 1], tail);
             }
-            Expr::Call(fun, args) => {
-                let Some(arity) = self.funs.get(fun) else {
-                    return raise_undefined_fun(*fun);
+            ExprKind::Call(fun, args) => {
+                // Calls to a top-level function that isn't shadowed don't need a closure.
+                let direct = match &fun.kind {
+                    ExprKind::Var(f)
+                        if !cx.env.contains_key(f) && !self.globals.contains_key(f) =>
+                    {
+                        Some(*f)
+                    }
+                    _ => None,
                 };
-                if args.len() != *arity {
-                    raise_wrong_number_of_args(*fun, *arity, args.len());
+
+                // The callee and arguments are evaluated into locals first, so the collector
+                // never sees a half-written argument area.
+                let (mut currcx, fun_mem) = cx.next_local();
+                if direct.is_none() {
+                    self.compile_expr(cx, Loc::Mem(fun_mem), fun);
+                }
+                let mut arg_mems = vec![];
+                for arg in args {
+                    let (nextcx, mem) = currcx.next_local();
+                    self.compile_expr(&currcx, Loc::Mem(mem), arg);
+                    arg_mems.push(mem);
+                    currcx = nextcx;
+                }
+                if direct.is_none() {
+                    self.emit_instr(Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(fun_mem))));
+                    self.check_is_closure(Rax, args.len());
+                }
+                // Arguments past a variadic function's fixed parameters are packed into a vector
+                // that takes the callee's slot, which a direct call doesn't otherwise use.
+                if let Some((required, true)) = direct.map(|f| self.funs[&f]) {
+                    if args.len() > required {
+                        self.alloc_vec(&arg_mems[required..]);
+                        self.move_to(Loc::Mem(fun_mem), Arg64::Reg(Rax));
+                    } else {
+                        self.move_to(Loc::Mem(fun_mem), Arg32::Imm(NIL));
+                    }
+                    arg_mems.truncate(required);
+                    arg_mems.push(fun_mem);
+                }
+                if let (true, Some(frame)) = (tail, cx.frame) {
+                    self.tail_call(frame, direct, &arg_mems);
+                    return;
                 }
 
-                let mut nargs = args.len() as i32;
-                if nargs % 2 == 0 {
-                    self.emit_instr(Instr::Sub(BinArgs::ToReg(Rsp, Arg32::Imm(8 * nargs))));
-                } else {
-                    self.emit_instrs([
-                        Instr::Push(Arg32::Imm(MEM_SET_VAL)),
-                        Instr::Sub(BinArgs::ToReg(Rsp, Arg32::Imm(8 * nargs))),
-                    ]);
-                    nargs += 1;
+                // The callee pops the argument area when it returns.
+                let slots = arg_slots(arg_mems.len());
+                self.emit_instr(Instr::Sub(BinArgs::ToReg(Rsp, Arg32::Imm(8 * slots))));
+                self.write_args(Rsp, 0, direct.is_some(), &arg_mems);
+                match direct {
+                    Some(f) => self.emit_instr(Instr::Call(fun_label(f))),
+                    None => self.emit_instr(Instr::CallIndirect(mref![Rax + %(CLOSURE_CODE)])),
                 }
-                for (i, arg) in args.iter().enumerate() {
-                    self.compile_expr(cx, Loc::Mem(mref![Rsp + %(8 * i)]), arg);
+                self.memset(cx.si, args.len() as u32 + 1, Reg32::Imm(MEM_SET_VAL));
+                self.move_to(dst, Arg64::Reg(Rax));
+            }
+            ExprKind::Lambda(params, body) => {
+                let mut free = vec![];
+                let bound = params.iter().map(|param| param.name).collect();
+                body.free_vars(&bound, &mut free);
+                let captures: Vec<_> = free
+                    .into_iter()
+                    .filter_map(|x| Some((x, *cx.env.get(&x)?)))
+                    .collect();
+
+                let label = format!("snek_lambda_{}", self.next_tag());
+                self.compile_lambda(&label, &captures, params, body);
+                let mems: Vec<_> = captures.iter().map(|(_, var)| var.mem).collect();
+                self.alloc_closure(&label, params.len(), &mems);
+                self.move_to(dst, Arg64::Reg(Rax));
+            }
+            ExprKind::Record(op, es) => {
+                let mut currcx = cx.clone();
+                let mut mems = vec![];
+                for e in es {
+                    let (nextcx, mem) = currcx.next_local();
+                    self.compile_expr(&currcx, Loc::Mem(mem), e);
+                    mems.push(mem);
+                    currcx = nextcx;
                 }
-                self.emit_instrs([
-                    Instr::Call(fun_label(*fun)),
-                    Instr::Add(BinArgs::ToReg(Rsp, Arg32::Imm(8 * nargs))),
-                ]);
+                self.compile_record_op(*op, &mems);
+                self.memset(cx.si, es.len() as u32, Reg32::Imm(MEM_SET_VAL));
                 self.move_to(dst, Arg64::Reg(Rax));
             }
-            Expr::Nil => {
-                self.move_to(dst, Arg32::Imm(NIL));
+            ExprKind::Dict(op, es) => {
+                let mut currcx = cx.clone();
+                let mut mems = vec![];
+                for e in es {
+                    let (nextcx, mem) = currcx.next_local();
+                    self.compile_expr(&currcx, Loc::Mem(mem), e);
+                    mems.push(mem);
+                    currcx = nextcx;
+                }
+                self.compile_dict_op(cx, *op, &mems);
+                self.memset(cx.si, es.len().max(1) as u32, Reg32::Imm(MEM_SET_VAL));
+                self.move_to(dst, Arg64::Reg(Rax));
             }
-            Expr::Input => {
-                if cx.in_fun {
-                    raise_input_in_fun()
-                } else {
-                    self.move_to(dst, Arg32::Reg(INPUT_REG))
+            ExprKind::VecOp(op, es) => {
+                let mut currcx = cx.clone();
+                let mut mems = vec![];
+                for e in es {
+                    let (nextcx, mem) = currcx.next_local();
+                    self.compile_expr(&currcx, Loc::Mem(mem), e);
+                    mems.push(mem);
+                    currcx = nextcx;
                 }
+                let types: Vec<_> = es.iter().map(|e| self.types.of(e)).collect();
+                self.compile_vec_op(*op, &mems, &types);
+                self.memset(cx.si, es.len() as u32, Reg32::Imm(MEM_SET_VAL));
+                self.move_to(dst, Arg64::Reg(Rax));
             }
-            Expr::MakeVec(size, elem) => {
-                let tag = self.next_tag();
-                let alloc_finish_lbl = format!("make_vec_alloc_finish_{tag}");
-
+            ExprKind::Io(op, es) => {
+                if let [e] = es.as_slice() {
+                    let (_, mem) = cx.next_local();
+                    self.compile_expr(cx, Loc::Mem(mem), e);
+                }
+                self.compile_io_op(cx, *op);
+                self.memset(cx.si, es.len() as u32, Reg32::Imm(MEM_SET_VAL));
+                self.move_to(dst, Arg64::Reg(Rax));
+            }
+            ExprKind::Nil => {
+                self.move_to(dst, Arg32::Imm(NIL));
+            }
+            ExprKind::Input => {
+                let slot = self.static_slot(INPUT.to_string());
+                self.move_to(dst, Arg32::Mem(slot));
+            }
+            ExprKind::Args => {
+                let slot = self.static_slot(ARGS.to_string());
+                self.move_to(dst, Arg32::Mem(slot));
+            }
+            ExprKind::MakeVec(size, elem) => {
                 let (nextcx, size_mem) = cx.next_local();
                 let (_, elem_mem) = nextcx.next_local();
 
                 self.compile_expr(cx, Loc::Mem(size_mem), size);
                 self.compile_expr(&nextcx, Loc::Mem(elem_mem), elem);
                 self.emit_instr(Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Mem(size_mem))));
-                self.check_is_num(Rdi);
+                self.check_fixnum(Rdi, size);
                 self.emit_instrs([
                     Instr::Sar(BinArgs::ToReg(Rdi, Arg32::Imm(1))),
                     Instr::Cmp(BinArgs::ToReg(Rdi, Arg32::Imm(0))),
                     Instr::Jl(INVALID_SIZE.to_string()),
-                    Instr::Lea(Rax, mref![HEAP_PTR + 8 * Rdi + 16]),
-                    Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Reg(HEAP_END))),
-                    Instr::Jle(alloc_finish_lbl.clone()),
-                    // Call try_gc to ensure we can allocate `size + 2` quad words
-                    // (1 extra for the size of the vector + 1 extra for the GC metadata)
-                    Instr::Add(BinArgs::ToReg(Rdi, Arg32::Imm(2))),
-                    Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(HEAP_PTR))),
-                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(STACK_BASE))),
-                    Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(Rbp))),
-                    Instr::Mov(MovArgs::ToReg(R8, Arg64::Reg(Rsp))),
-                    Instr::Call("snek_try_gc".to_string()),
-                    Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rax))),
-                    Instr::Label(alloc_finish_lbl),
+                ]);
+                self.reserve_vec(Rdi);
+                self.emit_instrs([
                     // Load size again in %rsi
                     Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Mem(size_mem))),
                     Instr::Sar(BinArgs::ToReg(Rsi, Arg32::Imm(1))),
@@ -341,55 +955,20 @@ impl Session {
                 self.memset(cx.si, 2, Reg32::Imm(MEM_SET_VAL));
                 self.move_to(dst, Arg64::Reg(Rax));
             }
-            Expr::Vec(elems) => {
-                let tag = self.next_tag();
-                let vec_alloc_finish_lbl = format!("vec_alloc_finish_{tag}");
-
-                let size: i32 = elems.len().try_into().unwrap();
+            ExprKind::Vec(elems) => {
                 let mut currcx = cx.clone();
+                let mut mems = vec![];
                 for elem in elems {
                     let (nextcx, mem) = currcx.next_local();
                     self.compile_expr(&currcx, Loc::Mem(mem), elem);
+                    mems.push(mem);
                     currcx = nextcx;
                 }
-
-                self.emit_instrs([
-                    Instr::Lea(Rax, mref![HEAP_PTR + %(8 * (size + 2))]),
-                    Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Reg(HEAP_END))),
-                    Instr::Jle(vec_alloc_finish_lbl.clone()),
-                    // Call try_gc to ensure we can allocate `size + 2` quad words
-                    // (1 extra for the size of the vector + 1 extra for the GC metadata)
-                    Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Imm(size as i64 + 2))),
-                    Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(HEAP_PTR))),
-                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(STACK_BASE))),
-                    Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(Rbp))),
-                    Instr::Mov(MovArgs::ToReg(R8, Arg64::Reg(Rsp))),
-                    Instr::Call("snek_try_gc".to_string()),
-                    Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rax))),
-                    Instr::Label(vec_alloc_finish_lbl),
-                    // Write GC word in HEAP_PTR
-                    Instr::Mov(MovArgs::ToMem(mref!(HEAP_PTR + 0), Reg32::Imm(GC_WORD_VAL))),
-                    // Write size in HEAP_PTR + 8
-                    Instr::Mov(MovArgs::ToMem(mref!(HEAP_PTR + 8), Reg32::Imm(size))),
-                ]);
-
-                for i in 0..elems.len() as u32 {
-                    self.move_to(
-                        Loc::Mem(mref!(HEAP_PTR + %(8 * (i + 2)))),
-                        Arg64::Mem(mref!(Rbp - %(8 * (cx.si + i + 1)))),
-                    )
-                }
-
-                self.emit_instrs([
-                    // Add tag to heap ptr and store it in %rax as the result of the expression
-                    Instr::Lea(Rax, mref!(HEAP_PTR + 1)),
-                    // Bump heap ptr
-                    Instr::Lea(HEAP_PTR, mref!(HEAP_PTR + %(8 * (size + 2)))),
-                ]);
+                self.alloc_vec(&mems);
                 self.memset(cx.si, elems.len() as u32, Reg32::Imm(MEM_SET_VAL));
                 self.move_to(dst, Arg64::Reg(Rax));
             }
-            Expr::VecSet(vec, idx, elem) => {
+            ExprKind::VecSet(vec, idx, elem) => {
                 let (nextcx1, vec_mem) = cx.next_local();
                 let (nextcx2, idx_mem) = nextcx1.next_local();
 
@@ -402,23 +981,24 @@ impl Session {
                     Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Mem(idx_mem))),
                 ]);
                 self.memset(cx.si, 2, Reg32::Imm(MEM_SET_VAL));
-                self.check_is_vec(Rax);
-                self.check_is_not_nil(Rax);
-                self.check_is_num(Rdi);
+                self.check_vec(Rax, self.types.of(vec));
+                self.check_fixnum(Rdi, idx);
                 self.emit_instrs([
                     Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(Rax))),
                     Instr::Sub(BinArgs::ToReg(Rcx, Arg32::Imm(1))),
                     Instr::Sar(BinArgs::ToReg(Rdi, Arg32::Imm(1))),
                     Instr::Cmp(BinArgs::ToReg(Rdi, Arg32::Imm(0))),
                     Instr::Jl(INDEX_OUT_OF_BOUNDS.to_string()),
-                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Mem(mref![Rcx + 8]))),
+                ]);
+                self.load_vec_len(Rdx, Rcx);
+                self.emit_instrs([
                     Instr::Cmp(BinArgs::ToReg(Rdi, Arg32::Reg(Rdx))),
                     Instr::Jge(INDEX_OUT_OF_BOUNDS.to_string()),
                     Instr::Mov(MovArgs::ToMem(mref![Rcx + 8 * Rdi + 16], Reg32::Reg(Rsi))),
                 ]);
                 self.move_to(dst, Arg64::Reg(Rax));
             }
-            Expr::VecGet(vec, idx) => {
+            ExprKind::VecGet(vec, idx) => {
                 let (nextcx, vec_mem) = cx.next_local();
 
                 self.compile_expr(cx, Loc::Mem(vec_mem), vec);
@@ -426,12 +1006,11 @@ impl Session {
 
                 self.emit_instrs([Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(vec_mem)))]);
                 self.memset(cx.si, 1, Reg32::Imm(MEM_SET_VAL));
-                self.check_is_vec(Rax);
-                self.check_is_not_nil(Rax);
-                self.check_is_num(Rdi);
+                self.check_vec(Rax, self.types.of(vec));
+                self.check_fixnum(Rdi, idx);
+                self.emit_instr(Instr::Sub(BinArgs::ToReg(Rax, Arg32::Imm(1))));
+                self.load_vec_len(Rdx, Rax);
                 self.emit_instrs([
-                    Instr::Sub(BinArgs::ToReg(Rax, Arg32::Imm(1))),
-                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Mem(mref![Rax + 8]))),
                     Instr::Sar(BinArgs::ToReg(Rdi, Arg32::Imm(1))),
                     Instr::Cmp(BinArgs::ToReg(Rdi, Arg32::Imm(0))),
                     Instr::Jl(INDEX_OUT_OF_BOUNDS.to_string()),
@@ -441,18 +1020,15 @@ impl Session {
                 ]);
                 self.move_to(dst, Arg64::Reg(Rax));
             }
-            Expr::VecLen(vec) => {
+            ExprKind::VecLen(vec) => {
                 self.compile_expr(cx, Loc::Reg(Rax), vec);
-                self.check_is_vec(Rax);
-                self.check_is_not_nil(Rax);
-                self.emit_instrs([
-                    Instr::Sub(BinArgs::ToReg(Rax, Arg32::Imm(1))),
-                    Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(mref![Rax + 8]))),
-                    Instr::Sal(BinArgs::ToReg(Rax, Arg32::Imm(1))),
-                ]);
+                self.check_vec(Rax, self.types.of(vec));
+                self.emit_instr(Instr::Sub(BinArgs::ToReg(Rax, Arg32::Imm(1))));
+                self.load_vec_len(Rax, Rax);
+                self.emit_instr(Instr::Sal(BinArgs::ToReg(Rax, Arg32::Imm(1))));
                 self.move_to(dst, Arg64::Reg(Rax));
             }
-            Expr::Gc => {
+            ExprKind::Gc => {
                 self.emit_instrs([
                     Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Reg(HEAP_PTR))),
                     Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(STACK_BASE))),
@@ -463,7 +1039,7 @@ impl Session {
                 ]);
                 self.move_to(dst, 0.repr32());
             }
-            Expr::PrintStack => {
+            ExprKind::PrintStack => {
                 self.emit_instrs([
                     Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Reg(STACK_BASE))),
                     Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(Rbp))),
@@ -472,7 +1048,7 @@ impl Session {
                 ]);
                 self.move_to(dst, 0.repr32());
             }
-            Expr::PrintHeap => {
+            ExprKind::PrintHeap => {
                 self.emit_instrs([
                     Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Reg(R15))),
                     Instr::Call("snek_print_heap".to_string()),
@@ -482,138 +1058,1070 @@ impl Session {
         }
     }
 
-    fn compile_un_op(&mut self, cx: &Ctxt, dst: Loc, op: Op1, e: &Expr) {
-        self.compile_expr(cx, Loc::Reg(Rax), e);
+    /// Leaves the result of `op` on the operands in the stack slots `args` in `%rax`.
+    fn compile_record_op(&mut self, op: RecordOp, args: &[MemRef]) {
         match op {
-            Op1::Add1 => {
-                self.check_is_num(Reg::Rax);
-                self.emit_instrs([
-                    Instr::Add(BinArgs::ToReg(Rax, 1.repr32())),
-                    Instr::Jo(OVERFLOW.to_string()),
-                ])
-            }
-            Op1::Sub1 => {
-                self.check_is_num(Reg::Rax);
-                self.emit_instrs([
-                    Instr::Sub(BinArgs::ToReg(Rax, 1.repr32())),
-                    Instr::Jo(OVERFLOW.to_string()),
-                ])
-            }
-            Op1::IsNum => {
-                self.emit_instrs([
-                    Instr::And(BinArgs::ToReg(Rax, Arg32::Imm(0b001))),
-                    Instr::Mov(MovArgs::ToReg(Rax, false.repr64())),
-                    Instr::Mov(MovArgs::ToReg(Rcx, true.repr64())),
-                    Instr::CMov(CMov::Z(Rax, Arg64::Reg(Rcx))),
-                ]);
+            RecordOp::New(id) => {
+                let len = args.len() as i32;
+                self.reserve(len + 2);
+                self.write_header(header(RECORD_KIND, len as i64) | (id as i64) << 32);
+                for (i, mem) in args.iter().enumerate() {
+                    self.move_to(Loc::Mem(mref!(HEAP_PTR + %(16 + 8 * i))), Arg64::Mem(*mem));
+                }
+                self.finish_alloc(OBJ_TAG, len + 2);
             }
-            Op1::IsBool => {
+            RecordOp::Is(id) => {
+                let end_lbl = format!("record_is_end_{}", self.next_tag());
                 self.emit_instrs([
-                    Instr::And(BinArgs::ToReg(Rax, Arg32::Imm(0b011))),
-                    Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Imm(0b011))),
+                    Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Mem(args[0]))),
                     Instr::Mov(MovArgs::ToReg(Rax, false.repr64())),
+                    Instr::Mov(MovArgs::ToReg(R11, Arg64::Reg(Rcx))),
+                    Instr::And(BinArgs::ToReg(R11, Arg32::Imm(0b111))),
+                    Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(OBJ_TAG))),
+                    Instr::Jne(end_lbl.clone()),
+                    Instr::Mov(MovArgs::ToReg(R11, Arg64::Mem(mref![Rcx + %(8 - OBJ_TAG)]))),
+                    Instr::Shr(BinArgs::ToReg(R11, Arg32::Imm(32))),
+                    Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(record_type(id)))),
                     Instr::Mov(MovArgs::ToReg(Rcx, true.repr64())),
                     Instr::CMov(CMov::E(Rax, Arg64::Reg(Rcx))),
+                    Instr::Label(end_lbl),
                 ]);
             }
-            Op1::IsVec => {
+            RecordOp::Get(id, i) => {
+                let field = mref![Rax + %(RECORD_FIELDS + 8 * i as i32)];
+                self.emit_instr(Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(args[0]))));
+                self.check_is_record(Rax, id);
+                self.emit_instr(Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(field))));
+            }
+            RecordOp::Set(id, i) => {
+                let field = mref![Rax + %(RECORD_FIELDS + 8 * i as i32)];
+                self.emit_instr(Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(args[0]))));
+                self.check_is_record(Rax, id);
                 self.emit_instrs([
-                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(Rax))),
-                    Instr::Mov(MovArgs::ToReg(Rax, true.repr64())),
-                    Instr::Mov(MovArgs::ToReg(Rcx, false.repr64())),
-                    Instr::Test(BinArgs::ToReg(Rdx, Arg32::Imm(0b01))),
-                    Instr::CMov(CMov::Z(Rax, Arg64::Reg(Rcx))),
-                    Instr::Test(BinArgs::ToReg(Rdx, Arg32::Imm(0b10))),
-                    Instr::CMov(CMov::NZ(Rax, Arg64::Reg(Rcx))),
+                    Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Mem(args[1]))),
+                    Instr::Mov(MovArgs::ToMem(field, Reg32::Reg(Rcx))),
                 ]);
             }
-            Op1::Print => self.emit_instrs([
-                Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Reg(Rax))),
-                Instr::Call("snek_print".to_string()),
-            ]),
         }
-        self.move_to(dst, Arg32::Reg(Rax));
     }
 
-    fn compile_bin_op(&mut self, cx: &Ctxt, dst: Loc, op: Op2, e1: &Expr, e2: &Expr) {
-        let (nextcx, mem) = cx.next_local();
-        self.compile_expr(cx, Loc::Mem(mem), e1);
-        self.compile_expr(&nextcx, Loc::Reg(Rcx), e2);
-        self.emit_instr(Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(mem))));
-        self.memset(cx.si, 1, Reg32::Imm(MEM_SET_VAL));
+    /// Calls the runtime function for a dictionary operation, passing it the address of the first
+    /// of the stack slots `args` holding its operands. It reads them from there, so it still finds
+    /// them if it has to collect garbage, and returns a null heap pointer if they aren't valid.
+    fn compile_dict_op(&mut self, cx: &Ctxt, op: DictOp, args: &[MemRef]) {
+        let first = mref![Rbp - %(8 * (cx.si + 1))];
+        if args.is_empty() {
+            // Without an operand, `make-dict` compares vector keys by identity.
+            self.move_to(Loc::Mem(first), false.repr64());
+        }
+        let fun = match op {
+            DictOp::New => "snek_make_dict",
+            DictOp::Set => "snek_dict_set",
+            DictOp::Get => "snek_dict_get",
+            DictOp::Has => "snek_dict_has",
+            DictOp::Remove => "snek_dict_remove",
+            DictOp::Keys => "snek_dict_keys",
+        };
+        self.call_with_operands(first, fun);
+        self.emit_instrs([
+            Instr::Test(BinArgs::ToReg(Rdx, Arg32::Reg(Rdx))),
+            Instr::Jz(INVALID_ARG.to_string()),
+            Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rdx))),
+        ]);
+    }
+
+    /// Calls the runtime function for a console operation, whose operand, if any, is in the stack
+    /// slot at `cx.si`. It may allocate what it reads from stdin, and returns a null heap pointer
+    /// when there is nothing to read or it can't be parsed.
+    fn compile_io_op(&mut self, cx: &Ctxt, op: IoOp) {
+        let first = mref![Rbp - %(8 * (cx.si + 1))];
+        let fun = match op {
+            IoOp::ReadInt => "snek_read_int",
+            IoOp::ReadValue => "snek_read_value",
+            IoOp::IsEof => "snek_eof",
+            IoOp::Display => "snek_display",
+            IoOp::Newline => "snek_newline",
+        };
+        self.call_with_operands(first, fun);
+        if let IoOp::ReadInt | IoOp::ReadValue = op {
+            self.emit_instrs([
+                Instr::Test(BinArgs::ToReg(Rdx, Arg32::Reg(Rdx))),
+                Instr::Jz(INVALID_INPUT.to_string()),
+            ]);
+        }
+        self.emit_instr(Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rdx))));
+    }
 
+    /// Calls the runtime function `fun` with the address of the stack slot `first` holding its
+    /// first operand and what it needs to collect garbage. It returns an `Allocated`: its value in
+    /// `%rax` and the new heap pointer in `%rdx`.
+    fn call_with_operands(&mut self, first: MemRef, fun: &str) {
+        self.emit_instrs([
+            Instr::Lea(Rdi, first),
+            Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(HEAP_PTR))),
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(STACK_BASE))),
+            Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(Rbp))),
+            Instr::Mov(MovArgs::ToReg(R8, Arg64::Reg(Rsp))),
+            Instr::Call(fun.to_string()),
+        ]);
+    }
+
+    /// Emits a vector operation on the values in the stack slots `args`, whose static types are
+    /// `types`. The operations that allocate read their operands from the slots again afterwards,
+    /// since the collector may have moved them.
+    fn compile_vec_op(&mut self, op: VecOp, args: &[MemRef], types: &[Type]) {
+        let tag = self.next_tag();
         match op {
-            Op2::Plus
-            | Op2::Minus
-            | Op2::Times
-            | Op2::Divide
-            | Op2::Greater
-            | Op2::GreaterEqual
-            | Op2::Less
-            | Op2::LessEqual => {
-                self.check_is_num(Rax);
-                self.check_is_num(Rcx);
-            }
-            Op2::Equal => {
-                let tag = self.next_tag();
-                let check_eq_finish_lbl = format!("check_eq_finish_{tag}");
-                // if (%rax ^ %rcx) & 0b11 == 0 {
-                //     jmp check_eq_finish
-                // } else if (%rax | %rcx) & 0b01 != 0 {
-                //     jmp invalid_arg
-                // }
+            VecOp::Push => {
+                // A full vector gets more slack from the runtime, which may move it.
+                let push_lbl = format!("vec_push_{tag}");
+                self.load_vec(Rax, args[0], types[0]);
                 self.emit_instrs([
-                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(Rax))),
-                    Instr::Xor(BinArgs::ToReg(Rdx, Arg32::Reg(Rcx))),
-                    Instr::Test(BinArgs::ToReg(Rdx, Arg32::Imm(0b11))),
-                    Instr::Jz(check_eq_finish_lbl.to_string()),
-                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(Rax))),
-                    Instr::Or(BinArgs::ToReg(Rdx, Arg32::Reg(Rcx))),
-                    Instr::Test(BinArgs::ToReg(Rdx, Arg32::Imm(0b01))),
-                    Instr::Jnz(INVALID_ARG.to_string()),
-                    Instr::Label(check_eq_finish_lbl.to_string()),
+                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Mem(mref![Rax + 8]))),
+                    Instr::Mov(MovArgs::ToReg(R11, Arg64::Reg(Rdx))),
+                    Instr::Shr(BinArgs::ToReg(R11, Arg32::Imm(32))),
+                    Instr::Jnz(push_lbl.clone()),
+                    Instr::Lea(Rdi, args[0]),
+                    Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(HEAP_PTR))),
+                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(STACK_BASE))),
+                    Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(Rbp))),
+                    Instr::Mov(MovArgs::ToReg(R8, Arg64::Reg(Rsp))),
+                    Instr::Call("snek_vec_grow".to_string()),
+                    Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rdx))),
+                    Instr::Sub(BinArgs::ToReg(Rax, Arg32::Imm(VEC_TAG))),
+                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Mem(mref![Rax + 8]))),
+                    Instr::Label(push_lbl),
+                ]);
+                self.load_vec_len(Rcx, Rax);
+                self.emit_instrs([
+                    Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Mem(args[1]))),
+                    Instr::Mov(MovArgs::ToMem(mref![Rax + 8 * Rcx + 16], Reg32::Reg(Rsi))),
+                    Instr::Add(BinArgs::ToReg(Rdx, Arg32::Imm(1))),
+                    Instr::Mov(MovArgs::ToReg(R11, Arg64::Imm(VEC_SLACK_ONE))),
+                    Instr::Sub(BinArgs::ToReg(Rdx, Arg32::Reg(R11))),
+                    Instr::Mov(MovArgs::ToMem(mref![Rax + 8], Reg32::Reg(Rdx))),
+                    Instr::Add(BinArgs::ToReg(Rax, Arg32::Imm(VEC_TAG))),
                 ]);
             }
-        }
-
-        match op {
-            Op2::Plus => {
+            VecOp::Pop => {
+                self.load_vec(Rax, args[0], types[0]);
+                self.load_vec_len(Rcx, Rax);
                 self.emit_instrs([
-                    Instr::Add(BinArgs::ToReg(Rax, Arg32::Reg(Rcx))),
-                    Instr::Jo(OVERFLOW.to_string()),
+                    Instr::Test(BinArgs::ToReg(Rcx, Arg32::Reg(Rcx))),
+                    Instr::Jz(INDEX_OUT_OF_BOUNDS.to_string()),
+                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Mem(mref![Rax + 8]))),
+                    Instr::Sub(BinArgs::ToReg(Rdx, Arg32::Imm(1))),
+                    Instr::Mov(MovArgs::ToReg(R11, Arg64::Imm(VEC_SLACK_ONE))),
+                    Instr::Add(BinArgs::ToReg(Rdx, Arg32::Reg(R11))),
+                    // Vectors are kind 0, so slack that no longer fits carries into the kind.
+                    Instr::Mov(MovArgs::ToReg(R11, Arg64::Reg(Rdx))),
+                    Instr::Shr(BinArgs::ToReg(R11, Arg32::Imm(56))),
+                    Instr::Jnz(OVERFLOW.to_string()),
+                    Instr::Mov(MovArgs::ToMem(mref![Rax + 8], Reg32::Reg(Rdx))),
+                    // The last element is at `16 + 8 * (len - 1)`.
+                    Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Mem(mref![Rax + 8 * Rcx + 8]))),
+                    Instr::Mov(MovArgs::ToMem(mref![Rax + 8 * Rcx + 8], Reg32::Imm(NIL))),
+                    Instr::Mov(MovArgs::ToReg(Rax, Arg64::Reg(Rsi))),
                 ]);
             }
-            Op2::Minus => {
+            VecOp::Slice => {
+                // 0 <= start <= end <= len
+                self.load_vec(Rax, args[0], types[0]);
+                self.load_vec_len(Rdx, Rax);
+                self.load_index(Rsi, args[1]);
+                self.load_index(Rdi, args[2]);
                 self.emit_instrs([
-                    Instr::Sub(BinArgs::ToReg(Rax, Arg32::Reg(Rcx))),
-                    Instr::Jo(OVERFLOW.to_string()),
+                    Instr::Cmp(BinArgs::ToReg(Rsi, Arg32::Imm(0))),
+                    Instr::Jl(INDEX_OUT_OF_BOUNDS.to_string()),
+                    Instr::Cmp(BinArgs::ToReg(Rsi, Arg32::Reg(Rdi))),
+                    Instr::Jg(INDEX_OUT_OF_BOUNDS.to_string()),
+                    Instr::Cmp(BinArgs::ToReg(Rdi, Arg32::Reg(Rdx))),
+                    Instr::Jg(INDEX_OUT_OF_BOUNDS.to_string()),
+                    Instr::Sub(BinArgs::ToReg(Rdi, Arg32::Reg(Rsi))),
                 ]);
+                self.reserve_vec(Rdi);
+                self.emit_instrs([
+                    Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(args[0]))),
+                    Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Mem(args[1]))),
+                    Instr::Sar(BinArgs::ToReg(Rsi, Arg32::Imm(1))),
+                    Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Mem(args[2]))),
+                    Instr::Sar(BinArgs::ToReg(Rcx, Arg32::Imm(1))),
+                    Instr::Sub(BinArgs::ToReg(Rcx, Arg32::Reg(Rsi))),
+                    Instr::Mov(MovArgs::ToMem(mref![HEAP_PTR + 0], Reg32::Imm(GC_WORD_VAL))),
+                    Instr::Mov(MovArgs::ToMem(mref![HEAP_PTR + 8], Reg32::Reg(Rcx))),
+                    Instr::Mov(MovArgs::ToReg(R9, Arg64::Reg(Rcx))),
+                    Instr::Lea(Rsi, mref![Rax + 8 * Rsi + 15]),
+                    Instr::Lea(Rdi, mref![HEAP_PTR + 16]),
+                    Instr::Rep(Movsq),
+                ]);
+                self.finish_vec(R9);
             }
-            Op2::Times => {
+            VecOp::Copy => {
+                // dst-start, src-start, n >= 0, dst-start + n <= len(dst), src-start + n <= len(src)
+                self.load_vec(Rax, args[0], types[0]);
+                self.load_vec(Rdx, args[2], types[2]);
+                self.load_index(Rdi, args[1]);
+                self.load_index(Rsi, args[3]);
+                self.load_index(Rcx, args[4]);
+                for (reg, vec) in [(Rdi, Rax), (Rsi, Rdx)] {
+                    self.load_vec_len(R8, vec);
+                    self.emit_instrs([
+                        Instr::Cmp(BinArgs::ToReg(reg, Arg32::Imm(0))),
+                        Instr::Jl(INDEX_OUT_OF_BOUNDS.to_string()),
+                        Instr::Mov(MovArgs::ToReg(R9, Arg64::Reg(reg))),
+                        Instr::Add(BinArgs::ToReg(R9, Arg32::Reg(Rcx))),
+                        Instr::Cmp(BinArgs::ToReg(R9, Arg32::Reg(R8))),
+                        Instr::Jg(INDEX_OUT_OF_BOUNDS.to_string()),
+                    ]);
+                }
+                // Copying within a vector towards its end has to go backwards, like `memmove`.
+                let forward_lbl = format!("vec_copy_forward_{tag}");
+                let finish_lbl = format!("vec_copy_finish_{tag}");
                 self.emit_instrs([
-                    Instr::Sar(BinArgs::ToReg(Rax, Arg32::Imm(1))),
-                    Instr::IMul(BinArgs::ToReg(Rax, Arg32::Reg(Rcx))),
-                    Instr::Jo(OVERFLOW.to_string()),
+                    Instr::Cmp(BinArgs::ToReg(Rcx, Arg32::Imm(0))),
+                    Instr::Jl(INDEX_OUT_OF_BOUNDS.to_string()),
+                    Instr::Lea(Rdi, mref![Rax + 8 * Rdi + 16]),
+                    Instr::Lea(Rsi, mref![Rdx + 8 * Rsi + 16]),
+                    Instr::Cmp(BinArgs::ToReg(Rdi, Arg32::Reg(Rsi))),
+                    Instr::Jle(forward_lbl.clone()),
+                    Instr::Lea(Rdi, mref![Rdi + 8 * Rcx + 0]),
+                    Instr::Sub(BinArgs::ToReg(Rdi, Arg32::Imm(8))),
+                    Instr::Lea(Rsi, mref![Rsi + 8 * Rcx + 0]),
+                    Instr::Sub(BinArgs::ToReg(Rsi, Arg32::Imm(8))),
+                    Instr::Std,
+                    Instr::Rep(Movsq),
+                    Instr::Cld,
+                    Instr::Jmp(finish_lbl.clone()),
+                    Instr::Label(forward_lbl),
+                    Instr::Rep(Movsq),
+                    Instr::Label(finish_lbl),
+                    Instr::Add(BinArgs::ToReg(Rax, Arg32::Imm(VEC_TAG))),
                 ]);
             }
-            Op2::Divide => {
+            VecOp::Append => {
+                self.load_vec(Rax, args[0], types[0]);
+                self.load_vec(Rcx, args[1], types[1]);
+                self.load_vec_len(Rdi, Rax);
+                self.load_vec_len(Rdx, Rcx);
+                self.emit_instr(Instr::Add(BinArgs::ToReg(Rdi, Arg32::Reg(Rdx))));
+                self.reserve_vec(Rdi);
                 self.emit_instrs([
-                    Instr::Cqo,
-                    Instr::IDiv(Rcx),
-                    Instr::Sal(BinArgs::ToReg(Rax, Arg32::Imm(1))),
-                    Instr::Jo(OVERFLOW.to_string()),
+                    Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(args[0]))),
+                    Instr::Sub(BinArgs::ToReg(Rax, Arg32::Imm(VEC_TAG))),
+                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Mem(args[1]))),
+                    Instr::Sub(BinArgs::ToReg(Rdx, Arg32::Imm(VEC_TAG))),
                 ]);
+                self.load_vec_len(Rcx, Rax);
+                self.load_vec_len(R8, Rdx);
+                self.emit_instrs([
+                    Instr::Lea(R9, mref![Rcx + 1 * R8 + 0]),
+                    Instr::Mov(MovArgs::ToMem(mref![HEAP_PTR + 0], Reg32::Imm(GC_WORD_VAL))),
+                    Instr::Mov(MovArgs::ToMem(mref![HEAP_PTR + 8], Reg32::Reg(R9))),
+                    Instr::Lea(Rdi, mref![HEAP_PTR + 16]),
+                    Instr::Lea(Rsi, mref![Rax + 16]),
+                    Instr::Rep(Movsq),
+                    Instr::Lea(Rsi, mref![Rdx + 16]),
+                    Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(R8))),
+                    Instr::Rep(Movsq),
+                ]);
+                self.finish_vec(R9);
             }
-            Op2::Equal => self.compile_cmp(CMov::E),
-            Op2::Greater => self.compile_cmp(CMov::G),
-            Op2::GreaterEqual => self.compile_cmp(CMov::GE),
-            Op2::Less => self.compile_cmp(CMov::L),
-            Op2::LessEqual => self.compile_cmp(CMov::LE),
         }
-        self.move_to(dst, Arg32::Reg(Rax));
     }
 
-    fn compile_cmp(&mut self, cmp: impl FnOnce(Reg, Arg64) -> CMov) {
+    /// Compiles a `match` into a decision tree that jumps to the code of the first arm whose pattern
+    /// matches, or raises a "no match" error carrying the value if none does.
+    fn compile_match(&mut self, cx: &Ctxt, dst: Loc, e: &Expr, arms: &[MatchArm], tail: bool) {
+        let tag = self.next_tag();
+        let fail_lbl = format!("match_fail_{tag}");
+        let end_lbl = format!("match_end_{tag}");
+        let arm_lbls: Vec<String> = (0..arms.len())
+            .map(|i| format!("match_arm_{tag}_{i}"))
+            .collect();
+
+        let (armcx, scrutinee) = cx.next_local();
+        self.compile_expr(cx, Loc::Mem(scrutinee), e);
+        let rows = arms
+            .iter()
+            .enumerate()
+            .map(|(i, arm)| MatchRow {
+                tests: vec![(vec![], &arm.pattern)],
+                arm: i,
+            })
+            .collect();
+        self.compile_decision_tree(scrutinee, rows, &arm_lbls, &fail_lbl);
+
+        self.emit_instr(Instr::Label(fail_lbl));
+        self.reserve(4);
+        self.write_header(header(ERROR_KIND, 2));
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToMem(
+                mref![HEAP_PTR + 16],
+                Reg32::Imm(NO_MATCH << 1),
+            )),
+            Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(scrutinee))),
+            Instr::Mov(MovArgs::ToMem(mref![HEAP_PTR + 24], Reg32::Reg(Rax))),
+        ]);
+        self.finish_alloc(OBJ_TAG, 4);
+        self.emit_instr(Instr::Jmp(RAISE.to_string()));
+
+        for (arm, lbl) in arms.iter().zip(arm_lbls) {
+            self.emit_instr(Instr::Label(lbl));
+            let mut currcx = armcx.clone();
+            for (binding, path) in arm.pattern.bindings() {
+                let (nextcx, mem) = currcx.next_local();
+                self.load_path(Rax, scrutinee, &path);
+                self.move_to(Loc::Mem(mem), Arg64::Reg(Rax));
+                let boxed = needs_box(binding.name, [&arm.body]);
+                if boxed {
+                    self.box_slot(mem);
+                }
+                currcx = nextcx.add_binding(binding.name, VarLoc { mem, boxed });
+            }
+            self.compile_expr_at(&currcx, Loc::Reg(Rax), &arm.body, tail);
+            self.emit_instr(Instr::Jmp(end_lbl.clone()));
+        }
+        self.emit_instr(Instr::Label(end_lbl));
+        let slots = arms.iter().map(|arm| arm.pattern.bindings().len()).max();
+        self.memset(
+            cx.si,
+            1 + slots.unwrap_or(0) as u32,
+            Reg32::Imm(MEM_SET_VAL),
+        );
+        self.move_to(dst, Arg64::Reg(Rax));
+    }
+
+    /// Emits the tests that pick the arm for the scrutinee of a `match` from `rows`, the arms that
+    /// can still match, in order. Each test looks at a value that the first row's pattern
+    /// constrains, and splits the rows by what they need there.
+    fn compile_decision_tree(
+        &mut self,
+        scrutinee: MemRef,
+        rows: Vec<MatchRow>,
+        arm_lbls: &[String],
+        fail_lbl: &str,
+    ) {
+        let Some(first) = rows.first() else {
+            self.emit_instr(Instr::Jmp(fail_lbl.to_string()));
+            return;
+        };
+        let Some((path, _)) = first
+            .tests
+            .iter()
+            .find(|(_, pat)| pattern_head(pat).is_some())
+        else {
+            self.emit_instr(Instr::Jmp(arm_lbls[first.arm].clone()));
+            return;
+        };
+        let path = path.clone();
+
+        // Every head some row needs at `path`, in order. Rows with a pattern there that matches
+        // anything go down every branch.
+        let mut heads = vec![];
+        for (p, pat) in rows.iter().flat_map(|row| &row.tests) {
+            match pattern_head(pat) {
+                Some(head) if *p == path && !heads.contains(&head) => heads.push(head),
+                _ => {}
+            }
+        }
+        for head in heads {
+            let next_lbl = format!("match_next_{}", self.next_tag());
+            self.load_path(Rax, scrutinee, &path);
+            self.test_pattern_head(Rax, head, &next_lbl);
+            let rows = rows
+                .iter()
+                .filter_map(|row| row.specialize(&path, Some(head)))
+                .collect();
+            self.compile_decision_tree(scrutinee, rows, arm_lbls, fail_lbl);
+            self.emit_instr(Instr::Label(next_lbl));
+        }
+        let rows = rows
+            .iter()
+            .filter_map(|row| row.specialize(&path, None))
+            .collect();
+        self.compile_decision_tree(scrutinee, rows, arm_lbls, fail_lbl);
+    }
+
+    /// Loads the value at `path` in the scrutinee of a `match` into `reg`. The vectors along the
+    /// way must have been checked already.
+    fn load_path(&mut self, reg: Reg, scrutinee: MemRef, path: &[usize]) {
+        self.emit_instr(Instr::Mov(MovArgs::ToReg(reg, Arg64::Mem(scrutinee))));
+        for i in path {
+            let elem = 16 + 8 * *i as i32 - VEC_TAG;
+            self.emit_instr(Instr::Mov(MovArgs::ToReg(
+                reg,
+                Arg64::Mem(mref![reg + %(elem)]),
+            )));
+        }
+    }
+
+    /// Jumps to `fail_lbl` unless the value in `reg` has `head`. Clobbers `reg`.
+    fn test_pattern_head(&mut self, reg: Reg, head: PatternHead, fail_lbl: &str) {
+        let fail = || Instr::Jne(fail_lbl.to_string());
+        match head {
+            PatternHead::Number(n) => match i32::try_from(n << 1) {
+                Ok(imm) => {
+                    self.emit_instrs([Instr::Cmp(BinArgs::ToReg(reg, Arg32::Imm(imm))), fail()])
+                }
+                Err(_) => self.emit_instrs([
+                    Instr::Mov(MovArgs::ToReg(R11, n.repr64())),
+                    Instr::Cmp(BinArgs::ToReg(reg, Arg32::Reg(R11))),
+                    fail(),
+                ]),
+            },
+            PatternHead::Boolean(b) => {
+                self.emit_instrs([Instr::Cmp(BinArgs::ToReg(reg, b.repr32())), fail()])
+            }
+            PatternHead::Nil => {
+                self.emit_instrs([Instr::Cmp(BinArgs::ToReg(reg, Arg32::Imm(NIL))), fail()])
+            }
+            PatternHead::Vec(len) => {
+                self.emit_instrs([
+                    Instr::Mov(MovArgs::ToReg(R11, Arg64::Reg(reg))),
+                    Instr::And(BinArgs::ToReg(R11, Arg32::Imm(0b111))),
+                    Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(VEC_TAG))),
+                    fail(),
+                    Instr::Cmp(BinArgs::ToReg(reg, Arg32::Imm(NIL))),
+                    Instr::Je(fail_lbl.to_string()),
+                    Instr::Sub(BinArgs::ToReg(reg, Arg32::Imm(VEC_TAG))),
+                ]);
+                self.load_vec_len(R11, reg);
+                self.emit_instrs([
+                    Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(len as i32))),
+                    fail(),
+                ]);
+            }
+        }
+    }
+
+    /// Loads the vector in `mem`, whose static type is `ty`, into `reg` without its tag, checking
+    /// that it is one.
+    fn load_vec(&mut self, reg: Reg, mem: MemRef, ty: Type) {
+        self.emit_instr(Instr::Mov(MovArgs::ToReg(reg, Arg64::Mem(mem))));
+        self.check_vec(reg, ty);
+        self.emit_instr(Instr::Sub(BinArgs::ToReg(reg, Arg32::Imm(VEC_TAG))));
+    }
+
+    /// Loads the length of the vector `vec` points to, without its tag, into `dst`.
+    fn load_vec_len(&mut self, dst: Reg, vec: Reg) {
+        // Shifting the slack out of the header leaves its low 32 bits.
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(dst, Arg64::Mem(mref![vec + 8]))),
+            Instr::Sal(BinArgs::ToReg(dst, Arg32::Imm(32))),
+            Instr::Shr(BinArgs::ToReg(dst, Arg32::Imm(32))),
+        ]);
+    }
+
+    /// Loads the number in `mem` into `reg` as a machine integer, checking that it is one.
+    fn load_index(&mut self, reg: Reg, mem: MemRef) {
+        self.emit_instr(Instr::Mov(MovArgs::ToReg(reg, Arg64::Mem(mem))));
+        self.check_is_num(reg);
+        self.emit_instr(Instr::Sar(BinArgs::ToReg(reg, Arg32::Imm(1))));
+    }
+
+    /// Leaves a pointer to the vector of `len` elements just written at `HEAP_PTR` in `%rax` and
+    /// bumps the heap pointer past it.
+    fn finish_vec(&mut self, len: Reg) {
+        self.emit_instrs([
+            Instr::Lea(Rax, mref![HEAP_PTR + 1]),
+            Instr::Lea(HEAP_PTR, mref![HEAP_PTR + 8 * len + 16]),
+        ]);
+    }
+
+    /// Fills the argument area at `[reg + offset]`: the closure in `%rax` (or nil for a `direct`
+    /// call) goes first, followed by `args` and, if needed, a padding slot.
+    fn write_args(&mut self, reg: Reg, offset: i32, direct: bool, args: &[MemRef]) {
+        let slot = |i: usize| mref![reg + %(offset + 8 * i as i32)];
+        match direct {
+            true => self.move_to(Loc::Mem(slot(0)), Arg32::Imm(NIL)),
+            false => self.move_to(Loc::Mem(slot(0)), Arg64::Reg(Rax)),
+        }
+        for (i, mem) in args.iter().enumerate() {
+            self.move_to(Loc::Mem(slot(i + 1)), Arg64::Mem(*mem));
+        }
+        if arg_slots(args.len()) as usize > args.len() + 1 {
+            self.move_to(Loc::Mem(slot(args.len() + 1)), Arg32::Imm(NIL));
+        }
+    }
+
+    /// Replaces the current frame with a call to `direct` (or the closure in `%rax`), passing
+    /// `args`. The new argument area ends where the current one does, however their sizes differ,
+    /// so the caller's frame is left intact and the stack looks just as if the caller had made the
+    /// call itself. Since callees pop their own arguments, the caller never has to know.
+    fn tail_call(&mut self, frame: Frame, direct: Option<Symbol>, args: &[MemRef]) {
+        let slots = arg_slots(args.len());
+        let base = 16 + 8 * (frame.arg_slots - slots);
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(R10, Arg64::Mem(mref![Rbp + 8]))),
+            Instr::Mov(MovArgs::ToReg(R9, Arg64::Mem(mref![Rbp + 0]))),
+        ]);
+        if slots > frame.arg_slots {
+            // A bigger area can reach down into the locals holding the arguments, so they are
+            // staged below both first.
+            let stage = base.min(-8 * frame.size) - 8 * slots;
+            self.emit_instr(Instr::Lea(Rsp, mref![Rbp + %(stage)]));
+            self.write_args(Rsp, 0, direct.is_some(), args);
+            for i in 0..slots {
+                self.move_to(
+                    Loc::Mem(mref![Rbp + %(base + 8 * i)]),
+                    Arg64::Mem(mref![Rsp + %(8 * i)]),
+                );
+            }
+        } else {
+            self.write_args(Rbp, base, direct.is_some(), args);
+        }
+        self.emit_instrs([
+            Instr::Lea(Rsp, mref![Rbp + %(base - 8)]),
+            Instr::Mov(MovArgs::ToMem(mref![Rsp + 0], Reg32::Reg(R10))),
+            Instr::Mov(MovArgs::ToReg(Rbp, Arg64::Reg(R9))),
+        ]);
+        match direct {
+            Some(f) => self.emit_instr(Instr::Jmp(fun_label(f))),
+            None => self.emit_instr(Instr::JmpIndirect(mref![Rax + %(CLOSURE_CODE)])),
+        }
+    }
+
+    fn compile_un_op(&mut self, cx: &Ctxt, dst: Loc, op: Op1, e: &Expr) {
+        self.compile_expr(cx, Loc::Reg(Rax), e);
+        let operands = Operands::of(self.types.of(e), Type::Int);
+        match op {
+            Op1::Add1 => {
+                self.emit_instr(Instr::Mov(MovArgs::ToReg(Rcx, 1.repr64())));
+                self.compile_arith("snek_add", Instr::Addsd, operands, |slow| {
+                    vec![
+                        Instr::Add(BinArgs::ToReg(Rax, Arg32::Reg(Rcx))),
+                        Instr::Jo(slow.to_string()),
+                    ]
+                });
+            }
+            Op1::Sub1 => {
+                self.emit_instr(Instr::Mov(MovArgs::ToReg(Rcx, 1.repr64())));
+                self.compile_arith("snek_sub", Instr::Subsd, operands, |slow| {
+                    vec![
+                        Instr::Sub(BinArgs::ToReg(Rax, Arg32::Reg(Rcx))),
+                        Instr::Jo(slow.to_string()),
+                    ]
+                });
+            }
+            Op1::IsFloat => {
+                self.load_kind(Rax, Rcx);
+                self.emit_instrs([
+                    Instr::Cmp(BinArgs::ToReg(Rcx, Arg32::Imm(FLOAT_KIND as i32))),
+                    Instr::Mov(MovArgs::ToReg(Rax, false.repr64())),
+                    Instr::Mov(MovArgs::ToReg(Rcx, true.repr64())),
+                    Instr::CMov(CMov::E(Rax, Arg64::Reg(Rcx))),
+                ]);
+            }
+            Op1::IsNum => {
+                // A bignum counts as a number: turn it into 0 so it passes the fixnum test below.
+                let is_num_finish_lbl = format!("is_num_finish_{}", self.next_tag());
+                self.emit_instrs([
+                    Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(Rax))),
+                    Instr::And(BinArgs::ToReg(Rcx, Arg32::Imm(0b111))),
+                    Instr::Cmp(BinArgs::ToReg(Rcx, Arg32::Imm(OBJ_TAG))),
+                    Instr::Jne(is_num_finish_lbl.clone()),
+                    Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Mem(mref![Rax + %(8 - OBJ_TAG)]))),
+                    Instr::Shr(BinArgs::ToReg(Rcx, Arg32::Imm(56))),
+                    Instr::Cmp(BinArgs::ToReg(Rcx, Arg32::Imm(BIGNUM_KIND as i32))),
+                    Instr::Jne(is_num_finish_lbl.clone()),
+                    Instr::Mov(MovArgs::ToReg(Rax, Arg64::Imm(0))),
+                    Instr::Label(is_num_finish_lbl),
+                    Instr::And(BinArgs::ToReg(Rax, Arg32::Imm(0b001))),
+                    Instr::Mov(MovArgs::ToReg(Rax, false.repr64())),
+                    Instr::Mov(MovArgs::ToReg(Rcx, true.repr64())),
+                    Instr::CMov(CMov::Z(Rax, Arg64::Reg(Rcx))),
+                ]);
+            }
+            Op1::IsBool => {
+                self.emit_instrs([
+                    Instr::And(BinArgs::ToReg(Rax, Arg32::Imm(0b011))),
+                    Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Imm(0b011))),
+                    Instr::Mov(MovArgs::ToReg(Rax, false.repr64())),
+                    Instr::Mov(MovArgs::ToReg(Rcx, true.repr64())),
+                    Instr::CMov(CMov::E(Rax, Arg64::Reg(Rcx))),
+                ]);
+            }
+            Op1::IsVec => {
+                self.emit_instrs([
+                    Instr::And(BinArgs::ToReg(Rax, Arg32::Imm(0b111))),
+                    Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Imm(VEC_TAG))),
+                    Instr::Mov(MovArgs::ToReg(Rax, false.repr64())),
+                    Instr::Mov(MovArgs::ToReg(Rcx, true.repr64())),
+                    Instr::CMov(CMov::E(Rax, Arg64::Reg(Rcx))),
+                ]);
+            }
+            Op1::ErrorCode => {
+                self.load_kind(Rax, Rcx);
+                self.emit_instrs([
+                    Instr::Cmp(BinArgs::ToReg(Rcx, Arg32::Imm(ERROR_KIND as i32))),
+                    Instr::Jne(INVALID_ARG.to_string()),
+                    Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(mref![Rax + %(ERROR_CODE)]))),
+                ]);
+            }
+            Op1::Print => self.emit_instrs([
+                Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Reg(Rax))),
+                Instr::Call("snek_print".to_string()),
+            ]),
+            Op1::BitNot => {
+                // Inverting a fixnum sets its tag bit, which has to be cleared again.
+                let fixnum = self.types.is_fixnum(e);
+                self.emit_instr(Instr::Mov(MovArgs::ToReg(Rcx, 0.repr64())));
+                self.compile_int_arith("snek_bit_not", operands, fixnum, |_| {
+                    vec![
+                        Instr::Not(Loc::Reg(Rax)),
+                        Instr::Xor(BinArgs::ToReg(Rax, Arg32::Imm(1))),
+                    ]
+                });
+            }
+            Op1::Abs => {
+                let fixnum = self.types.is_fixnum(e);
+                self.emit_instr(Instr::Mov(MovArgs::ToReg(Rcx, 0.repr64())));
+                self.compile_int_arith("snek_abs", operands, fixnum, |slow| {
+                    vec![
+                        Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(Rax))),
+                        Instr::Neg(Loc::Reg(Rcx)),
+                        Instr::Jo(slow.to_string()),
+                        Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Imm(0))),
+                        Instr::CMov(CMov::L(Rax, Arg64::Reg(Rcx))),
+                    ]
+                });
+            }
+        }
+        self.move_to(dst, Arg32::Reg(Rax));
+    }
+
+    fn compile_bin_op(&mut self, cx: &Ctxt, dst: Loc, op: Op2, e1: &Expr, e2: &Expr) {
+        let (nextcx, mem) = cx.next_local();
+        self.compile_expr(cx, Loc::Mem(mem), e1);
+        self.compile_expr(&nextcx, Loc::Reg(Rcx), e2);
+        self.emit_instr(Instr::Mov(MovArgs::ToReg(Rax, Arg64::Mem(mem))));
+        self.memset(cx.si, 1, Reg32::Imm(MEM_SET_VAL));
+
+        let operands = Operands::of(self.types.of(e1), self.types.of(e2));
+        match op {
+            Op2::Plus => self.compile_arith("snek_add", Instr::Addsd, operands, |slow| {
+                vec![
+                    Instr::Add(BinArgs::ToReg(Rax, Arg32::Reg(Rcx))),
+                    Instr::Jo(slow.to_string()),
+                ]
+            }),
+            Op2::Minus => self.compile_arith("snek_sub", Instr::Subsd, operands, |slow| {
+                vec![
+                    Instr::Sub(BinArgs::ToReg(Rax, Arg32::Reg(Rcx))),
+                    Instr::Jo(slow.to_string()),
+                ]
+            }),
+            Op2::Times => self.compile_arith("snek_mul", Instr::Mulsd, operands, |slow| {
+                vec![
+                    Instr::Sar(BinArgs::ToReg(Rax, Arg32::Imm(1))),
+                    Instr::IMul(BinArgs::ToReg(Rax, Arg32::Reg(Rcx))),
+                    Instr::Jo(slow.to_string()),
+                ]
+            }),
+            Op2::Divide => {
+                // Only a fixnum can be zero, and a bignum divided by it must raise here too.
+                self.emit_instrs([
+                    Instr::Test(BinArgs::ToReg(Rcx, Arg32::Reg(Rcx))),
+                    Instr::Jz(DIVIDE_BY_ZERO.to_string()),
+                ]);
+                self.compile_arith("snek_div", Instr::Divsd, operands, |slow| {
+                    vec![
+                        Instr::Cqo,
+                        Instr::IDiv(Rcx),
+                        Instr::Sal(BinArgs::ToReg(Rax, Arg32::Imm(1))),
+                        Instr::Jo(slow.to_string()),
+                    ]
+                });
+            }
+            Op2::Equal => self.compile_equal(),
+            Op2::StructEqual => self.emit_instrs([
+                Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Reg(Rax))),
+                Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(Rcx))),
+                Instr::Call("snek_equal".to_string()),
+            ]),
+            Op2::Greater | Op2::GreaterEqual | Op2::Less | Op2::LessEqual => {
+                self.compile_num_cmp(op, operands)
+            }
+            Op2::Mod
+            | Op2::Remainder
+            | Op2::BitAnd
+            | Op2::BitOr
+            | Op2::BitXor
+            | Op2::ArithShift
+            | Op2::Min
+            | Op2::Max => {
+                let fixnums = self.types.is_fixnum(e1) && self.types.is_fixnum(e2);
+                self.compile_int_op(op, operands, fixnums);
+            }
+        }
+        self.move_to(dst, Arg32::Reg(Rax));
+    }
+
+    /// Emits an arithmetic operation on `%rax` and `%rcx`. When both are fixnums, `fast` computes
+    /// the result in `%rax`, jumping to the label it's given on overflow. When one is a float and
+    /// the other a float or fixnum, the fixnum is converted and `float_op` computes a new float.
+    /// Anything else goes to the runtime function `slow_fn`, which allocates a bignum if the result
+    /// doesn't fit in a fixnum. What the type checker knows about the `operands` skips the paths
+    /// and checks that can't be needed.
+    fn compile_arith(
+        &mut self,
+        slow_fn: &str,
+        float_op: fn(Xmm, Xmm) -> Instr,
+        operands: Operands,
+        fast: impl FnOnce(&str) -> Vec<Instr>,
+    ) {
+        let tag = self.next_tag();
+        let float_lbl = format!("arith_float_{tag}");
+        let slow_lbl = format!("arith_slow_{tag}");
+        let finish_lbl = format!("arith_finish_{tag}");
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Reg(Rax))),
+            Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(Rcx))),
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(Rax))),
+            Instr::Or(BinArgs::ToReg(Rdx, Arg32::Reg(Rcx))),
+            Instr::Test(BinArgs::ToReg(Rdx, Arg32::Imm(0b001))),
+        ]);
+        if operands == Operands::Ints {
+            // Two ints that aren't both fixnums involve a bignum.
+            self.emit_instr(Instr::Jnz(slow_lbl.clone()));
+            self.emit_instrs(fast(&slow_lbl));
+            self.emit_instr(Instr::Jmp(finish_lbl.clone()));
+        } else {
+            self.emit_instr(Instr::Jnz(float_lbl.clone()));
+            self.emit_instrs(fast(&slow_lbl));
+            self.emit_instrs([Instr::Jmp(finish_lbl.clone()), Instr::Label(float_lbl)]);
+            self.load_float(Rax, Xmm0, &slow_lbl);
+            self.load_float(Rcx, Xmm1, &slow_lbl);
+            self.emit_instr(float_op(Xmm0, Xmm1));
+            self.alloc_float(&finish_lbl);
+        }
+        self.emit_instr(Instr::Label(slow_lbl));
+        if operands == Operands::Unknown {
+            self.check_is_number(Rdi);
+            self.check_is_number(Rsi);
+        }
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(HEAP_PTR))),
+            Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(STACK_BASE))),
+            Instr::Mov(MovArgs::ToReg(R8, Arg64::Reg(Rbp))),
+            Instr::Mov(MovArgs::ToReg(R9, Arg64::Reg(Rsp))),
+            Instr::Call(slow_fn.to_string()),
+            Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rdx))),
+            Instr::Label(finish_lbl),
+        ]);
+    }
+
+    /// Emits an integer operation on `%rax` and `%rcx`, like [`Session::compile_arith`] without
+    /// the float path. When both are fixnums, `fast` computes the result in `%rax`, jumping to the
+    /// label it's given if it doesn't fit in one. Anything else goes to the runtime function
+    /// `slow_fn`, which works on bignums and returns a null heap pointer if the result is too big
+    /// to allocate. When the type checker knows both are `fixnums`, their tags aren't tested.
+    fn compile_int_arith(
+        &mut self,
+        slow_fn: &str,
+        operands: Operands,
+        fixnums: bool,
+        fast: impl FnOnce(&str) -> Vec<Instr>,
+    ) {
+        let tag = self.next_tag();
+        let slow_lbl = format!("int_slow_{tag}");
+        let finish_lbl = format!("int_finish_{tag}");
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Reg(Rax))),
+            Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(Rcx))),
+        ]);
+        if !fixnums {
+            self.emit_instrs([
+                Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(Rax))),
+                Instr::Or(BinArgs::ToReg(Rdx, Arg32::Reg(Rcx))),
+                Instr::Test(BinArgs::ToReg(Rdx, Arg32::Imm(0b001))),
+                Instr::Jnz(slow_lbl.clone()),
+            ]);
+        }
+        self.emit_instrs(fast(&slow_lbl));
+        self.emit_instrs([Instr::Jmp(finish_lbl.clone()), Instr::Label(slow_lbl)]);
+        if operands != Operands::Ints {
+            self.check_is_int(Rdi);
+            self.check_is_int(Rsi);
+        }
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(HEAP_PTR))),
+            Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(STACK_BASE))),
+            Instr::Mov(MovArgs::ToReg(R8, Arg64::Reg(Rbp))),
+            Instr::Mov(MovArgs::ToReg(R9, Arg64::Reg(Rsp))),
+            Instr::Call(slow_fn.to_string()),
+            Instr::Test(BinArgs::ToReg(Rdx, Arg32::Reg(Rdx))),
+            Instr::Jz(OVERFLOW.to_string()),
+            Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rdx))),
+            Instr::Label(finish_lbl),
+        ]);
+    }
+
+    /// Emits an operation on the integers in `%rax` and `%rcx` with
+    /// [`Session::compile_int_arith`]. A fixnum is tagged as twice its value, so remainders,
+    /// bitwise operations, `min` and `max` work on the tagged values as is.
+    fn compile_int_op(&mut self, op: Op2, operands: Operands, fixnums: bool) {
+        let tag = self.next_tag();
+        if let Op2::Mod | Op2::Remainder = op {
+            // Only fixnum 0 is zero; bignums never are.
+            self.emit_instrs([
+                Instr::Test(BinArgs::ToReg(Rcx, Arg32::Reg(Rcx))),
+                Instr::Jz(DIVIDE_BY_ZERO.to_string()),
+            ]);
+        }
+        let slow_fn = match op {
+            Op2::Mod => "snek_mod",
+            Op2::Remainder => "snek_remainder",
+            Op2::BitAnd => "snek_bit_and",
+            Op2::BitOr => "snek_bit_or",
+            Op2::BitXor => "snek_bit_xor",
+            Op2::ArithShift => "snek_shift",
+            Op2::Min => "snek_min",
+            Op2::Max => "snek_max",
+            _ => unreachable!("not an integer operation"),
+        };
+        self.compile_int_arith(slow_fn, operands, fixnums, |slow| match op {
+            Op2::Remainder => vec![
+                Instr::Cqo,
+                Instr::IDiv(Rcx),
+                Instr::Mov(MovArgs::ToReg(Rax, Arg64::Reg(Rdx))),
+            ],
+            Op2::Mod => {
+                // A nonzero remainder whose sign differs from the divisor's gets the divisor added.
+                let mod_finish_lbl = format!("mod_finish_{tag}");
+                vec![
+                    Instr::Cqo,
+                    Instr::IDiv(Rcx),
+                    Instr::Mov(MovArgs::ToReg(Rax, Arg64::Reg(Rdx))),
+                    Instr::Test(BinArgs::ToReg(Rax, Arg32::Reg(Rax))),
+                    Instr::Jz(mod_finish_lbl.clone()),
+                    Instr::Xor(BinArgs::ToReg(Rdx, Arg32::Reg(Rcx))),
+                    Instr::Sar(BinArgs::ToReg(Rdx, Arg32::Imm(63))),
+                    Instr::And(BinArgs::ToReg(Rdx, Arg32::Reg(Rcx))),
+                    Instr::Add(BinArgs::ToReg(Rax, Arg32::Reg(Rdx))),
+                    Instr::Label(mod_finish_lbl),
+                ]
+            }
+            Op2::BitAnd => vec![Instr::And(BinArgs::ToReg(Rax, Arg32::Reg(Rcx)))],
+            Op2::BitOr => vec![Instr::Or(BinArgs::ToReg(Rax, Arg32::Reg(Rcx)))],
+            Op2::BitXor => vec![Instr::Xor(BinArgs::ToReg(Rax, Arg32::Reg(Rcx)))],
+            Op2::Min => vec![
+                Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Reg(Rcx))),
+                Instr::CMov(CMov::G(Rax, Arg64::Reg(Rcx))),
+            ],
+            Op2::Max => vec![
+                Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Reg(Rcx))),
+                Instr::CMov(CMov::L(Rax, Arg64::Reg(Rcx))),
+            ],
+            Op2::ArithShift => {
+                // Shift counts are clamped to 63, which overflows any nonzero value shifted left
+                // and leaves 0 or -1 when shifting right. A left shift overflowed if shifting back
+                // doesn't give the original value.
+                let right_lbl = format!("shift_right_{tag}");
+                let shift_finish_lbl = format!("shift_finish_{tag}");
+                let clamp = [
+                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Imm(63))),
+                    Instr::Cmp(BinArgs::ToReg(Rcx, Arg32::Reg(Rdx))),
+                    Instr::CMov(CMov::G(Rcx, Arg64::Reg(Rdx))),
+                ];
+                let mut instrs = vec![
+                    Instr::Sar(BinArgs::ToReg(Rcx, Arg32::Imm(1))),
+                    Instr::Cmp(BinArgs::ToReg(Rcx, Arg32::Imm(0))),
+                    Instr::Jl(right_lbl.clone()),
+                ];
+                instrs.extend(clamp.clone());
+                instrs.extend([
+                    Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(Rax))),
+                    Instr::SalCl(Rax),
+                    Instr::Mov(MovArgs::ToReg(R11, Arg64::Reg(Rax))),
+                    Instr::SarCl(R11),
+                    Instr::Cmp(BinArgs::ToReg(R11, Arg32::Reg(Rdx))),
+                    Instr::Jne(slow.to_string()),
+                    Instr::Jmp(shift_finish_lbl.clone()),
+                    Instr::Label(right_lbl),
+                    Instr::Neg(Loc::Reg(Rcx)),
+                ]);
+                instrs.extend(clamp);
+                instrs.extend([
+                    Instr::SarCl(Rax),
+                    Instr::And(BinArgs::ToReg(Rax, Arg32::Imm(-2))),
+                    Instr::Label(shift_finish_lbl),
+                ]);
+                instrs
+            }
+            _ => unreachable!("not an integer operation"),
+        });
+    }
+
+    /// Emits an ordering comparison of the numbers in `%rax` and `%rcx`, with the same three paths
+    /// as [`Session::compile_arith`]. Floats are compared with `ucomisd`, which sets the flags like
+    /// an unsigned comparison; `<` and `<=` swap the operands so that NaN compares false.
+    fn compile_num_cmp(&mut self, op: Op2, operands: Operands) {
+        // The conditional move that sets the result for fixnums, and the one for floats.
+        type Cond = fn(Reg, Arg64) -> CMov;
+        let (cmp, float_cmp, swap, code): (Cond, Cond, _, _) = match op {
+            Op2::Less => (CMov::L, CMov::A, true, 0),
+            Op2::LessEqual => (CMov::LE, CMov::AE, true, 1),
+            Op2::Greater => (CMov::G, CMov::A, false, 2),
+            Op2::GreaterEqual => (CMov::GE, CMov::AE, false, 3),
+            _ => unreachable!("not an ordering comparison"),
+        };
+        let tag = self.next_tag();
+        let fixnum_lbl = format!("cmp_fixnum_{tag}");
+        let slow_lbl = format!("cmp_slow_{tag}");
+        let finish_lbl = format!("cmp_finish_{tag}");
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(Rax))),
+            Instr::Or(BinArgs::ToReg(Rdx, Arg32::Reg(Rcx))),
+            Instr::Test(BinArgs::ToReg(Rdx, Arg32::Imm(0b001))),
+            Instr::Jz(fixnum_lbl.clone()),
+        ]);
+        if operands != Operands::Ints {
+            self.load_float(Rax, Xmm0, &slow_lbl);
+            self.load_float(Rcx, Xmm1, &slow_lbl);
+            self.emit_instrs([
+                if swap {
+                    Instr::Ucomisd(Xmm1, Xmm0)
+                } else {
+                    Instr::Ucomisd(Xmm0, Xmm1)
+                },
+                Instr::Mov(MovArgs::ToReg(Rax, false.repr64())),
+                Instr::Mov(MovArgs::ToReg(Rcx, true.repr64())),
+                Instr::CMov(float_cmp(Rax, Arg64::Reg(Rcx))),
+                Instr::Jmp(finish_lbl.clone()),
+            ]);
+        }
+        self.emit_instr(Instr::Label(slow_lbl));
+        if operands == Operands::Unknown {
+            self.check_is_number(Rax);
+            self.check_is_number(Rcx);
+        }
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Reg(Rax))),
+            Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(Rcx))),
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Imm(code))),
+            Instr::Call("snek_compare".to_string()),
+            Instr::Jmp(finish_lbl.clone()),
+            Instr::Label(fixnum_lbl),
+        ]);
+        self.compile_cmp(cmp);
+        self.emit_instr(Instr::Label(finish_lbl));
+    }
+
+    /// Loads the number in `reg` into `xmm` as a double if it is a fixnum or a float, and jumps to
+    /// `other` otherwise. Clobbers `%rdx`.
+    fn load_float(&mut self, reg: Reg, xmm: Xmm, other: &str) {
+        let tag = self.next_tag();
+        let obj_lbl = format!("load_float_obj_{tag}");
+        let finish_lbl = format!("load_float_finish_{tag}");
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(reg))),
+            Instr::Test(BinArgs::ToReg(Rdx, Arg32::Imm(0b001))),
+            Instr::Jnz(obj_lbl.clone()),
+            Instr::Sar(BinArgs::ToReg(Rdx, Arg32::Imm(1))),
+            Instr::Cvtsi2sd(xmm, Rdx),
+            Instr::Jmp(finish_lbl.clone()),
+            Instr::Label(obj_lbl),
+        ]);
+        self.load_kind(reg, Rdx);
+        self.emit_instrs([
+            Instr::Cmp(BinArgs::ToReg(Rdx, Arg32::Imm(FLOAT_KIND as i32))),
+            Instr::Jne(other.to_string()),
+            Instr::Movsd(xmm, mref![reg + %(FLOAT_VAL)]),
+            Instr::Label(finish_lbl),
+        ]);
+    }
+
+    /// Leaves the kind of the object `reg` points to in `dst`, or -1 if `reg` doesn't hold a
+    /// `0b101`-tagged pointer.
+    fn load_kind(&mut self, reg: Reg, dst: Reg) {
+        let finish_lbl = format!("load_kind_finish_{}", self.next_tag());
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(dst, Arg64::Reg(reg))),
+            Instr::And(BinArgs::ToReg(dst, Arg32::Imm(0b111))),
+            Instr::Cmp(BinArgs::ToReg(dst, Arg32::Imm(OBJ_TAG))),
+            // `mov` leaves the flags alone.
+            Instr::Mov(MovArgs::ToReg(dst, Arg64::Imm(-1))),
+            Instr::Jne(finish_lbl.clone()),
+            Instr::Mov(MovArgs::ToReg(dst, Arg64::Mem(mref![reg + %(8 - OBJ_TAG)]))),
+            Instr::Shr(BinArgs::ToReg(dst, Arg32::Imm(56))),
+            Instr::Label(finish_lbl),
+        ]);
+    }
+
+    /// Boxes the double in `%xmm0` as a new float, leaving a pointer to it in `%rax`, and jumps to
+    /// `finish`. When the heap is full the runtime collects garbage and allocates it, since the
+    /// collector may clobber `%xmm0`.
+    fn alloc_float(&mut self, finish: &str) {
+        let inline_lbl = format!("alloc_float_inline_{}", self.next_tag());
+        self.emit_instrs([
+            Instr::MovqFromXmm(Rdi, Xmm0),
+            Instr::Lea(Rax, mref![HEAP_PTR + 24]),
+            Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Reg(HEAP_END))),
+            Instr::Jle(inline_lbl.clone()),
+            Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(HEAP_PTR))),
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(STACK_BASE))),
+            Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(Rbp))),
+            Instr::Mov(MovArgs::ToReg(R8, Arg64::Reg(Rsp))),
+            Instr::Call("snek_alloc_float".to_string()),
+            Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rdx))),
+            Instr::Jmp(finish.to_string()),
+            Instr::Label(inline_lbl),
+        ]);
+        self.write_header(header(FLOAT_KIND, 1));
+        self.emit_instr(Instr::Mov(MovArgs::ToMem(
+            mref![HEAP_PTR + 16],
+            Reg32::Reg(Rdi),
+        )));
+        self.finish_alloc(OBJ_TAG, 3);
+        self.emit_instr(Instr::Jmp(finish.to_string()));
+    }
+
+    /// Emits `=` on `%rax` and `%rcx`. Values with different tags can't be compared, and the
+    /// runtime handles anything involving a `0b101`-tagged object, since it may be a bignum.
+    fn compile_equal(&mut self) {
+        let tag = self.next_tag();
+        let check_eq_finish_lbl = format!("check_eq_finish_{tag}");
+        let eq_slow_lbl = format!("eq_slow_{tag}");
+        let eq_finish_lbl = format!("eq_finish_{tag}");
+        for reg in [Rax, Rcx] {
+            self.emit_instrs([
+                Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(reg))),
+                Instr::And(BinArgs::ToReg(Rdx, Arg32::Imm(0b111))),
+                Instr::Cmp(BinArgs::ToReg(Rdx, Arg32::Imm(OBJ_TAG))),
+                Instr::Je(eq_slow_lbl.clone()),
+            ]);
+        }
+        // if (%rax ^ %rcx) & 0b11 == 0 {
+        //     jmp check_eq_finish
+        // } else if (%rax | %rcx) & 0b01 != 0 {
+        //     jmp invalid_arg
+        // }
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(Rax))),
+            Instr::Xor(BinArgs::ToReg(Rdx, Arg32::Reg(Rcx))),
+            Instr::Test(BinArgs::ToReg(Rdx, Arg32::Imm(0b11))),
+            Instr::Jz(check_eq_finish_lbl.to_string()),
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(Rax))),
+            Instr::Or(BinArgs::ToReg(Rdx, Arg32::Reg(Rcx))),
+            Instr::Test(BinArgs::ToReg(Rdx, Arg32::Imm(0b01))),
+            Instr::Jnz(INVALID_ARG.to_string()),
+            Instr::Label(check_eq_finish_lbl.to_string()),
+        ]);
+        self.compile_cmp(CMov::E);
+        self.emit_instrs([
+            Instr::Jmp(eq_finish_lbl.clone()),
+            Instr::Label(eq_slow_lbl),
+            Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Reg(Rax))),
+            Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(Rcx))),
+            Instr::Call("snek_eq".to_string()),
+            Instr::Test(BinArgs::ToReg(Rax, Arg32::Reg(Rax))),
+            Instr::Jz(INVALID_ARG.to_string()),
+            Instr::Label(eq_finish_lbl),
+        ]);
+    }
+
+    fn compile_cmp(&mut self, cmp: impl FnOnce(Reg, Arg64) -> CMov) {
         self.emit_instrs([
             Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Reg(Rcx))),
             Instr::Mov(MovArgs::ToReg(Rax, false.repr64())),
@@ -622,6 +2130,18 @@ impl Session {
         ]);
     }
 
+    /// Points `%rcx` at the slot of the global `x`, returning the slot.
+    fn global_slot(&mut self, x: Symbol) -> MemRef {
+        let offset = 8 * self.globals[&x];
+        self.static_slot(format!("{GLOBALS} + {offset}"))
+    }
+
+    /// Points `%rcx` at the slot at `label`, returning the slot.
+    fn static_slot(&mut self, label: String) -> MemRef {
+        self.emit_instr(Instr::LeaLabel(Rcx, label));
+        mref![Rcx + 0]
+    }
+
     fn move_to(&mut self, dst: Loc, src: impl Into<Arg64>) {
         let src = src.into();
         if dst == src {
@@ -656,6 +2176,13 @@ impl Session {
         }
     }
 
+    /// Checks that `reg`, which holds the value of `e`, is a fixnum, unless that is already known.
+    fn check_fixnum(&mut self, reg: Reg, e: &Expr) {
+        if !self.types.is_fixnum(e) {
+            self.check_is_num(reg);
+        }
+    }
+
     fn check_is_num(&mut self, reg: Reg) {
         self.emit_instrs([
             Instr::Test(BinArgs::ToReg(reg, Arg32::Imm(0b001))),
@@ -663,12 +2190,77 @@ impl Session {
         ]);
     }
 
-    fn check_is_vec(&mut self, reg: Reg) {
+    /// Checks that `reg` holds a fixnum, a bignum or a float. Clobbers `%rdx`.
+    fn check_is_number(&mut self, reg: Reg) {
+        let finish_lbl = format!("check_is_number_finish_{}", self.next_tag());
         self.emit_instrs([
             Instr::Test(BinArgs::ToReg(reg, Arg32::Imm(0b001))),
-            Instr::Jz(INVALID_ARG.to_string()), // jump if is num
-            Instr::Test(BinArgs::ToReg(reg, Arg32::Imm(0b010))),
-            Instr::Jnz(INVALID_ARG.to_string()), // jump if is bool
+            Instr::Jz(finish_lbl.clone()),
+        ]);
+        self.load_kind(reg, Rdx);
+        self.emit_instrs([
+            Instr::Cmp(BinArgs::ToReg(Rdx, Arg32::Imm(BIGNUM_KIND as i32))),
+            Instr::Je(finish_lbl.clone()),
+            Instr::Cmp(BinArgs::ToReg(Rdx, Arg32::Imm(FLOAT_KIND as i32))),
+            Instr::Jne(INVALID_ARG.to_string()),
+            Instr::Label(finish_lbl),
+        ]);
+    }
+
+    /// Checks that `reg` holds a fixnum or a bignum. Clobbers `%rdx`.
+    fn check_is_int(&mut self, reg: Reg) {
+        let finish_lbl = format!("check_is_int_finish_{}", self.next_tag());
+        self.emit_instrs([
+            Instr::Test(BinArgs::ToReg(reg, Arg32::Imm(0b001))),
+            Instr::Jz(finish_lbl.clone()),
+        ]);
+        self.load_kind(reg, Rdx);
+        self.emit_instrs([
+            Instr::Cmp(BinArgs::ToReg(Rdx, Arg32::Imm(BIGNUM_KIND as i32))),
+            Instr::Jne(INVALID_ARG.to_string()),
+            Instr::Label(finish_lbl),
+        ]);
+    }
+
+    fn check_is_vec(&mut self, reg: Reg) {
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(R11, Arg64::Reg(reg))),
+            Instr::And(BinArgs::ToReg(R11, Arg32::Imm(0b111))),
+            Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(VEC_TAG))),
+            Instr::Jne(INVALID_ARG.to_string()),
+        ]);
+    }
+
+    /// Checks that `reg` holds a closure taking `nargs` arguments.
+    fn check_is_closure(&mut self, reg: Reg, nargs: usize) {
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(R11, Arg64::Reg(reg))),
+            Instr::And(BinArgs::ToReg(R11, Arg32::Imm(0b111))),
+            Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(OBJ_TAG))),
+            Instr::Jne(INVALID_ARG.to_string()),
+            Instr::Mov(MovArgs::ToReg(R11, Arg64::Mem(mref![reg + %(8 - OBJ_TAG)]))),
+            Instr::Shr(BinArgs::ToReg(R11, Arg32::Imm(56))),
+            Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(CLOSURE_KIND as i32))),
+            Instr::Jne(INVALID_ARG.to_string()),
+            Instr::Cmp(BinArgs::ToMem(
+                mref![reg + %(CLOSURE_ARITY)],
+                Reg32::Imm((nargs as i32) << 1),
+            )),
+            Instr::Jne(WRONG_ARITY.to_string()),
+        ]);
+    }
+
+    /// Checks that `reg` holds a record of the struct with type id `id`.
+    fn check_is_record(&mut self, reg: Reg, id: usize) {
+        self.emit_instrs([
+            Instr::Mov(MovArgs::ToReg(R11, Arg64::Reg(reg))),
+            Instr::And(BinArgs::ToReg(R11, Arg32::Imm(0b111))),
+            Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(OBJ_TAG))),
+            Instr::Jne(WRONG_RECORD_TYPE.to_string()),
+            Instr::Mov(MovArgs::ToReg(R11, Arg64::Mem(mref![reg + %(8 - OBJ_TAG)]))),
+            Instr::Shr(BinArgs::ToReg(R11, Arg32::Imm(32))),
+            Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(record_type(id)))),
+            Instr::Jne(WRONG_RECORD_TYPE.to_string()),
         ]);
     }
 
@@ -679,6 +2271,149 @@ impl Session {
         ]);
     }
 
+    /// Checks that `reg` holds a vector other than nil, unless its static type `ty` says so.
+    fn check_vec(&mut self, reg: Reg, ty: Type) {
+        if !ty.is_vec() {
+            self.check_is_vec(reg);
+            self.check_is_not_nil(reg);
+        }
+    }
+
+    /// Checks that `reg` holds a value of type `ty`. Clobbers `%r11`.
+    fn check_type(&mut self, reg: Reg, ty: Type) {
+        let finish_lbl = format!("check_type_finish_{}", self.next_tag());
+        match ty {
+            Type::Int => {
+                self.emit_instrs([
+                    Instr::Test(BinArgs::ToReg(reg, Arg32::Imm(0b001))),
+                    Instr::Jz(finish_lbl.clone()),
+                ]);
+                self.load_kind(reg, R11);
+                self.emit_instrs([
+                    Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(BIGNUM_KIND as i32))),
+                    Instr::Jne(INVALID_ARG.to_string()),
+                ]);
+            }
+            Type::Float => {
+                self.load_kind(reg, R11);
+                self.emit_instrs([
+                    Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(FLOAT_KIND as i32))),
+                    Instr::Jne(INVALID_ARG.to_string()),
+                ]);
+            }
+            Type::Bool => self.emit_instrs([
+                Instr::Mov(MovArgs::ToReg(R11, Arg64::Reg(reg))),
+                Instr::And(BinArgs::ToReg(R11, Arg32::Imm(0b011))),
+                Instr::Cmp(BinArgs::ToReg(R11, Arg32::Imm(0b011))),
+                Instr::Jne(INVALID_ARG.to_string()),
+            ]),
+            Type::Nil => self.emit_instrs([
+                Instr::Cmp(BinArgs::ToReg(reg, Arg32::Imm(NIL))),
+                Instr::Jne(INVALID_ARG.to_string()),
+            ]),
+            Type::Vec(_) => self.check_vec(reg, Type::Any),
+            Type::Any | Type::Never => {}
+        }
+        self.emit_instr(Instr::Label(finish_lbl));
+    }
+
+    /// Makes room for `words` words at `HEAP_PTR`, collecting garbage if the heap is full. Every
+    /// live value has to be in a stack slot, since the collector may move objects.
+    fn reserve(&mut self, words: i32) {
+        let alloc_finish_lbl = format!("alloc_finish_{}", self.next_tag());
+        self.emit_instrs([
+            Instr::Lea(Rax, mref![HEAP_PTR + %(8 * words)]),
+            Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Reg(HEAP_END))),
+            Instr::Jle(alloc_finish_lbl.clone()),
+            Instr::Mov(MovArgs::ToReg(Rdi, Arg64::Imm(words as i64))),
+            Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(HEAP_PTR))),
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(STACK_BASE))),
+            Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(Rbp))),
+            Instr::Mov(MovArgs::ToReg(R8, Arg64::Reg(Rsp))),
+            Instr::Call("snek_try_gc".to_string()),
+            Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rax))),
+            Instr::Label(alloc_finish_lbl),
+        ]);
+    }
+
+    /// Writes the GC word and `header` of an object at `HEAP_PTR`.
+    fn write_header(&mut self, header: i64) {
+        self.emit_instr(Instr::Mov(MovArgs::ToMem(
+            mref!(HEAP_PTR + 0),
+            Reg32::Imm(GC_WORD_VAL),
+        )));
+        self.move_to(Loc::Mem(mref!(HEAP_PTR + 8)), Arg64::Imm(header));
+    }
+
+    /// Leaves a pointer to the object at `HEAP_PTR`, tagged with `tag`, in `%rax` and bumps the
+    /// heap pointer past its `words` words.
+    fn finish_alloc(&mut self, tag: i32, words: i32) {
+        self.emit_instrs([
+            Instr::Lea(Rax, mref!(HEAP_PTR + %(tag))),
+            Instr::Lea(HEAP_PTR, mref!(HEAP_PTR + %(8 * words))),
+        ]);
+    }
+
+    /// Makes room for a vector with the number of elements in `len` at `HEAP_PTR`, like
+    /// [`Session::reserve`]. Clobbers `len` if the collector runs.
+    fn reserve_vec(&mut self, len: Reg) {
+        let alloc_finish_lbl = format!("alloc_vec_finish_{}", self.next_tag());
+        self.emit_instrs([
+            Instr::Lea(Rax, mref![HEAP_PTR + 8 * len + 16]),
+            Instr::Cmp(BinArgs::ToReg(Rax, Arg32::Reg(HEAP_END))),
+            Instr::Jle(alloc_finish_lbl.clone()),
+            // The vector takes two more words for its GC word and header.
+            Instr::Lea(Rdi, mref![len + 2]),
+            Instr::Mov(MovArgs::ToReg(Rsi, Arg64::Reg(HEAP_PTR))),
+            Instr::Mov(MovArgs::ToReg(Rdx, Arg64::Reg(STACK_BASE))),
+            Instr::Mov(MovArgs::ToReg(Rcx, Arg64::Reg(Rbp))),
+            Instr::Mov(MovArgs::ToReg(R8, Arg64::Reg(Rsp))),
+            Instr::Call("snek_try_gc".to_string()),
+            Instr::Mov(MovArgs::ToReg(HEAP_PTR, Arg64::Reg(Rax))),
+            Instr::Label(alloc_finish_lbl),
+        ]);
+    }
+
+    /// Allocates a vector holding the values in the stack slots `elems`.
+    fn alloc_vec(&mut self, elems: &[MemRef]) {
+        let size = elems.len() as i32;
+        self.reserve(size + 2);
+        self.write_header(header(0, size as i64));
+        for (i, mem) in elems.iter().enumerate() {
+            self.move_to(Loc::Mem(mref!(HEAP_PTR + %(8 * (i + 2)))), Arg64::Mem(*mem));
+        }
+        self.finish_alloc(VEC_TAG, size + 2);
+    }
+
+    /// Allocates a closure over the code at `label`, copying the captured values from `captures`.
+    fn alloc_closure(&mut self, label: &str, arity: usize, captures: &[MemRef]) {
+        let len = 2 + captures.len() as i32;
+        self.reserve(len + 2);
+        self.write_header(header(CLOSURE_KIND, len as i64));
+        self.emit_instrs([
+            Instr::LeaLabel(Rax, label.to_string()),
+            Instr::Mov(MovArgs::ToMem(mref![HEAP_PTR + 16], Reg32::Reg(Rax))),
+            Instr::Mov(MovArgs::ToMem(
+                mref![HEAP_PTR + 24],
+                Reg32::Imm((arity as i32) << 1),
+            )),
+        ]);
+        for (i, mem) in captures.iter().enumerate() {
+            self.move_to(Loc::Mem(mref![HEAP_PTR + %(32 + 8 * i)]), Arg64::Mem(*mem));
+        }
+        self.finish_alloc(OBJ_TAG, len + 2);
+    }
+
+    /// Moves the value in the stack slot `mem` into a new box, leaving a pointer to the box in the
+    /// slot.
+    fn box_slot(&mut self, mem: MemRef) {
+        self.reserve(3);
+        self.write_header(header(BOX_KIND, 1));
+        self.move_to(Loc::Mem(mref![HEAP_PTR + 16]), Arg64::Mem(mem));
+        self.finish_alloc(OBJ_TAG, 3);
+        self.move_to(Loc::Mem(mem), Arg64::Reg(Rax));
+    }
+
     fn emit_instrs(&mut self, instrs: impl IntoIterator<Item = Instr>) {
         self.instrs.extend(instrs);
     }
@@ -703,39 +2438,166 @@ fn frame_size(locals: u32, calle_saved: &[Reg]) -> u32 {
     }
 }
 
+/// What `pat` checks about a value before its subpatterns, or `None` if it matches anything.
+fn pattern_head(pat: &Pattern) -> Option<PatternHead> {
+    match pat {
+        Pattern::Wildcard | Pattern::Var(_) => None,
+        Pattern::Number(n) => Some(PatternHead::Number(*n)),
+        Pattern::Boolean(b) => Some(PatternHead::Boolean(*b)),
+        Pattern::Nil => Some(PatternHead::Nil),
+        Pattern::Vec(pats) => Some(PatternHead::Vec(pats.len())),
+    }
+}
+
+/// The header of an object of `kind` with `len` payload words.
+fn header(kind: i64, len: i64) -> i64 {
+    kind << 56 | len
+}
+
+/// The top half of the header of a record of the struct with type id `id`.
+fn record_type(id: usize) -> i32 {
+    (RECORD_KIND << 24) as i32 | id as i32
+}
+
+/// The size of the argument area of a function taking `arity` arguments: the closure it was called
+/// through, the arguments, and a padding slot if needed to keep the stack 16-byte aligned.
+fn arg_slots(arity: usize) -> i32 {
+    let slots = arity as i32 + 1;
+    slots + slots % 2
+}
+
 fn depth(e: &Expr) -> u32 {
-    match e {
-        Expr::BinOp(_, e1, e2) => depth(e1).max(depth(e2) + 1),
-        Expr::Let(bindings, e) => bindings
+    match &e.kind {
+        // The scrutinee takes a slot, and each arm's variables the slots above it.
+        ExprKind::Match(e, arms) => arms
+            .iter()
+            .map(|arm| arm.pattern.bindings().len() as u32 + depth(&arm.body) + 1)
+            .max()
+            .unwrap_or(0)
+            .max(depth(e)),
+        ExprKind::BinOp(_, e1, e2) => depth(e1).max(depth(e2) + 1),
+        ExprKind::Let(bindings, e) => bindings
             .iter()
             .enumerate()
             .map(|(i, (_, e))| depth(e) + (i as u32))
             .max()
             .unwrap_or(0)
             .max(depth(e) + bindings.len() as u32),
-        Expr::If(e1, e2, e3) => depth(e1).max(depth(e2)).max(depth(e3)),
-        Expr::Call(_, es) | Expr::Block(es) => es.iter().map(depth).max().unwrap_or(0),
-        Expr::UnOp(_, e) | Expr::Loop(e) | Expr::Break(e) | Expr::Set(_, e) => depth(e),
-        Expr::MakeVec(size, elem) => depth(size).max(depth(elem) + 1).max(2),
-        Expr::Vec(elems) => elems
+        ExprKind::If(e1, e2, e3) => depth(e1).max(depth(e2)).max(depth(e3)),
+        // The body runs above the handler's four slots, and the handler above its binding.
+        ExprKind::Try(body, _, handler) => (depth(body) + 4).max(depth(handler) + 1),
+        // One slot for the callee and one for each argument.
+        ExprKind::Call(fun, args) => args
+            .iter()
+            .enumerate()
+            .map(|(i, e)| depth(e) + (i as u32) + 1)
+            .max()
+            .unwrap_or(0)
+            .max(depth(fun))
+            .max(args.len() as u32 + 1),
+        ExprKind::Block(es) => es.iter().map(depth).max().unwrap_or(0),
+        ExprKind::UnOp(_, e)
+        | ExprKind::Loop(e)
+        | ExprKind::Break(e)
+        | ExprKind::Raise(e)
+        | ExprKind::Set(_, e)
+        | ExprKind::VecLen(e) => depth(e),
+        ExprKind::MakeVec(size, elem) => depth(size).max(depth(elem) + 1).max(2),
+        ExprKind::Vec(elems)
+        | ExprKind::Record(_, elems)
+        | ExprKind::VecOp(_, elems)
+        | ExprKind::Io(_, elems) => elems
             .iter()
             .enumerate()
             .map(|(i, e)| depth(e) + (i as u32))
             .max()
             .unwrap_or(0)
             .max(elems.len() as u32),
-        Expr::VecSet(vec, idx, val) => depth(vec).max(depth(idx) + 1).max(depth(val) + 2).max(2),
-        Expr::VecGet(vec, idx) => depth(vec).max(depth(idx) + 1),
-        Expr::PrintStack
-        | Expr::PrintHeap
-        | Expr::Gc
-        | Expr::VecLen(_)
-        | Expr::Input
-        | Expr::Nil
-        | Expr::Var(_)
-        | Expr::Number(_)
-        | Expr::Boolean(_) => 0,
+        // `make-dict` without an operand still passes one.
+        ExprKind::Dict(_, es) => es
+            .iter()
+            .enumerate()
+            .map(|(i, e)| depth(e) + (i as u32))
+            .max()
+            .unwrap_or(0)
+            .max(es.len().max(1) as u32),
+        ExprKind::VecSet(vec, idx, val) => {
+            depth(vec).max(depth(idx) + 1).max(depth(val) + 2).max(2)
+        }
+        ExprKind::VecGet(vec, idx) => depth(vec).max(depth(idx) + 1),
+        ExprKind::PrintStack
+        | ExprKind::PrintHeap
+        | ExprKind::Gc
+        // A lambda's body runs in its own frame.
+        | ExprKind::Lambda(..)
+        | ExprKind::Input
+        | ExprKind::Args
+        | ExprKind::Nil
+        | ExprKind::Var(_)
+        | ExprKind::Number(_)
+        | ExprKind::Float(_)
+        | ExprKind::Boolean(_) => 0,
+        ExprKind::LocalFuns(..) => unreachable!("local functions are lifted before compiling"),
+    }
+}
+
+/// Whether the variable `x`, in scope for all of `scope`, has to be boxed: whether it is both
+/// assigned to and captured by a lambda.
+fn needs_box<'a>(x: Symbol, scope: impl IntoIterator<Item = &'a Expr>) -> bool {
+    #[derive(Default)]
+    struct Uses {
+        assigned: bool,
+        captured: bool,
+    }
+
+    fn find_uses(e: &Expr, x: Symbol, in_lambda: bool, uses: &mut Uses) {
+        match &e.kind {
+            ExprKind::Var(y) if *y == x => uses.captured |= in_lambda,
+            ExprKind::Set(y, rhs) => {
+                if *y == x {
+                    uses.assigned = true;
+                    uses.captured |= in_lambda;
+                }
+                find_uses(rhs, x, in_lambda, uses);
+            }
+            ExprKind::Let(bindings, body) => {
+                for (binding, rhs) in bindings {
+                    find_uses(rhs, x, in_lambda, uses);
+                    if binding.name == x {
+                        return;
+                    }
+                }
+                find_uses(body, x, in_lambda, uses);
+            }
+            ExprKind::Try(body, binding, handler) => {
+                find_uses(body, x, in_lambda, uses);
+                if binding.name != x {
+                    find_uses(handler, x, in_lambda, uses);
+                }
+            }
+            ExprKind::Match(e, arms) => {
+                find_uses(e, x, in_lambda, uses);
+                for arm in arms {
+                    let bindings = arm.pattern.bindings();
+                    if bindings.iter().all(|(binding, _)| binding.name != x) {
+                        find_uses(&arm.body, x, in_lambda, uses);
+                    }
+                }
+            }
+            ExprKind::Lambda(params, body) => {
+                if params.iter().all(|param| param.name != x) {
+                    find_uses(body, x, true, uses);
+                }
+            }
+            _ => e.for_each_child(|e| find_uses(e, x, in_lambda, uses)),
+        }
+    }
+
+    let mut uses = Uses::default();
+    for e in scope {
+        find_uses(e, x, false, &mut uses);
     }
+    uses.assigned && uses.captured
 }
 
 trait Repr64 {
@@ -770,53 +2632,16 @@ impl Repr32 for bool {
     }
 }
 
-fn fun_arity_map(prg: &Prog) -> Result<HashMap<Symbol, usize>, Symbol> {
-    let mut map = HashMap::new();
-    for fun in &prg.funs {
-        if map.insert(fun.name, fun.params.len()).is_some() {
-            return Err(fun.name);
-        }
-    }
-    Ok(map)
-}
-
-fn check_dup_bindings<'a>(bindings: impl IntoIterator<Item = &'a Symbol>) {
-    let mut seen = HashSet::new();
-    for name in bindings {
-        if !seen.insert(*name) {
-            raise_duplicate_binding(*name);
-        }
-    }
-}
-
-fn raise_duplicate_binding(id: Symbol) {
-    panic!("duplicate binding {id}");
-}
-
-fn raise_duplicate_function<T>(name: Symbol) -> T {
-    panic!("duplicate function name {name}")
-}
-
-fn raise_unbound_identifier<T>(id: Symbol) -> T {
-    panic!("unbound variable identifier {id}")
-}
-
-fn raise_break_outside_loop() {
-    panic!("break outside loop")
-}
-
-fn raise_input_in_fun<T>() -> T {
-    panic!("cannot use input inside function definition")
-}
-
-fn raise_undefined_fun(fun: Symbol) {
-    panic!("function {fun} not defined")
-}
-
-fn raise_wrong_number_of_args(fun: Symbol, expected: usize, got: usize) {
-    panic!("function {fun} takes {expected} arguments but {got} were supplied")
+/// The entry of a function with annotated parameters that checks its arguments before falling
+/// into [`fun_label`].
+fn checked_label(fun: Symbol) -> String {
+    format!("{}.checked", fun_label(fun))
 }
 
+/// The label of a top-level function. Identifiers may contain `-` and end in `?` or `!`, which
+/// aren't allowed in labels; they become `.d`, `.p` and `.b`. Identifiers can't contain `.`, and
+/// the names [`lift`] makes only have one before a number, so no two functions share a label.
 fn fun_label(fun: Symbol) -> String {
-    format!("snek_fun_{}", fun.replace("-", "_"))
+    let name = fun.replace("-", ".d").replace('?', ".p").replace('!', ".b");
+    format!("snek_fun_{name}")
 }
@@ -0,0 +1,52 @@
+use std::fmt::Write;
+
+use crate::syntax::Span;
+
+/// Renders `headline` as `file:line:col: headline`, followed by the source line containing `span`
+/// with the span underlined.
+pub fn render(path: &str, src: &str, span: Span, headline: &str) -> String {
+    let start = span.start.min(src.len());
+    let loc = Location::of(src, start);
+    let line_src = src[loc.line_start..]
+        .split('\n')
+        .next()
+        .unwrap_or("")
+        .trim_end_matches('\r');
+
+    // Only the part of the span that falls on the first line gets underlined. Tabs in the
+    // prefix are kept so the carets line up with the source however the terminal renders them.
+    let prefix: String = line_src[..(start - loc.line_start).min(line_src.len())]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let end = span.end.min(loc.line_start + line_src.len());
+    let width = src.get(start..end).map_or(0, |s| s.chars().count()).max(1);
+
+    let gutter = " ".repeat(loc.line.to_string().len());
+    let mut out = String::new();
+    let _ = writeln!(out, "{path}:{}:{}: {headline}", loc.line, loc.col);
+    let _ = writeln!(out, "{gutter} |");
+    let _ = writeln!(out, "{} | {line_src}", loc.line);
+    let _ = write!(out, "{gutter} | {prefix}{}", "^".repeat(width));
+    out
+}
+
+/// A 1-based line and column, counting columns in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub col: usize,
+    line_start: usize,
+}
+
+impl Location {
+    pub fn of(src: &str, offset: usize) -> Location {
+        let offset = offset.min(src.len());
+        let line_start = src[..offset].rfind('\n').map_or(0, |i| i + 1);
+        Location {
+            line: src[..offset].matches('\n').count() + 1,
+            col: src[line_start..offset].chars().count() + 1,
+            line_start,
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::fmt;
+
+use crate::{
+    diagnostic,
+    syntax::{Span, Symbol, Type},
+};
+
+/// Everything that can go wrong while turning a snek source file into assembly.
+///
+/// Each variant has a stable error code (see [`CompileError::code`]) that tools can rely on. Codes
+/// are never renumbered or reused; new variants get the next free code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileError {
+    /// A form that doesn't match the grammar, e.g. `(let x 1)` or an unbalanced paren.
+    Malformed {
+        message: String,
+        span: Span,
+    },
+    /// An integer literal that doesn't fit in 63 bits.
+    IntegerOverflow {
+        span: Span,
+    },
+    /// The same name bound twice in a single `let` or parameter list, or by a `define` and another
+    /// `define` or a top-level function.
+    DuplicateBinding {
+        name: Symbol,
+        span: Span,
+    },
+    /// Two top-level functions with the same name.
+    DuplicateFunction {
+        name: Symbol,
+        span: Span,
+    },
+    UnboundIdentifier {
+        name: Symbol,
+        span: Span,
+    },
+    UndefinedFunction {
+        name: Symbol,
+        span: Span,
+    },
+    WrongArity {
+        name: Symbol,
+        expected: usize,
+        /// Whether the function takes a rest parameter, so `expected` is only the minimum.
+        variadic: bool,
+        got: usize,
+        span: Span,
+    },
+    BreakOutsideLoop {
+        span: Span,
+    },
+    /// A function with a rest parameter used as a value. Extra arguments are packed at the call
+    /// site, which needs to know the callee.
+    VariadicAsValue {
+        name: Symbol,
+        span: Span,
+    },
+    /// A value whose static type doesn't fit where it is used, e.g. a `bool` passed for an `int`
+    /// parameter. `expected` is a type or a description like "a number".
+    TypeMismatch {
+        expected: String,
+        found: Type,
+        span: Span,
+    },
+    /// A local function assigning a variable from around it. Local functions are passed copies of
+    /// those variables, so the assignment would be lost.
+    AssignCaptured {
+        name: Symbol,
+        span: Span,
+    },
+    /// A `define` whose initializer refers to a function that reads a global defined after it,
+    /// whose slot would still be nil. `through` is the name the initializer refers to.
+    GlobalReadEarly {
+        name: Symbol,
+        through: Symbol,
+        span: Span,
+    },
+}
+
+impl CompileError {
+    pub fn malformed(span: Span, message: impl ToString) -> CompileError {
+        CompileError::Malformed {
+            message: message.to_string(),
+            span,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileError::Malformed { .. } => "E0001",
+            CompileError::IntegerOverflow { .. } => "E0002",
+            CompileError::DuplicateBinding { .. } => "E0003",
+            CompileError::DuplicateFunction { .. } => "E0004",
+            CompileError::UnboundIdentifier { .. } => "E0005",
+            CompileError::UndefinedFunction { .. } => "E0006",
+            CompileError::WrongArity { .. } => "E0007",
+            CompileError::BreakOutsideLoop { .. } => "E0008",
+            CompileError::VariadicAsValue { .. } => "E0010",
+            CompileError::TypeMismatch { .. } => "E0011",
+            CompileError::AssignCaptured { .. } => "E0012",
+            CompileError::GlobalReadEarly { .. } => "E0013",
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            CompileError::Malformed { span, .. }
+            | CompileError::IntegerOverflow { span }
+            | CompileError::DuplicateBinding { span, .. }
+            | CompileError::DuplicateFunction { span, .. }
+            | CompileError::UnboundIdentifier { span, .. }
+            | CompileError::UndefinedFunction { span, .. }
+            | CompileError::WrongArity { span, .. }
+            | CompileError::BreakOutsideLoop { span }
+            | CompileError::VariadicAsValue { span, .. }
+            | CompileError::TypeMismatch { span, .. }
+            | CompileError::AssignCaptured { span, .. }
+            | CompileError::GlobalReadEarly { span, .. } => *span,
+        }
+    }
+
+    /// Renders the error as `file:line:col: error[CODE]: message` followed by the offending source
+    /// line.
+    pub fn render(&self, path: &str, src: &str) -> String {
+        let headline = format!("error[{}]: {self}", self.code());
+        diagnostic::render(path, src, self.span(), &headline)
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Malformed { message, .. } => write!(f, "Invalid syntax: {message}"),
+            CompileError::IntegerOverflow { .. } => {
+                write!(f, "Invalid syntax: integer literal overflow")
+            }
+            CompileError::DuplicateBinding { name, .. } => write!(f, "duplicate binding {name}"),
+            CompileError::DuplicateFunction { name, .. } => {
+                write!(f, "duplicate function name {name}")
+            }
+            CompileError::UnboundIdentifier { name, .. } => {
+                write!(f, "unbound variable identifier {name}")
+            }
+            CompileError::UndefinedFunction { name, .. } => {
+                write!(f, "function {name} not defined")
+            }
+            CompileError::WrongArity {
+                name,
+                expected,
+                variadic,
+                got,
+                ..
+            } => {
+                let at_least = if *variadic { "at least " } else { "" };
+                write!(
+                    f,
+                    "function {name} takes {at_least}{expected} arguments but {got} were supplied"
+                )
+            }
+            CompileError::BreakOutsideLoop { .. } => write!(f, "break outside loop"),
+            CompileError::VariadicAsValue { name, .. } => {
+                write!(
+                    f,
+                    "function {name} takes a rest parameter and cannot be used as a value"
+                )
+            }
+            CompileError::TypeMismatch {
+                expected, found, ..
+            } => write!(f, "mismatched types: expected {expected}, found {found}"),
+            CompileError::AssignCaptured { name, .. } => {
+                write!(
+                    f,
+                    "cannot assign to {name}, which the local function captures"
+                )
+            }
+            CompileError::GlobalReadEarly { name, through, .. } => {
+                write!(
+                    f,
+                    "global {name} may be read through {through} before it is defined"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Something suspicious about a program that still compiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// `--infer-types` found a program that doesn't type-check, so it is compiled with all the
+    /// usual checks. `reason` says what didn't fit, e.g. "expected int, found bool".
+    InferenceFailed { reason: String, span: Span },
+}
+
+impl Warning {
+    pub fn span(&self) -> Span {
+        match self {
+            Warning::InferenceFailed { span, .. } => *span,
+        }
+    }
+
+    /// Renders the warning as `file:line:col: warning: message` followed by the offending source
+    /// line.
+    pub fn render(&self, path: &str, src: &str) -> String {
+        diagnostic::render(path, src, self.span(), &format!("warning: {self}"))
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::InferenceFailed { reason, .. } => {
+                write!(
+                    f,
+                    "type inference failed, compiling with dynamic checks: {reason}"
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,598 @@
+use crate::{
+    error::CompileError,
+    parser,
+    reader::{self, Comment},
+    syntax::{
+        Binding, Expr, ExprKind, FunDecl, GlobalDecl, MatchArm, Op1, Op2, Pattern, Prog, Span,
+        StructDecl,
+    },
+};
+
+/// Lines are kept within this many columns where the program's nesting allows it.
+const WIDTH: usize = 80;
+
+/// Parses `src` and re-emits it in canonical form: a form stays on one line if it fits within
+/// [`WIDTH`] columns, and is broken across lines with standard lisp indentation otherwise. Comments
+/// are kept next to the code that follows them, or at the end of the line they trailed.
+///
+/// The output parses to the same program as `src`, and formatting it again leaves it unchanged.
+pub fn format(src: &str) -> Result<String, Vec<CompileError>> {
+    let prog = parser::parse(src)?;
+    let mut f = Formatter {
+        src,
+        out: String::new(),
+        comments: reader::comments(src),
+        next: 0,
+        after_comment: false,
+    };
+    f.prog(&prog);
+    Ok(f.out)
+}
+
+/// How an expression is laid out. Every layout has a flat, single-line rendering; the variants
+/// differ in how they break when that doesn't fit.
+enum Layout<'a> {
+    Atom(String),
+    /// `(head arg ...)`, broken with every argument aligned under the first one.
+    Call(String, Vec<&'a Expr>),
+    /// `(head body ...)`, broken with the body indented by two.
+    Body(String, Vec<Item<'a>>),
+    /// `(fun arg ...)` with a computed function, broken with every argument under the function.
+    Apply(&'a Expr, Vec<&'a Expr>),
+    Let(&'a [(Binding, Expr)], Vec<Item<'a>>),
+    /// `(try body (catch e handler))`, broken with the body and the `catch` indented by two.
+    Try(&'a Expr, Binding, &'a Expr),
+    /// `(match e (pattern body) ...)`, broken with every arm on its own line indented by two.
+    Match(&'a Expr, &'a [MatchArm]),
+    /// `[elem ...]`, broken with every element aligned under the first one.
+    Vec(Vec<&'a Expr>),
+}
+
+/// A line of a `block`, `let` or other body: an expression, or a local function defined for the
+/// lines after it.
+#[derive(Clone, Copy)]
+enum Item<'a> {
+    Expr(&'a Expr),
+    Fun(&'a FunDecl),
+}
+
+struct Formatter<'a> {
+    src: &'a str,
+    out: String,
+    comments: Vec<Comment>,
+    /// Index of the first comment that hasn't been emitted yet.
+    next: usize,
+    /// Whether the current output line ends in a comment, so nothing else may follow on it.
+    after_comment: bool,
+}
+
+impl Formatter<'_> {
+    fn prog(&mut self, prog: &Prog) {
+        enum Decl<'a> {
+            Struct(&'a StructDecl),
+            Fun(&'a FunDecl),
+            Global(&'a GlobalDecl),
+        }
+        let mut decls: Vec<_> = prog
+            .structs
+            .iter()
+            .map(|decl| (decl.span, Decl::Struct(decl)))
+            .collect();
+        decls.extend(prog.funs.iter().map(|fun| (fun.span, Decl::Fun(fun))));
+        decls.extend(
+            prog.globals
+                .iter()
+                .map(|global| (global.span, Decl::Global(global))),
+        );
+        decls.sort_by_key(|(span, _)| span.start);
+
+        for (span, decl) in decls {
+            self.flush_comments(span.start, 0);
+            match decl {
+                Decl::Struct(decl) => self.struct_decl(decl),
+                Decl::Fun(fun) => self.fun(fun, 0),
+                Decl::Global(global) => self.global(global),
+            }
+            self.trailing_comment(span.end);
+            self.out.push_str("\n\n");
+        }
+        self.flush_comments(prog.main.span.start, 0);
+        self.expr(&prog.main, 0);
+        self.out.push('\n');
+        self.flush_comments(usize::MAX, 0);
+        while self.out.ends_with("\n\n") {
+            self.out.pop();
+        }
+    }
+
+    fn struct_decl(&mut self, decl: &StructDecl) {
+        let fields: Vec<String> = decl
+            .fields
+            .iter()
+            .map(|field| field.name.to_string())
+            .collect();
+        self.out
+            .push_str(&format!("(struct {} ({}))", decl.name, fields.join(" ")));
+    }
+
+    fn global(&mut self, global: &GlobalDecl) {
+        let head = format!("(define {}", global.name);
+        match flat(&global.init) {
+            Some(init)
+                if !self.has_comment(global.span) && head.len() + init.len() + 2 <= WIDTH =>
+            {
+                self.out.push_str(&format!("{head} {init})"));
+            }
+            _ => {
+                self.out.push_str(&head);
+                self.newline(2);
+                self.expr(&global.init, 1);
+                self.close(global.span.end, 2);
+            }
+        }
+    }
+
+    /// Emits a function definition starting at column `col`.
+    fn fun(&mut self, fun: &FunDecl, col: usize) {
+        let mut sig = format!("(fun ({}", fun.name);
+        for param in &fun.params {
+            match param.ty {
+                Some(ty) => sig.push_str(&format!(" ({} : {ty})", param.name)),
+                None => sig.push_str(&format!(" {}", param.name)),
+            }
+        }
+        if let Some(rest) = &fun.rest {
+            sig.push_str(&format!(" . {}", rest.name));
+        }
+        sig.push(')');
+        if let Some(ret) = fun.ret {
+            sig.push_str(&format!(" : {ret}"));
+        }
+
+        match flat(&fun.body) {
+            Some(body)
+                if !self.has_comment(fun.span) && col + sig.len() + body.len() + 2 <= WIDTH =>
+            {
+                self.out.push_str(&format!("{sig} {body})"));
+            }
+            _ => {
+                self.out.push_str(&sig);
+                self.newline(col + 2);
+                self.expr(&fun.body, 1);
+                self.close(fun.span.end, col + 2);
+            }
+        }
+    }
+
+    /// Emits `e` starting at the current column. `closers` is the number of `)` that will directly
+    /// follow it, which have to fit on the line too.
+    fn expr(&mut self, e: &Expr, closers: usize) {
+        let col = self.col();
+        self.flush_comments(e.span.start, col);
+
+        let col = self.col();
+        let flat = flat(e).filter(|flat| self.fits(col, flat, closers, e.span));
+        match layout(e) {
+            Layout::Atom(s) => self.out.push_str(&s),
+            _ if flat.is_some() => self.out.push_str(&flat.unwrap()),
+            Layout::Call(head, args) => {
+                self.out.push_str(&format!("({head}"));
+                let indent = col + head.len() + 2;
+                for (i, arg) in args.iter().enumerate() {
+                    if i == 0 {
+                        self.out.push(' ');
+                    } else {
+                        self.newline(indent);
+                    }
+                    self.expr(arg, if i + 1 == args.len() { closers + 1 } else { 0 });
+                }
+                self.close(e.span.end, indent);
+            }
+            Layout::Body(head, body) => {
+                self.out.push_str(&format!("({head}"));
+                for (i, item) in body.iter().enumerate() {
+                    self.newline(col + 2);
+                    self.item(*item, if i + 1 == body.len() { closers + 1 } else { 0 });
+                }
+                self.close(e.span.end, col + 2);
+            }
+            Layout::Apply(fun, args) => {
+                self.out.push('(');
+                self.expr(fun, if args.is_empty() { closers + 1 } else { 0 });
+                for (i, arg) in args.iter().enumerate() {
+                    self.newline(col + 1);
+                    self.expr(arg, if i + 1 == args.len() { closers + 1 } else { 0 });
+                }
+                self.close(e.span.end, col + 1);
+            }
+            Layout::Let(bindings, body) => {
+                self.out.push_str("(let ");
+                self.bindings(bindings, col + 5);
+                for (i, item) in body.iter().enumerate() {
+                    self.newline(col + 2);
+                    self.item(*item, if i + 1 == body.len() { closers + 1 } else { 0 });
+                }
+                self.close(e.span.end, col + 2);
+            }
+            Layout::Try(body, binding, handler) => {
+                self.out.push_str("(try");
+                self.newline(col + 2);
+                self.expr(body, 0);
+                self.newline(col + 2);
+                self.out.push_str(&format!("(catch {}", binding.name));
+                self.newline(col + 4);
+                self.expr(handler, closers + 2);
+                self.close(handler.span.end, col + 4);
+                self.close(e.span.end, col + 2);
+            }
+            Layout::Match(scrutinee, arms) => {
+                self.out.push_str("(match ");
+                self.expr(scrutinee, 0);
+                for (i, arm) in arms.iter().enumerate() {
+                    self.newline(col + 2);
+                    self.arm(arm, if i + 1 == arms.len() { closers + 1 } else { 0 });
+                }
+                self.close(e.span.end, col + 2);
+            }
+            Layout::Vec(elems) => {
+                self.out.push('[');
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        self.newline(col + 1);
+                    }
+                    self.expr(elem, if i + 1 == elems.len() { closers + 1 } else { 0 });
+                }
+                self.close_with(e.span.end, col + 1, ']');
+            }
+        }
+        self.trailing_comment(e.span.end);
+    }
+
+    fn item(&mut self, item: Item, closers: usize) {
+        match item {
+            Item::Expr(e) => self.expr(e, closers),
+            Item::Fun(fun) => {
+                let col = self.col();
+                self.flush_comments(fun.span.start, col);
+                let col = self.col();
+                self.fun(fun, col);
+                self.trailing_comment(fun.span.end);
+            }
+        }
+    }
+
+    /// Emits a `match` arm, on one line if it fits and with its body on the next line otherwise.
+    fn arm(&mut self, arm: &MatchArm, closers: usize) {
+        let col = self.col();
+        self.flush_comments(arm.span.start, col);
+        let pat = pattern(&arm.pattern);
+        let flat = flat(&arm.body)
+            .map(|body| format!("({pat} {body})"))
+            .filter(|flat| self.fits(col, flat, closers, arm.span));
+        if let Some(flat) = flat {
+            self.out.push_str(&flat);
+        } else {
+            self.out.push_str(&format!("({pat}"));
+            self.newline(col + 2);
+            self.expr(&arm.body, closers + 1);
+            self.close(arm.span.end, col + 2);
+        }
+        self.trailing_comment(arm.span.end);
+    }
+
+    /// Emits a `let`'s bindings, all on the current line if they fit and one per line otherwise.
+    fn bindings(&mut self, bindings: &[(Binding, Expr)], col: usize) {
+        let first = bindings[0].0.span.start;
+        let end = bindings[bindings.len() - 1].1.span.end;
+        let flat =
+            flat_bindings(bindings).filter(|flat| self.fits(col, flat, 0, Span::new(first, end)));
+        if let Some(flat) = flat {
+            self.out.push_str(&flat);
+            return;
+        }
+
+        self.out.push('(');
+        for (i, (binding, rhs)) in bindings.iter().enumerate() {
+            if i > 0 {
+                self.newline(col + 1);
+            }
+            self.flush_comments(binding.span.start, col + 1);
+            self.out.push_str(&format!("({} ", binding_name(binding)));
+            self.expr(rhs, if i + 1 == bindings.len() { 2 } else { 1 });
+            self.close(rhs.span.end, col + 2);
+        }
+        self.close(end, col + 1);
+    }
+
+    fn fits(&self, col: usize, flat: &str, closers: usize, span: Span) -> bool {
+        col + flat.len() + closers <= WIDTH && !self.has_comment(span)
+    }
+
+    /// Emits the comments that start before `pos`, each followed by a line break to `indent`.
+    fn flush_comments(&mut self, pos: usize, indent: usize) {
+        while let Some(comment) = self.comments.get(self.next) {
+            if comment.span.start >= pos {
+                break;
+            }
+            let text = comment.text.clone();
+            let own_line = self.src[..comment.span.start]
+                .trim_end_matches([' ', '\t'])
+                .ends_with('\n');
+            self.next += 1;
+            if own_line && !self.line_is_blank() {
+                self.newline(indent);
+            } else if !self.line_is_blank() {
+                self.out.truncate(self.out.trim_end_matches(' ').len());
+                self.out.push(' ');
+            }
+            self.out.push_str(&text);
+            self.newline(indent);
+        }
+    }
+
+    /// Keeps a comment that followed `pos` on the same source line at the end of the current line.
+    fn trailing_comment(&mut self, pos: usize) {
+        let Some(comment) = self.comments.get(self.next) else {
+            return;
+        };
+        let between = self.src.get(pos..comment.span.start).unwrap_or("\n");
+        if !between.chars().all(|c| c == ' ' || c == '\t') {
+            return;
+        }
+        self.out.push(' ');
+        self.out.push_str(&comment.text);
+        self.next += 1;
+        self.after_comment = true;
+    }
+
+    /// Closes a list that ends at `end`, first emitting any comments left inside it.
+    fn close(&mut self, end: usize, indent: usize) {
+        self.close_with(end, indent, ')');
+    }
+
+    fn close_with(&mut self, end: usize, indent: usize, closer: char) {
+        self.flush_comments(end, indent);
+        if self.after_comment {
+            self.newline(indent);
+        }
+        self.out.push(closer);
+    }
+
+    fn has_comment(&self, span: Span) -> bool {
+        self.comments
+            .get(self.next)
+            .is_some_and(|comment| comment.span.start < span.end)
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.out.truncate(self.out.trim_end_matches(' ').len());
+        self.out.push('\n');
+        self.out.push_str(&" ".repeat(indent));
+        self.after_comment = false;
+    }
+
+    fn col(&self) -> usize {
+        let line_start = self.out.rfind('\n').map_or(0, |i| i + 1);
+        self.out[line_start..].chars().count()
+    }
+
+    fn line_is_blank(&self) -> bool {
+        let line_start = self.out.rfind('\n').map_or(0, |i| i + 1);
+        self.out[line_start..].trim().is_empty()
+    }
+}
+
+fn layout(e: &Expr) -> Layout<'_> {
+    fn call<'a>(head: &str, args: Vec<&'a Expr>) -> Layout<'a> {
+        Layout::Call(head.to_string(), args)
+    }
+    match &e.kind {
+        ExprKind::Number(n) => Layout::Atom(n.to_string()),
+        ExprKind::Float(x) => Layout::Atom(float_literal(*x)),
+        ExprKind::Boolean(b) => Layout::Atom(b.to_string()),
+        ExprKind::Var(x) => Layout::Atom(x.to_string()),
+        ExprKind::Input => Layout::Atom("input".to_string()),
+        ExprKind::Nil => Layout::Atom("nil".to_string()),
+        ExprKind::PrintStack => call("snek-printstack", vec![]),
+        ExprKind::PrintHeap => call("snek-printheap", vec![]),
+        ExprKind::Gc => call("gc", vec![]),
+        ExprKind::Args => call("args", vec![]),
+        ExprKind::Let(bindings, body) => Layout::Let(bindings, let_items(body)),
+        ExprKind::UnOp(op, e) => call(op1_name(*op), vec![e]),
+        ExprKind::BinOp(op, e1, e2) => call(op2_name(*op), vec![e1, e2]),
+        ExprKind::If(e1, e2, e3) => call("if", vec![e1, e2, e3]),
+        ExprKind::Loop(e) => Layout::Body("loop".to_string(), vec![Item::Expr(e)]),
+        ExprKind::Break(e) => call("break", vec![e]),
+        ExprKind::Set(x, e) => Layout::Call(format!("set! {x}"), vec![e]),
+        ExprKind::MakeVec(size, elem) => call("make-vec", vec![size, elem]),
+        ExprKind::Vec(es) => Layout::Vec(es.iter().collect()),
+        ExprKind::VecSet(vec, idx, elem) => call("vec-set!", vec![vec, idx, elem]),
+        ExprKind::VecGet(vec, idx) => call("vec-get", vec![vec, idx]),
+        ExprKind::VecLen(vec) => call("vec-len", vec![vec]),
+        ExprKind::Block(es) => Layout::Body("block".to_string(), block_items(es)),
+        // Local functions are only parsed in the bodies above, which lay them out.
+        ExprKind::LocalFuns(..) => {
+            Layout::Body("block".to_string(), block_items(std::slice::from_ref(e)))
+        }
+        ExprKind::Call(fun, args) => match &fun.kind {
+            ExprKind::Var(f) => Layout::Call(f.to_string(), args.iter().collect()),
+            _ => Layout::Apply(fun, args.iter().collect()),
+        },
+        ExprKind::Lambda(params, body) => {
+            let params: Vec<String> = params.iter().map(|param| param.name.to_string()).collect();
+            Layout::Body(
+                format!("lambda ({})", params.join(" ")),
+                vec![Item::Expr(body)],
+            )
+        }
+        ExprKind::Raise(e) => call("raise", vec![e]),
+        ExprKind::Try(body, binding, handler) => Layout::Try(body, *binding, handler),
+        ExprKind::Match(e, arms) => Layout::Match(e, arms),
+        ExprKind::Dict(op, es) => call(op.name(), es.iter().collect()),
+        ExprKind::VecOp(op, es) => call(op.name(), es.iter().collect()),
+        ExprKind::Io(op, es) => call(op.name(), es.iter().collect()),
+        ExprKind::Record(..) => unreachable!("records only appear in generated functions"),
+    }
+}
+
+/// Renders `e` on a single line, or returns `None` if it must be broken: a `block` that sequences
+/// several expressions always puts each on its own line.
+fn flat(e: &Expr) -> Option<String> {
+    let list = |head: &str, es: &[&Expr]| {
+        let mut s = format!("({head}");
+        for e in es {
+            s.push(' ');
+            s.push_str(&flat(e)?);
+        }
+        s.push(')');
+        Some(s)
+    };
+    match layout(e) {
+        Layout::Atom(s) => Some(s),
+        Layout::Call(head, args) => list(&head, &args),
+        Layout::Body(head, body) => match body[..] {
+            [Item::Expr(e)] => list(&head, &[e]),
+            _ => None,
+        },
+        Layout::Apply(fun, args) => list(&flat(fun)?, &args),
+        Layout::Let(bindings, body) => match body[..] {
+            [Item::Expr(body)] => Some(format!(
+                "(let {} {})",
+                flat_bindings(bindings)?,
+                flat(body)?
+            )),
+            _ => None,
+        },
+        Layout::Try(body, binding, handler) => Some(format!(
+            "(try {} (catch {} {}))",
+            flat(body)?,
+            binding.name,
+            flat(handler)?
+        )),
+        Layout::Match(e, arms) => {
+            let mut s = format!("(match {}", flat(e)?);
+            for arm in arms {
+                s.push_str(&format!(
+                    " ({} {})",
+                    pattern(&arm.pattern),
+                    flat(&arm.body)?
+                ));
+            }
+            s.push(')');
+            Some(s)
+        }
+        Layout::Vec(elems) => {
+            let elems = elems.iter().map(|e| flat(e)).collect::<Option<Vec<_>>>()?;
+            Some(format!("[{}]", elems.join(" ")))
+        }
+    }
+}
+
+/// The lines of a `block` made of `es`. Local functions come before the rest of the block, which
+/// is their body.
+fn block_items(es: &[Expr]) -> Vec<Item<'_>> {
+    let mut items = vec![];
+    for e in es {
+        match &e.kind {
+            ExprKind::LocalFuns(funs, body) => {
+                items.extend(funs.iter().map(Item::Fun));
+                match &body.kind {
+                    ExprKind::Block(es) => items.extend(block_items(es)),
+                    _ => items.extend(block_items(std::slice::from_ref(&**body))),
+                }
+            }
+            _ => items.push(Item::Expr(e)),
+        }
+    }
+    items
+}
+
+/// The lines of a `let` body: any local functions, then the expression they are defined for.
+fn let_items(body: &Expr) -> Vec<Item<'_>> {
+    match &body.kind {
+        ExprKind::LocalFuns(funs, body) => funs
+            .iter()
+            .map(Item::Fun)
+            .chain([Item::Expr(body)])
+            .collect(),
+        _ => vec![Item::Expr(body)],
+    }
+}
+
+/// Renders a pattern, writing vector patterns with brackets.
+fn pattern(pat: &Pattern) -> String {
+    match pat {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Var(binding) => binding.name.to_string(),
+        Pattern::Number(n) => n.to_string(),
+        Pattern::Boolean(b) => b.to_string(),
+        Pattern::Nil => "nil".to_string(),
+        Pattern::Vec(pats) => {
+            let pats: Vec<String> = pats.iter().map(pattern).collect();
+            format!("[{}]", pats.join(" "))
+        }
+    }
+}
+
+fn flat_bindings(bindings: &[(Binding, Expr)]) -> Option<String> {
+    let bindings = bindings
+        .iter()
+        .map(|(binding, rhs)| Some(format!("({} {})", binding_name(binding), flat(rhs)?)))
+        .collect::<Option<Vec<_>>>()?;
+    Some(format!("({})", bindings.join(" ")))
+}
+
+/// The name a `let` binding starts with, followed by its annotation if it has one.
+fn binding_name(binding: &Binding) -> String {
+    match binding.ty {
+        Some(ty) => format!("{} : {ty}", binding.name),
+        None => binding.name.to_string(),
+    }
+}
+
+/// `x` as a literal the reader reads back as the same float, which always has a `.`.
+fn float_literal(x: f64) -> String {
+    let s = format!("{x:?}");
+    match s.find('e') {
+        Some(i) if !s[..i].contains('.') => format!("{}.0{}", &s[..i], &s[i..]),
+        _ => s,
+    }
+}
+
+fn op1_name(op: Op1) -> &'static str {
+    match op {
+        Op1::Add1 => "add1",
+        Op1::Sub1 => "sub1",
+        Op1::IsNum => "isnum",
+        Op1::IsFloat => "isfloat",
+        Op1::IsBool => "isbool",
+        Op1::IsVec => "isvec",
+        Op1::Print => "print",
+        Op1::BitNot => "bitwise-not",
+        Op1::Abs => "abs",
+        Op1::ErrorCode => "error-code",
+    }
+}
+
+fn op2_name(op: Op2) -> &'static str {
+    match op {
+        Op2::Plus => "+",
+        Op2::Minus => "-",
+        Op2::Times => "*",
+        Op2::Divide => "/",
+        Op2::Equal => "=",
+        Op2::StructEqual => "equal?",
+        Op2::Greater => ">",
+        Op2::GreaterEqual => ">=",
+        Op2::Less => "<",
+        Op2::LessEqual => "<=",
+        Op2::Mod => "mod",
+        Op2::Remainder => "remainder",
+        Op2::BitAnd => "bitwise-and",
+        Op2::BitOr => "bitwise-or",
+        Op2::BitXor => "bitwise-xor",
+        Op2::ArithShift => "arithmetic-shift",
+        Op2::Min => "min",
+        Op2::Max => "max",
+    }
+}
@@ -0,0 +1,916 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::Warning,
+    syntax::{
+        DictOp, Expr, ExprKind, FunDecl, IoOp, Op1, Op2, Pattern, Prog, RecordOp, Span, Symbol,
+        Type, VecOp,
+    },
+    typeck::{is_assigned, Types},
+};
+
+/// Infers the types of a program without annotations, for `--infer-types`. This is
+/// Hindley-Milner inference: every expression gets a type, possibly with type variables, and the
+/// ways values are used become equations between types that are solved by unification. Top-level
+/// functions are inferred a group of mutually recursive ones at a time, callees first, and are
+/// generic in whatever their uses don't pin down; `let` and `lambda` bindings are not.
+///
+/// Vectors and dictionaries are homogeneous and vectors are never nil, so a linked list of
+/// `[head tail]` vectors ending in nil doesn't type-check. Values only known at run time, like
+/// `input` or a caught exception, are checked to have the scalar type their uses need when they are
+/// read. A program that doesn't type-check gives a warning saying why, and should be compiled
+/// with the types found by [`crate::typeck`] alone.
+pub fn infer(prg: &Prog, struct_funs: &[FunDecl]) -> Result<Types, Warning> {
+    let funs: Vec<&FunDecl> = struct_funs.iter().chain(&prg.funs).collect();
+    let mut inferencer = Inferencer {
+        decls: funs.iter().map(|fun| (fun.name, *fun)).collect(),
+        struct_names: prg.structs.iter().map(|decl| decl.name).collect(),
+        subst: vec![],
+        numeric: vec![],
+        schemes: HashMap::new(),
+        group: HashMap::new(),
+        fields: vec![],
+        globals: HashMap::new(),
+        loops: vec![],
+        types: vec![],
+        fixnums: HashSet::new(),
+        dynamic: vec![],
+    };
+    inferencer.fields = prg
+        .structs
+        .iter()
+        .map(|decl| decl.fields.iter().map(|_| inferencer.fresh()).collect())
+        .collect();
+    for global in &prg.globals {
+        let ty = inferencer.fresh();
+        inferencer.globals.insert(global.name, ty);
+    }
+    for group in components(&funs) {
+        inferencer.infer_group(&group)?;
+    }
+    for global in &prg.globals {
+        let ty = inferencer.infer(&Env::default(), &global.init)?;
+        let var = inferencer.globals[&global.name].clone();
+        inferencer.unify(&ty, &var, global.init.span)?;
+    }
+    inferencer.infer(&Env::default(), &prg.main)?;
+    inferencer.finish()
+}
+
+/// A type being inferred, which may contain type variables.
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    Var(usize),
+    Int,
+    Float,
+    Bool,
+    Nil,
+    Vec(Box<Ty>),
+    /// A function taking arguments of the first types and returning the last.
+    Fun(Vec<Ty>, Box<Ty>),
+    /// A record of the struct with the given type id.
+    Record(usize),
+    /// A dictionary with keys of the first type and values of the second.
+    Dict(Box<Ty>, Box<Ty>),
+}
+
+/// The type of a top-level function, generic in `vars`: each use gets fresh variables for them.
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Ty,
+}
+
+#[derive(Clone)]
+enum Local {
+    /// A variable of the given type, and whether it is known to hold a fixnum.
+    Typed(Ty, bool),
+    /// A variable whose value is only known at run time. Each read of it gets its own type.
+    Dynamic,
+}
+
+type Env = im::HashMap<Symbol, Local>;
+
+/// Why two types can't be made equal.
+enum Mismatch {
+    Types,
+    /// A variable would have to stand for a type containing itself.
+    Infinite,
+}
+
+struct Inferencer<'a> {
+    decls: HashMap<Symbol, &'a FunDecl>,
+    struct_names: Vec<Symbol>,
+    /// What each type variable stands for, once unification has found out.
+    subst: Vec<Option<Ty>>,
+    /// Whether each type variable can only stand for `int` or `float`.
+    numeric: Vec<bool>,
+    /// The generic types of the functions inferred so far.
+    schemes: HashMap<Symbol, Scheme>,
+    /// The types of the functions being inferred together, which their bodies use as they are.
+    group: HashMap<Symbol, Ty>,
+    /// The type of each field of each struct, shared by all records of the struct.
+    fields: Vec<Vec<Ty>>,
+    /// The type of each global, which every function shares.
+    globals: HashMap<Symbol, Ty>,
+    /// The result type of each enclosing loop.
+    loops: Vec<Ty>,
+    types: Vec<(&'a Expr, Ty)>,
+    fixnums: HashSet<*const Expr>,
+    /// Each read of a value only known at run time, with the type it is used as.
+    dynamic: Vec<(&'a Expr, Ty)>,
+}
+
+impl<'a> Inferencer<'a> {
+    fn infer_group(&mut self, group: &[&'a FunDecl]) -> Result<(), Warning> {
+        for fun in group {
+            let params = fun.params.iter().map(|_| self.fresh()).collect();
+            let ty = Ty::Fun(params, Box::new(self.fresh()));
+            self.group.insert(fun.name, ty);
+        }
+        for fun in group {
+            let Ty::Fun(params, ret) = self.group[&fun.name].clone() else {
+                unreachable!()
+            };
+            let mut env = Env::default();
+            for (param, ty) in fun.params.iter().zip(params) {
+                if let Some(annotation) = param.ty {
+                    let expected = self.annotated(annotation);
+                    self.unify(&ty, &expected, param.span)?;
+                }
+                env.insert(param.name, Local::Typed(ty, false));
+            }
+            if let Some(rest) = fun.rest {
+                env.insert(rest.name, Local::Dynamic);
+            }
+            if let Some(annotation) = fun.ret {
+                let expected = self.annotated(annotation);
+                self.unify(&ret, &expected, fun.span)?;
+            }
+            let body = self.infer(&env, &fun.body)?;
+            self.unify(&body, &ret, fun.body.span)?;
+        }
+
+        // Struct fields and globals aren't generic, so neither are the variables in their types.
+        // Nor is the type of a value only known at run time, which has to be resolved by its uses
+        // in every caller to be checked against it.
+        let mut fixed = HashSet::new();
+        let shared: Vec<Ty> = self
+            .fields
+            .iter()
+            .flatten()
+            .chain(self.globals.values())
+            .chain(self.dynamic.iter().map(|(_, ty)| ty))
+            .cloned()
+            .collect();
+        for ty in &shared {
+            self.free_vars(ty, &mut fixed);
+        }
+        for fun in group {
+            let ty = self.group.remove(&fun.name).unwrap();
+            let mut vars = HashSet::new();
+            self.free_vars(&ty, &mut vars);
+            let vars = vars.difference(&fixed).copied().collect();
+            self.schemes.insert(fun.name, Scheme { vars, ty });
+        }
+        Ok(())
+    }
+
+    /// Finds the type of `e` and of all its subexpressions.
+    fn infer(&mut self, env: &Env, e: &'a Expr) -> Result<Ty, Warning> {
+        let ty = match &e.kind {
+            ExprKind::Number(_) => {
+                self.fixnums.insert(e);
+                Ty::Int
+            }
+            ExprKind::Float(_) => Ty::Float,
+            ExprKind::Boolean(_) => Ty::Bool,
+            ExprKind::Nil => Ty::Nil,
+            ExprKind::Input | ExprKind::Args => self.read_dynamic(e),
+            ExprKind::LocalFuns(..) => unreachable!("local functions are lifted before typing"),
+            ExprKind::PrintStack | ExprKind::PrintHeap | ExprKind::Gc => Ty::Int,
+            ExprKind::Var(x) => match env.get(x) {
+                Some(Local::Typed(ty, fixnum)) => {
+                    if *fixnum {
+                        self.fixnums.insert(e);
+                    }
+                    ty.clone()
+                }
+                Some(Local::Dynamic) => self.read_dynamic(e),
+                None => match self.globals.get(x) {
+                    Some(ty) => ty.clone(),
+                    // A name that isn't a variable is a top-level function used as a value.
+                    None => self.fun_type(*x),
+                },
+            },
+            ExprKind::Let(bindings, body) => {
+                let mut env = env.clone();
+                for (i, (binding, rhs)) in bindings.iter().enumerate() {
+                    let ty = self.infer(&env, rhs)?;
+                    if let Some(annotation) = binding.ty {
+                        let expected = self.annotated(annotation);
+                        self.unify(&ty, &expected, rhs.span)?;
+                    }
+                    let scope = bindings[i + 1..].iter().map(|(_, rhs)| rhs);
+                    let fixnum =
+                        self.is_fixnum(rhs) && !is_assigned(binding.name, scope.chain([&**body]));
+                    env.insert(binding.name, Local::Typed(ty, fixnum));
+                }
+                self.infer(&env, body)?
+            }
+            ExprKind::UnOp(op, operand) => match op {
+                Op1::Add1 | Op1::Sub1 => self.number(env, operand)?,
+                Op1::BitNot => {
+                    self.expect(env, operand, &Ty::Int)?;
+                    if self.is_fixnum(operand) {
+                        self.fixnums.insert(e);
+                    }
+                    Ty::Int
+                }
+                Op1::Abs => {
+                    // The absolute value of the smallest fixnum is a bignum.
+                    self.expect(env, operand, &Ty::Int)?;
+                    Ty::Int
+                }
+                Op1::Print => self.infer(env, operand)?,
+                Op1::IsNum | Op1::IsFloat | Op1::IsBool | Op1::IsVec => {
+                    self.infer(env, operand)?;
+                    Ty::Bool
+                }
+                Op1::ErrorCode => {
+                    self.infer(env, operand)?;
+                    self.fixnums.insert(e);
+                    Ty::Int
+                }
+            },
+            ExprKind::BinOp(op, e1, e2) => self.infer_bin_op(env, e, *op, e1, e2)?,
+            ExprKind::If(cond, e2, e3) => {
+                self.infer(env, cond)?;
+                let ty = self.infer(env, e2)?;
+                self.expect(env, e3, &ty)?;
+                ty
+            }
+            ExprKind::Loop(body) => {
+                let ty = self.fresh();
+                self.loops.push(ty.clone());
+                self.infer(env, body)?;
+                self.loops.pop();
+                ty
+            }
+            ExprKind::Break(val) => {
+                let ty = self.loops.last().unwrap().clone();
+                self.expect(env, val, &ty)?;
+                self.fresh()
+            }
+            ExprKind::Raise(val) => {
+                self.infer(env, val)?;
+                self.fresh()
+            }
+            ExprKind::Set(x, rhs) => {
+                let ty = self.infer(env, rhs)?;
+                let var = match env.get(x) {
+                    Some(Local::Typed(var, _)) => Some(var),
+                    Some(Local::Dynamic) => None,
+                    None => self.globals.get(x),
+                };
+                if let Some(var) = var.cloned() {
+                    self.unify(&ty, &var, rhs.span)?;
+                }
+                ty
+            }
+            ExprKind::Block(es) => {
+                let mut ty = Ty::Nil;
+                for e in es {
+                    ty = self.infer(env, e)?;
+                }
+                ty
+            }
+            ExprKind::Call(fun, args) => {
+                let direct = match &fun.kind {
+                    ExprKind::Var(f) if !env.contains_key(f) => self.decls.get(f).copied(),
+                    _ => None,
+                };
+                let fun_ty = match direct {
+                    Some(decl) => self.fun_type(decl.name),
+                    None => self.infer(env, fun)?,
+                };
+                match (direct, fun_ty) {
+                    // Arguments past the fixed parameters go to a rest parameter, which can hold
+                    // anything.
+                    (Some(_), Ty::Fun(params, ret)) => {
+                        for (i, arg) in args.iter().enumerate() {
+                            match params.get(i) {
+                                Some(param) => self.expect(env, arg, param)?,
+                                None => {
+                                    self.infer(env, arg)?;
+                                }
+                            }
+                        }
+                        *ret
+                    }
+                    (_, fun_ty) => {
+                        let mut arg_tys = vec![];
+                        for arg in args {
+                            arg_tys.push(self.infer(env, arg)?);
+                        }
+                        let ret = self.fresh();
+                        let expected = Ty::Fun(arg_tys, Box::new(ret.clone()));
+                        self.unify(&fun_ty, &expected, fun.span)?;
+                        ret
+                    }
+                }
+            }
+            ExprKind::Lambda(params, body) => {
+                let mut env = env.clone();
+                let mut param_tys = vec![];
+                for param in params {
+                    let ty = self.fresh();
+                    env.insert(param.name, Local::Typed(ty.clone(), false));
+                    param_tys.push(ty);
+                }
+                let loops = std::mem::take(&mut self.loops);
+                let ret = self.infer(&env, body)?;
+                self.loops = loops;
+                Ty::Fun(param_tys, Box::new(ret))
+            }
+            ExprKind::Record(op, es) => match (op, &es[..]) {
+                (RecordOp::New(id), fields) => {
+                    for (field, ty) in fields.iter().zip(self.fields[*id].clone()) {
+                        self.expect(env, field, &ty)?;
+                    }
+                    Ty::Record(*id)
+                }
+                (RecordOp::Is(_), [val]) => {
+                    self.infer(env, val)?;
+                    Ty::Bool
+                }
+                (RecordOp::Get(id, i), [record]) => {
+                    self.expect(env, record, &Ty::Record(*id))?;
+                    self.fields[*id][*i].clone()
+                }
+                (RecordOp::Set(id, i), [record, val]) => {
+                    self.expect(env, record, &Ty::Record(*id))?;
+                    let ty = self.fields[*id][*i].clone();
+                    self.expect(env, val, &ty)?;
+                    Ty::Record(*id)
+                }
+                _ => unreachable!("wrong number of operands to a record operation"),
+            },
+            ExprKind::Try(body, binding, handler) => {
+                let ty = self.infer(env, body)?;
+                let env = env.update(binding.name, Local::Dynamic);
+                self.expect(&env, handler, &ty)?;
+                ty
+            }
+            ExprKind::Dict(op, es) => self.infer_dict_op(env, *op, es)?,
+            ExprKind::VecOp(op, es) => self.infer_vec_op(env, *op, es)?,
+            ExprKind::Io(op, es) => match (op, es.as_slice()) {
+                (IoOp::ReadInt, []) => Ty::Int,
+                (IoOp::ReadValue, []) => self.read_dynamic(e),
+                (IoOp::IsEof, []) => Ty::Bool,
+                (IoOp::Display, [val]) => self.infer(env, val)?,
+                (IoOp::Newline, []) => Ty::Nil,
+                _ => unreachable!("wrong number of operands to an I/O operation"),
+            },
+            ExprKind::MakeVec(size, elem) => {
+                self.expect(env, size, &Ty::Int)?;
+                Ty::Vec(Box::new(self.infer(env, elem)?))
+            }
+            ExprKind::Vec(es) => {
+                let elem = self.fresh();
+                for e in es {
+                    self.expect(env, e, &elem)?;
+                }
+                Ty::Vec(Box::new(elem))
+            }
+            ExprKind::VecSet(vec, idx, val) => {
+                let elem = self.elem(env, vec)?;
+                self.expect(env, idx, &Ty::Int)?;
+                self.expect(env, val, &elem)?;
+                Ty::Vec(Box::new(elem))
+            }
+            ExprKind::VecGet(vec, idx) => {
+                let elem = self.elem(env, vec)?;
+                self.expect(env, idx, &Ty::Int)?;
+                elem
+            }
+            ExprKind::VecLen(vec) => {
+                self.elem(env, vec)?;
+                self.fixnums.insert(e);
+                Ty::Int
+            }
+            ExprKind::Match(scrutinee, arms) => {
+                let ty = self.infer(env, scrutinee)?;
+                let result = self.fresh();
+                for arm in arms {
+                    let mut env = env.clone();
+                    self.pattern(&arm.pattern, &ty, &mut env, arm.span)?;
+                    self.expect(&env, &arm.body, &result)?;
+                }
+                result
+            }
+        };
+        self.types.push((e, ty.clone()));
+        Ok(ty)
+    }
+
+    fn infer_bin_op(
+        &mut self,
+        env: &Env,
+        e: &'a Expr,
+        op: Op2,
+        e1: &'a Expr,
+        e2: &'a Expr,
+    ) -> Result<Ty, Warning> {
+        Ok(match op {
+            Op2::Plus | Op2::Minus | Op2::Times | Op2::Divide => {
+                let ty = self.number(env, e1)?;
+                self.expect(env, e2, &ty)?;
+                ty
+            }
+            Op2::Greater | Op2::GreaterEqual | Op2::Less | Op2::LessEqual => {
+                let ty = self.number(env, e1)?;
+                self.expect(env, e2, &ty)?;
+                Ty::Bool
+            }
+            Op2::Mod
+            | Op2::Remainder
+            | Op2::BitAnd
+            | Op2::BitOr
+            | Op2::BitXor
+            | Op2::ArithShift
+            | Op2::Min
+            | Op2::Max => {
+                self.expect(env, e1, &Ty::Int)?;
+                self.expect(env, e2, &Ty::Int)?;
+                // A remainder is smaller than the divisor, and the other operations on two
+                // fixnums give a fixnum, except that shifting left can give a bignum.
+                let (fixnum1, fixnum2) = (self.is_fixnum(e1), self.is_fixnum(e2));
+                let fixnum = match op {
+                    Op2::Mod | Op2::Remainder => fixnum2,
+                    Op2::ArithShift => false,
+                    _ => fixnum1 && fixnum2,
+                };
+                if fixnum {
+                    self.fixnums.insert(e);
+                }
+                Ty::Int
+            }
+            Op2::Equal | Op2::StructEqual => {
+                self.infer(env, e1)?;
+                self.infer(env, e2)?;
+                Ty::Bool
+            }
+        })
+    }
+
+    /// The type of a vector operation. Its vector operands are the ones named `vec`, `dst` and
+    /// `src` in [`VecOp`].
+    fn infer_vec_op(&mut self, env: &Env, op: VecOp, es: &'a [Expr]) -> Result<Ty, Warning> {
+        Ok(match (op, es) {
+            (VecOp::Push, [vec, val]) => {
+                let elem = self.elem(env, vec)?;
+                self.expect(env, val, &elem)?;
+                Ty::Vec(Box::new(elem))
+            }
+            (VecOp::Pop, [vec]) => self.elem(env, vec)?,
+            (VecOp::Slice, [vec, start, end]) => {
+                let elem = self.elem(env, vec)?;
+                self.expect(env, start, &Ty::Int)?;
+                self.expect(env, end, &Ty::Int)?;
+                Ty::Vec(Box::new(elem))
+            }
+            (VecOp::Copy, [dst, dst_start, src, src_start, n]) => {
+                let elem = self.elem(env, dst)?;
+                self.expect(env, dst_start, &Ty::Int)?;
+                let ty = Ty::Vec(Box::new(elem));
+                self.expect(env, src, &ty)?;
+                self.expect(env, src_start, &Ty::Int)?;
+                self.expect(env, n, &Ty::Int)?;
+                ty
+            }
+            (VecOp::Append, [a, b]) => {
+                let ty = Ty::Vec(Box::new(self.elem(env, a)?));
+                self.expect(env, b, &ty)?;
+                ty
+            }
+            _ => unreachable!("wrong number of operands to {}", op.name()),
+        })
+    }
+
+    fn infer_dict_op(&mut self, env: &Env, op: DictOp, es: &'a [Expr]) -> Result<Ty, Warning> {
+        let (key, val) = (self.fresh(), self.fresh());
+        let dict = Ty::Dict(Box::new(key.clone()), Box::new(val.clone()));
+        Ok(match (op, es) {
+            (DictOp::New, by_structure) => {
+                for e in by_structure {
+                    self.infer(env, e)?;
+                }
+                dict
+            }
+            (DictOp::Set, [d, k, v]) => {
+                self.expect(env, d, &dict)?;
+                self.expect(env, k, &key)?;
+                self.expect(env, v, &val)?;
+                dict
+            }
+            (DictOp::Get, [d, k, default]) => {
+                self.expect(env, d, &dict)?;
+                self.expect(env, k, &key)?;
+                self.expect(env, default, &val)?;
+                val
+            }
+            (DictOp::Has, [d, k]) => {
+                self.expect(env, d, &dict)?;
+                self.expect(env, k, &key)?;
+                Ty::Bool
+            }
+            (DictOp::Remove, [d, k]) => {
+                self.expect(env, d, &dict)?;
+                self.expect(env, k, &key)?;
+                dict
+            }
+            (DictOp::Keys, [d]) => {
+                self.expect(env, d, &dict)?;
+                Ty::Vec(Box::new(key))
+            }
+            _ => unreachable!("wrong number of operands to {}", op.name()),
+        })
+    }
+
+    /// Constrains a value of type `ty` to match `pat`, binding the pattern's variables in `env`.
+    fn pattern(
+        &mut self,
+        pat: &Pattern,
+        ty: &Ty,
+        env: &mut Env,
+        span: Span,
+    ) -> Result<(), Warning> {
+        match pat {
+            Pattern::Wildcard => Ok(()),
+            Pattern::Var(binding) => {
+                env.insert(binding.name, Local::Typed(ty.clone(), false));
+                Ok(())
+            }
+            Pattern::Number(_) => self.unify(ty, &Ty::Int, span),
+            Pattern::Boolean(_) => self.unify(ty, &Ty::Bool, span),
+            Pattern::Nil => self.unify(ty, &Ty::Nil, span),
+            Pattern::Vec(pats) => {
+                let elem = self.fresh();
+                self.unify(ty, &Ty::Vec(Box::new(elem.clone())), span)?;
+                for pat in pats {
+                    self.pattern(pat, &elem, env, span)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Finds the type of `e`, which must be `expected`.
+    fn expect(&mut self, env: &Env, e: &'a Expr, expected: &Ty) -> Result<(), Warning> {
+        let found = self.infer(env, e)?;
+        self.unify(&found, expected, e.span)
+    }
+
+    /// Finds the type of `e`, which must be a number.
+    fn number(&mut self, env: &Env, e: &'a Expr) -> Result<Ty, Warning> {
+        let ty = self.fresh();
+        self.numeric[self.subst.len() - 1] = true;
+        self.expect(env, e, &ty)?;
+        Ok(ty)
+    }
+
+    /// Finds the element type of `e`, which must be a vector.
+    fn elem(&mut self, env: &Env, e: &'a Expr) -> Result<Ty, Warning> {
+        let elem = self.fresh();
+        self.expect(env, e, &Ty::Vec(Box::new(elem.clone())))?;
+        Ok(elem)
+    }
+
+    fn is_fixnum(&self, e: &Expr) -> bool {
+        self.fixnums.contains(&(e as *const _))
+    }
+
+    /// The type of a read of a value only known at run time, which is whatever its uses need.
+    fn read_dynamic(&mut self, e: &'a Expr) -> Ty {
+        let ty = self.fresh();
+        self.dynamic.push((e, ty.clone()));
+        ty
+    }
+
+    /// The type of the top-level function `f`, with fresh variables for those it is generic in.
+    fn fun_type(&mut self, f: Symbol) -> Ty {
+        if let Some(ty) = self.group.get(&f) {
+            return ty.clone();
+        }
+        let scheme = &self.schemes[&f];
+        let (vars, ty) = (scheme.vars.clone(), scheme.ty.clone());
+        let mut fresh = HashMap::new();
+        for var in vars {
+            let numeric = self.numeric[self.root(var)];
+            fresh.insert(var, self.fresh());
+            self.numeric[self.subst.len() - 1] = numeric;
+        }
+        self.instantiate(&ty, &fresh)
+    }
+
+    fn instantiate(&self, ty: &Ty, fresh: &HashMap<usize, Ty>) -> Ty {
+        match self.resolve_shallow(ty) {
+            Ty::Var(var) => fresh.get(&var).cloned().unwrap_or(Ty::Var(var)),
+            Ty::Vec(elem) => Ty::Vec(Box::new(self.instantiate(&elem, fresh))),
+            Ty::Fun(params, ret) => Ty::Fun(
+                params
+                    .iter()
+                    .map(|ty| self.instantiate(ty, fresh))
+                    .collect(),
+                Box::new(self.instantiate(&ret, fresh)),
+            ),
+            Ty::Dict(key, val) => Ty::Dict(
+                Box::new(self.instantiate(&key, fresh)),
+                Box::new(self.instantiate(&val, fresh)),
+            ),
+            ty => ty,
+        }
+    }
+
+    fn annotated(&mut self, ty: Type) -> Ty {
+        match ty {
+            Type::Int => Ty::Int,
+            Type::Float => Ty::Float,
+            Type::Bool => Ty::Bool,
+            Type::Nil => Ty::Nil,
+            Type::Vec(elem) => Ty::Vec(Box::new(self.annotated(*elem))),
+            Type::Any | Type::Never => self.fresh(),
+        }
+    }
+
+    fn fresh(&mut self) -> Ty {
+        self.subst.push(None);
+        self.numeric.push(false);
+        Ty::Var(self.subst.len() - 1)
+    }
+
+    /// The variable `var` stands for, following the chain of variables bound to variables.
+    fn root(&self, mut var: usize) -> usize {
+        while let Some(Ty::Var(next)) = &self.subst[var] {
+            var = *next;
+        }
+        var
+    }
+
+    /// `ty` with its outermost variables replaced by what they stand for.
+    fn resolve_shallow(&self, ty: &Ty) -> Ty {
+        match ty {
+            Ty::Var(var) => {
+                let var = self.root(*var);
+                self.subst[var].clone().unwrap_or(Ty::Var(var))
+            }
+            ty => ty.clone(),
+        }
+    }
+
+    /// `ty` with every variable replaced by what it stands for.
+    fn resolve(&self, ty: &Ty) -> Ty {
+        match self.resolve_shallow(ty) {
+            Ty::Vec(elem) => Ty::Vec(Box::new(self.resolve(&elem))),
+            Ty::Fun(params, ret) => Ty::Fun(
+                params.iter().map(|ty| self.resolve(ty)).collect(),
+                Box::new(self.resolve(&ret)),
+            ),
+            Ty::Dict(key, val) => {
+                Ty::Dict(Box::new(self.resolve(&key)), Box::new(self.resolve(&val)))
+            }
+            ty => ty,
+        }
+    }
+
+    fn free_vars(&self, ty: &Ty, out: &mut HashSet<usize>) {
+        match self.resolve_shallow(ty) {
+            Ty::Var(var) => {
+                out.insert(var);
+            }
+            Ty::Vec(elem) => self.free_vars(&elem, out),
+            Ty::Fun(params, ret) => {
+                for ty in params.iter().chain([&*ret]) {
+                    self.free_vars(ty, out);
+                }
+            }
+            Ty::Dict(key, val) => {
+                self.free_vars(&key, out);
+                self.free_vars(&val, out);
+            }
+            Ty::Int | Ty::Float | Ty::Bool | Ty::Nil | Ty::Record(_) => {}
+        }
+    }
+
+    /// Makes `found`, the type of the expression at `span`, equal to `expected`, failing with the
+    /// reason if they can't be.
+    fn unify(&mut self, found: &Ty, expected: &Ty, span: Span) -> Result<(), Warning> {
+        match self.unify_types(found, expected) {
+            Ok(()) => Ok(()),
+            Err(Mismatch::Types) => Err(Warning::InferenceFailed {
+                reason: format!(
+                    "expected {}, found {}",
+                    self.show(expected),
+                    self.show(found)
+                ),
+                span,
+            }),
+            Err(Mismatch::Infinite) => Err(Warning::InferenceFailed {
+                reason:
+                    "this would need a type that contains itself, like a list of nested vectors"
+                        .to_string(),
+                span,
+            }),
+        }
+    }
+
+    fn unify_types(&mut self, a: &Ty, b: &Ty) -> Result<(), Mismatch> {
+        match (self.resolve_shallow(a), self.resolve_shallow(b)) {
+            (Ty::Var(a), Ty::Var(b)) => {
+                if a != b {
+                    self.numeric[b] |= self.numeric[a];
+                    self.subst[a] = Some(Ty::Var(b));
+                }
+                Ok(())
+            }
+            (Ty::Var(var), ty) | (ty, Ty::Var(var)) => self.bind(var, ty),
+            (Ty::Vec(a), Ty::Vec(b)) => self.unify_types(&a, &b),
+            (Ty::Fun(params_a, ret_a), Ty::Fun(params_b, ret_b))
+                if params_a.len() == params_b.len() =>
+            {
+                for (a, b) in params_a.iter().zip(&params_b) {
+                    self.unify_types(a, b)?;
+                }
+                self.unify_types(&ret_a, &ret_b)
+            }
+            (Ty::Dict(key_a, val_a), Ty::Dict(key_b, val_b)) => {
+                self.unify_types(&key_a, &key_b)?;
+                self.unify_types(&val_a, &val_b)
+            }
+            (a, b) if a == b => Ok(()),
+            _ => Err(Mismatch::Types),
+        }
+    }
+
+    fn bind(&mut self, var: usize, ty: Ty) -> Result<(), Mismatch> {
+        if self.numeric[var] && !matches!(ty, Ty::Int | Ty::Float) {
+            return Err(Mismatch::Types);
+        }
+        let mut vars = HashSet::new();
+        self.free_vars(&ty, &mut vars);
+        if vars.contains(&var) {
+            return Err(Mismatch::Infinite);
+        }
+        self.subst[var] = Some(ty);
+        Ok(())
+    }
+
+    fn show(&self, ty: &Ty) -> String {
+        match self.resolve_shallow(ty) {
+            Ty::Var(var) if self.numeric[var] => "a number".to_string(),
+            Ty::Var(_) => "_".to_string(),
+            Ty::Int => "int".to_string(),
+            Ty::Float => "float".to_string(),
+            Ty::Bool => "bool".to_string(),
+            Ty::Nil => "nil".to_string(),
+            Ty::Vec(elem) => format!("(vec {})", self.show(&elem)),
+            Ty::Fun(params, ret) => {
+                let params: Vec<_> = params.iter().map(|ty| self.show(ty)).collect();
+                format!("(fun ({}) {})", params.join(" "), self.show(&ret))
+            }
+            Ty::Record(id) => format!("#{}", self.struct_names[id]),
+            Ty::Dict(key, val) => format!("(dict {} {})", self.show(&key), self.show(&val)),
+        }
+    }
+
+    /// Turns what was found into [`Types`]. Reads of values only known at run time are checked
+    /// where their uses need a scalar type; one used as anything else, like a vector, can't be
+    /// checked by a single tag test, so it fails inference instead.
+    fn finish(self) -> Result<Types, Warning> {
+        let mut casts = HashMap::new();
+        for (e, ty) in &self.dynamic {
+            match self.resolve(ty) {
+                Ty::Var(_) => {}
+                ty @ (Ty::Int | Ty::Float | Ty::Bool | Ty::Nil) => {
+                    casts.insert(*e as *const _, to_type(&ty));
+                }
+                ty => {
+                    return Err(Warning::InferenceFailed {
+                        reason: format!(
+                            "this is only known when the program runs, but is used as {}",
+                            self.show(&ty)
+                        ),
+                        span: e.span,
+                    })
+                }
+            }
+        }
+        let types = self
+            .types
+            .iter()
+            .map(|(e, ty)| (*e as *const _, to_type(&self.resolve(ty))))
+            .filter(|(_, ty)| *ty != Type::Any)
+            .collect();
+        Ok(Types::new(types, casts, self.fixnums))
+    }
+}
+
+/// The [`Type`] of a value of the resolved type `ty`, which only describes scalars and vectors.
+fn to_type(ty: &Ty) -> Type {
+    match ty {
+        Ty::Int => Type::Int,
+        Ty::Float => Type::Float,
+        Ty::Bool => Type::Bool,
+        Ty::Nil => Type::Nil,
+        Ty::Vec(elem) => Type::vec(to_type(elem)),
+        Ty::Var(_) | Ty::Fun(..) | Ty::Record(_) | Ty::Dict(..) => Type::Any,
+    }
+}
+
+/// The groups of mutually recursive functions in `funs`, each after the groups it calls into, as
+/// found by Tarjan's algorithm on the graph of which functions refer to which.
+fn components<'a>(funs: &[&'a FunDecl]) -> Vec<Vec<&'a FunDecl>> {
+    struct Tarjan {
+        edges: Vec<Vec<usize>>,
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        next: usize,
+        components: Vec<Vec<usize>>,
+    }
+
+    impl Tarjan {
+        fn visit(&mut self, v: usize) {
+            self.index[v] = Some(self.next);
+            self.low[v] = self.next;
+            self.next += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+            for w in self.edges[v].clone() {
+                match self.index[w] {
+                    None => {
+                        self.visit(w);
+                        self.low[v] = self.low[v].min(self.low[w]);
+                    }
+                    Some(index) if self.on_stack[w] => self.low[v] = self.low[v].min(index),
+                    Some(_) => {}
+                }
+            }
+            if Some(self.low[v]) == self.index[v] {
+                let mut component = vec![];
+                loop {
+                    let w = self.stack.pop().unwrap();
+                    self.on_stack[w] = false;
+                    component.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                self.components.push(component);
+            }
+        }
+    }
+
+    fn references(e: &Expr, names: &HashMap<Symbol, usize>, out: &mut Vec<usize>) {
+        if let ExprKind::Var(x) = &e.kind {
+            out.extend(names.get(x));
+        }
+        e.for_each_child(|e| references(e, names, out));
+    }
+
+    let names: HashMap<Symbol, usize> = funs
+        .iter()
+        .enumerate()
+        .map(|(i, fun)| (fun.name, i))
+        .collect();
+    // A reference to a name shadowed by a local only adds an edge that isn't needed, which can
+    // merge groups but never splits one.
+    let edges = funs
+        .iter()
+        .map(|fun| {
+            let mut out = vec![];
+            references(&fun.body, &names, &mut out);
+            out
+        })
+        .collect();
+    let mut tarjan = Tarjan {
+        edges,
+        index: vec![None; funs.len()],
+        low: vec![0; funs.len()],
+        stack: vec![],
+        on_stack: vec![false; funs.len()],
+        next: 0,
+        components: vec![],
+    };
+    for v in 0..funs.len() {
+        if tarjan.index[v].is_none() {
+            tarjan.visit(v);
+        }
+    }
+    tarjan
+        .components
+        .into_iter()
+        .map(|component| component.into_iter().map(|v| funs[v]).collect())
+        .collect()
+}
@@ -0,0 +1,15 @@
+//! The snek compiler as a library. Tools that embed it can call [`parser::parse`] and
+//! [`compiler::compile`] directly and match on the [`error::CompileError`] they return.
+
+mod asm;
+mod check;
+pub mod compiler;
+mod diagnostic;
+pub mod error;
+pub mod formatter;
+mod infer;
+mod lift;
+pub mod parser;
+mod reader;
+pub mod syntax;
+mod typeck;
@@ -0,0 +1,266 @@
+use crate::syntax::{Binding, Expr, ExprKind, FunDecl, Pattern, Prog, Span, Symbol};
+
+/// Turns every local function into a top-level one, so that later passes never see
+/// [`ExprKind::LocalFuns`]. A lifted function takes the variables it uses from around its
+/// definition as extra parameters before its own, and every call passes them along; a local
+/// function used as a value becomes a lambda that does the same. The functions of a group can call
+/// each other, so they all take the variables any of them needs.
+///
+/// To keep those variables from being shadowed at a call site, every variable is renamed to a
+/// unique name first, and each lifted function gets one too. Both contain a `.`, which names in the
+/// source can't. The program must have passed [`crate::check`], which also makes sure no local
+/// function assigns a variable it captured, since it only gets a copy.
+pub fn lift(prg: &Prog) -> Prog {
+    let mut lifter = Lifter {
+        funs: vec![],
+        next: 0,
+    };
+    let mut funs: Vec<FunDecl> = prg
+        .funs
+        .iter()
+        .map(|fun| lifter.lift_fun(&Scope::default(), fun.name, &[], fun))
+        .collect();
+    let mut globals = prg.globals.clone();
+    for global in &mut globals {
+        lifter.lift_expr(&Scope::default(), &mut global.init);
+    }
+    let mut main = prg.main.clone();
+    lifter.lift_expr(&Scope::default(), &mut main);
+    funs.extend(lifter.funs);
+    Prog {
+        structs: prg.structs.clone(),
+        funs,
+        globals,
+        main,
+    }
+}
+
+#[derive(Clone)]
+enum Name {
+    /// A variable, renamed to the given name.
+    Var(Symbol),
+    /// A local function, lifted to the top-level function `lifted`, which takes the variables
+    /// `captured` before its own `arity` parameters.
+    Fun {
+        lifted: Symbol,
+        captured: Vec<Symbol>,
+        arity: usize,
+    },
+}
+
+type Scope = im::HashMap<Symbol, Name>;
+
+struct Lifter {
+    /// The lifted functions.
+    funs: Vec<FunDecl>,
+    /// The number of names made so far, which keeps them unique.
+    next: usize,
+}
+
+impl Lifter {
+    /// `fun` renamed to `name`, taking `captured` before its own parameters, with its body lifted.
+    fn lift_fun(
+        &mut self,
+        scope: &Scope,
+        name: Symbol,
+        captured: &[Symbol],
+        fun: &FunDecl,
+    ) -> FunDecl {
+        let mut scope = scope.clone();
+        let mut params: Vec<Binding> = captured
+            .iter()
+            .map(|x| Binding {
+                name: *x,
+                ty: None,
+                span: fun.span,
+            })
+            .collect();
+        params.extend(fun.params.iter().map(|param| self.bind(&mut scope, *param)));
+        let rest = fun.rest.map(|rest| self.bind(&mut scope, rest));
+        let mut body = fun.body.clone();
+        self.lift_expr(&scope, &mut body);
+        FunDecl {
+            name,
+            params,
+            rest,
+            ret: fun.ret,
+            body,
+            span: fun.span,
+        }
+    }
+
+    fn lift_expr(&mut self, scope: &Scope, e: &mut Expr) {
+        match &mut e.kind {
+            ExprKind::Var(x) => match scope.get(x) {
+                Some(Name::Var(renamed)) => *x = *renamed,
+                Some(Name::Fun {
+                    lifted,
+                    captured,
+                    arity,
+                }) => *e = self.fun_value(*lifted, captured, *arity, e.span),
+                None => {}
+            },
+            ExprKind::Set(x, rhs) => {
+                if let Some(Name::Var(renamed)) = scope.get(x) {
+                    *x = *renamed;
+                }
+                self.lift_expr(scope, rhs);
+            }
+            ExprKind::Call(fun, args) => {
+                for arg in args.iter_mut() {
+                    self.lift_expr(scope, arg);
+                }
+                match &mut fun.kind {
+                    ExprKind::Var(f) => match scope.get(f) {
+                        Some(Name::Fun {
+                            lifted, captured, ..
+                        }) => {
+                            *f = *lifted;
+                            let captured = captured.iter().map(|x| var(*x, fun.span));
+                            args.splice(0..0, captured);
+                        }
+                        _ => self.lift_expr(scope, fun),
+                    },
+                    _ => self.lift_expr(scope, fun),
+                }
+            }
+            ExprKind::Let(bindings, body) => {
+                let mut scope = scope.clone();
+                for (binding, rhs) in bindings {
+                    self.lift_expr(&scope, rhs);
+                    *binding = self.bind(&mut scope, *binding);
+                }
+                self.lift_expr(&scope, body);
+            }
+            ExprKind::Lambda(params, body) => {
+                let mut scope = scope.clone();
+                for param in params {
+                    *param = self.bind(&mut scope, *param);
+                }
+                self.lift_expr(&scope, body);
+            }
+            ExprKind::Try(body, binding, handler) => {
+                self.lift_expr(scope, body);
+                let mut scope = scope.clone();
+                *binding = self.bind(&mut scope, *binding);
+                self.lift_expr(&scope, handler);
+            }
+            ExprKind::Match(scrutinee, arms) => {
+                self.lift_expr(scope, scrutinee);
+                for arm in arms {
+                    let mut scope = scope.clone();
+                    self.bind_pattern(&mut scope, &mut arm.pattern);
+                    self.lift_expr(&scope, &mut arm.body);
+                }
+            }
+            ExprKind::LocalFuns(funs, body) => {
+                let captured = self.captured(scope, funs);
+                let mut scope = scope.clone();
+                let mut lifted = vec![];
+                for fun in funs.iter() {
+                    let name = self.fresh(fun.name);
+                    lifted.push(name);
+                    let arity = fun.params.len();
+                    let captured = captured.clone();
+                    scope.insert(
+                        fun.name,
+                        Name::Fun {
+                            lifted: name,
+                            captured,
+                            arity,
+                        },
+                    );
+                }
+                for (fun, name) in funs.iter().zip(lifted) {
+                    let fun = self.lift_fun(&scope, name, &captured, fun);
+                    self.funs.push(fun);
+                }
+                let mut body = std::mem::replace(&mut **body, Expr::new(ExprKind::Nil, e.span));
+                self.lift_expr(&scope, &mut body);
+                *e = body;
+            }
+            _ => e.for_each_child_mut(|e| self.lift_expr(scope, e)),
+        }
+    }
+
+    /// The renamed variables a group of local functions uses from `scope`, including those the
+    /// enclosing local functions it calls capture.
+    fn captured(&self, scope: &Scope, funs: &[FunDecl]) -> Vec<Symbol> {
+        let names = funs
+            .iter()
+            .fold(im::HashSet::new(), |names, fun| names.update(fun.name));
+        let mut free = vec![];
+        for fun in funs {
+            let bound = fun
+                .all_params()
+                .iter()
+                .fold(names.clone(), |bound, param| bound.update(param.name));
+            fun.body.free_vars(&bound, &mut free);
+        }
+
+        let mut captured = vec![];
+        for x in free {
+            let vars = match scope.get(&x) {
+                Some(Name::Var(renamed)) => vec![*renamed],
+                Some(Name::Fun { captured, .. }) => captured.clone(),
+                None => vec![],
+            };
+            for var in vars {
+                if !captured.contains(&var) {
+                    captured.push(var);
+                }
+            }
+        }
+        captured
+    }
+
+    /// A local function used as a value: a lambda that calls its lifted function with the
+    /// variables it captured, or just the lifted function if there are none.
+    fn fun_value(&mut self, lifted: Symbol, captured: &[Symbol], arity: usize, span: Span) -> Expr {
+        if captured.is_empty() {
+            return var(lifted, span);
+        }
+        let params: Vec<Binding> = (0..arity)
+            .map(|_| Binding {
+                name: self.fresh(Symbol::new("arg")),
+                ty: None,
+                span,
+            })
+            .collect();
+        let args = captured
+            .iter()
+            .chain(params.iter().map(|param| &param.name))
+            .map(|x| var(*x, span))
+            .collect();
+        let call = Expr::new(ExprKind::Call(Box::new(var(lifted, span)), args), span);
+        Expr::new(ExprKind::Lambda(params, Box::new(call)), span)
+    }
+
+    /// Adds `binding` to `scope` under a fresh name, returning it renamed.
+    fn bind(&mut self, scope: &mut Scope, binding: Binding) -> Binding {
+        let name = self.fresh(binding.name);
+        scope.insert(binding.name, Name::Var(name));
+        Binding { name, ..binding }
+    }
+
+    fn bind_pattern(&mut self, scope: &mut Scope, pat: &mut Pattern) {
+        match pat {
+            Pattern::Var(binding) => *binding = self.bind(scope, *binding),
+            Pattern::Vec(pats) => {
+                for pat in pats {
+                    self.bind_pattern(scope, pat);
+                }
+            }
+            Pattern::Wildcard | Pattern::Number(_) | Pattern::Boolean(_) | Pattern::Nil => {}
+        }
+    }
+
+    fn fresh(&mut self, name: Symbol) -> Symbol {
+        self.next += 1;
+        Symbol::new(format!("{name}.{}", self.next))
+    }
+}
+
+fn var(x: Symbol, span: Span) -> Expr {
+    Expr::new(ExprKind::Var(x), span)
+}
@@ -1,26 +1,99 @@
-use std::{
-    env,
-    fs::File,
-    io::{self, Read, Write},
-};
+use std::{env, fs, process};
 
-mod asm;
-mod compiler;
-mod parser;
-mod syntax;
+use forest_flame::{
+    compiler::{self, Options},
+    error::CompileError,
+    formatter, parser,
+};
 
-fn main() -> io::Result<()> {
+fn main() {
     let args: Vec<String> = env::args().collect();
-    let in_name = &args[1];
-    let out_name = &args[2];
-    let mut in_contents = String::new();
-    let mut in_file = File::open(in_name)?;
-    in_file.read_to_string(&mut in_contents)?;
-    let expr = parser::parse(&in_contents);
-    let asm = compiler::compile(&expr);
-
-    let mut out_file = File::create(out_name)?;
-    out_file.write_all(asm.as_bytes())?;
-
-    Ok(())
+    match &args[1..] {
+        [cmd, rest @ ..] if cmd == "fmt" => fmt(&args[0], rest),
+        [flag, in_name, out_name] if flag == "--infer-types" => {
+            let options = Options { infer_types: true };
+            compile(in_name, out_name, options)
+        }
+        [in_name, out_name] => compile(in_name, out_name, Options::default()),
+        _ => usage(&args[0]),
+    }
+}
+
+fn usage(prog: &str) -> ! {
+    eprintln!("usage: {prog} [--infer-types] <input.snek> <output.s>");
+    eprintln!("       {prog} fmt [--check | --in-place] <file.snek>...");
+    process::exit(2);
+}
+
+fn compile(in_name: &str, out_name: &str, options: Options) {
+    let in_contents = read_source(in_name);
+    let (asm, warnings) = parser::parse(&in_contents)
+        .and_then(|prog| compiler::compile_with(&prog, options))
+        .unwrap_or_else(|errs| report(in_name, &in_contents, &errs));
+    for warning in warnings {
+        eprintln!("{}\n", warning.render(in_name, &in_contents));
+    }
+
+    if let Err(err) = fs::write(out_name, asm) {
+        eprintln!("error: could not write {out_name}: {err}");
+        process::exit(2);
+    }
+}
+
+/// `fmt` prints each file formatted to stdout. With `--in-place` the files are rewritten instead,
+/// and with `--check` nothing is written but the exit status is 1 if any file isn't formatted.
+fn fmt(prog: &str, args: &[String]) {
+    let (mut check, mut in_place, mut files) = (false, false, vec![]);
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            "-i" | "--in-place" => in_place = true,
+            flag if flag.starts_with('-') => usage(prog),
+            file => files.push(file),
+        }
+    }
+    if files.is_empty() || (check && in_place) {
+        usage(prog);
+    }
+
+    let mut unformatted = false;
+    for file in files {
+        let src = read_source(file);
+        let formatted = formatter::format(&src).unwrap_or_else(|errs| report(file, &src, &errs));
+        if check {
+            if formatted != src {
+                eprintln!("{file} is not formatted");
+                unformatted = true;
+            }
+        } else if in_place {
+            if formatted != src {
+                if let Err(err) = fs::write(file, formatted) {
+                    eprintln!("error: could not write {file}: {err}");
+                    process::exit(2);
+                }
+            }
+        } else {
+            print!("{formatted}");
+        }
+    }
+    if unformatted {
+        process::exit(1);
+    }
+}
+
+fn read_source(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("error: could not read {path}: {err}");
+        process::exit(2);
+    })
+}
+
+fn report(path: &str, src: &str, errs: &[CompileError]) -> ! {
+    for err in errs {
+        eprintln!("{}\n", err.render(path, src));
+    }
+    if errs.len() > 1 {
+        eprintln!("error: aborting due to {} previous errors", errs.len());
+    }
+    process::exit(1);
 }
@@ -1,12 +1,26 @@
 use regex::Regex;
-use sexp::{Atom::*, Sexp};
 
-use crate::syntax::{Expr, FunDecl, Op1, Op2, Prog, Symbol};
+use crate::{
+    error::CompileError,
+    reader::{self, Atom::*, Sexp},
+    syntax::{
+        Binding, DictOp, Expr, ExprKind, FunDecl, GlobalDecl, IoOp, MatchArm, Op1, Op2, Pattern,
+        Prog, Span, StructDecl, Symbol, Type, VecOp,
+    },
+};
 
-pub fn parse(s: &str) -> Prog {
-    let s = format!("({})", s);
-    let s = sexp::parse(&s).unwrap_or_else(|_| syntax_error("invalid s-expr"));
-    Parser::new().parse_prog(&s)
+/// Parses a whole program. Every top-level form is parsed even if an earlier one is malformed, and
+/// all the errors found are returned sorted by position.
+pub fn parse(s: &str) -> Result<Prog, Vec<CompileError>> {
+    let (forms, mut errors) = reader::read(s);
+    let prog = Parser::new().parse_prog(&forms, Span::new(s.len(), s.len()), &mut errors);
+    match prog {
+        Some(prog) if errors.is_empty() => Ok(prog),
+        _ => {
+            errors.sort_by_key(|err| err.span().start);
+            Err(errors)
+        }
+    }
 }
 
 struct Parser {
@@ -16,179 +30,339 @@ struct Parser {
 impl Parser {
     fn new() -> Parser {
         Parser {
-            id_regex: Regex::new(r"^[a-zA-Z][a-zA-Z0-9_-]*$").unwrap(),
+            id_regex: Regex::new(r"^[a-zA-Z][a-zA-Z0-9_-]*[?!]?$").unwrap(),
         }
     }
 
-    fn parse_prog(&self, e: &Sexp) -> Prog {
-        let Sexp::List(es) = e else{
-            syntax_error("expected a list")
+    fn parse_prog(&self, es: &[Sexp], eof: Span, errors: &mut Vec<CompileError>) -> Option<Prog> {
+        let (decls, main) = match es {
+            [decls @ .., main] if !is_decl_form(main) => (decls, Some(main)),
+            _ => (es, None),
         };
-        if let [funcs @ .., main] = &es[..] {
-            let funcs = funcs.iter().map(|e| self.parse_func(e)).collect();
-            let main = self.parse_expr(main);
-            Prog { funs: funcs, main }
-        } else {
-            syntax_error("program must contain a main expression")
+        let (mut structs, mut funs, mut globals) = (vec![], vec![], vec![]);
+        for e in decls {
+            let parsed = if is_form(e, "struct") {
+                self.parse_struct(e).map(|decl| structs.push(decl))
+            } else if is_form(e, "define") {
+                self.parse_define(e).map(|decl| globals.push(decl))
+            } else {
+                self.parse_func(e).map(|fun| funs.push(fun))
+            };
+            if let Err(err) = parsed {
+                errors.push(err);
+            }
         }
+        let main = match main.map(|e| self.parse_expr(e)) {
+            Some(Ok(main)) => main,
+            Some(Err(err)) => {
+                errors.push(err);
+                return None;
+            }
+            None => {
+                // If the reader gave up on a form, the main expression may well have been in it.
+                if errors.is_empty() {
+                    errors.push(CompileError::malformed(
+                        eof,
+                        "program must contain a main expression",
+                    ));
+                }
+                return None;
+            }
+        };
+        Some(Prog {
+            structs,
+            funs,
+            globals,
+            main,
+        })
     }
 
-    fn parse_expr(&self, e: &Sexp) -> Expr {
-        match e {
-            &Sexp::Atom(I(n)) => {
-                if n >= -4611686018427387904 && n < 4611686018427387904 {
-                    Expr::Number(n)
+    fn parse_expr(&self, e: &Sexp) -> Result<Expr, CompileError> {
+        let span = e.span();
+        let kind = match e {
+            &Sexp::Atom(I(n), _) => {
+                if (-4611686018427387904..4611686018427387904).contains(&n) {
+                    ExprKind::Number(n)
                 } else {
-                    syntax_error("integer literal overflow")
+                    return Err(CompileError::IntegerOverflow { span });
                 }
             }
-            Sexp::Atom(S(id)) => match id.as_str() {
-                "true" => Expr::Boolean(true),
-                "false" => Expr::Boolean(false),
-                "input" => Expr::Input,
-                "nil" => Expr::Nil,
-                _ => Expr::Var(Symbol::new(id)),
+            &Sexp::Atom(F(x), _) => ExprKind::Float(x),
+            Sexp::Atom(S(id), _) => match id.as_str() {
+                "true" => ExprKind::Boolean(true),
+                "false" => ExprKind::Boolean(false),
+                "input" => ExprKind::Input,
+                "nil" => ExprKind::Nil,
+                _ => ExprKind::Var(Symbol::new(id)),
             },
-            Sexp::List(vec) => match &vec[..] {
+            Sexp::List(vec, _) => match &vec[..] {
                 // (snek-printstack)
-                [Sexp::Atom(S(keyword)), es @ ..] if keyword == "snek-printstack" => {
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "snek-printstack" => {
+                    if !es.is_empty() {
+                        return syntax_error(span, "snek-prinstack doesn't take any arguments");
+                    }
+                    ExprKind::PrintStack
+                }
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "snek-printheap" => {
                     if !es.is_empty() {
-                        return syntax_error("snek-prinstack doesn't take any arguments");
+                        return syntax_error(span, "snek-prinstack doesn't take any arguments");
                     }
-                    Expr::PrintStack
+                    ExprKind::PrintHeap
                 }
-                [Sexp::Atom(S(keyword)), es @ ..] if keyword == "snek-printheap" => {
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "args" => {
                     if !es.is_empty() {
-                        return syntax_error("snek-prinstack doesn't take any arguments");
+                        return syntax_error(span, "args doesn't take any arguments");
                     }
-                    Expr::PrintHeap
+                    ExprKind::Args
                 }
                 // (gc)
-                [Sexp::Atom(S(keyword)), es @ ..] if keyword == "gc" => {
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "gc" => {
                     if !es.is_empty() {
-                        return syntax_error("gc doesn't take any arguments");
+                        return syntax_error(span, "gc doesn't take any arguments");
                     }
-                    Expr::Gc
+                    ExprKind::Gc
                 }
                 // (make-vec size elem)
-                [Sexp::Atom(S(keyword)), es @ ..] if keyword == "make-vec" => {
-                    let [size, elem] = &es[..] else {
-                        return syntax_error("malformed vec");
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "make-vec" => {
+                    let [size, elem] = es else {
+                        return syntax_error(span, "malformed vec");
                     };
-                    let size = self.parse_expr(size);
-                    let elem = self.parse_expr(elem);
-                    Expr::MakeVec(Box::new(size), Box::new(elem))
+                    let size = self.parse_expr(size)?;
+                    let elem = self.parse_expr(elem)?;
+                    ExprKind::MakeVec(Box::new(size), Box::new(elem))
                 }
                 // (vec elem*)
-                [Sexp::Atom(S(keyword)), es @ ..] if keyword == "vec" => {
-                    Expr::Vec(es.iter().map(|e| self.parse_expr(e)).collect())
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "vec" => {
+                    ExprKind::Vec(self.parse_exprs(es)?)
                 }
                 // (vec-set! idx elem)
-                [Sexp::Atom(S(keyword)), es @ ..] if keyword == "vec-set!" => {
-                    let [vec, size, elem] = &es[..] else {
-                        return syntax_error("malformed vec-set!");
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "vec-set!" => {
+                    let [vec, size, elem] = es else {
+                        return syntax_error(span, "malformed vec-set!");
                     };
-                    let vec = self.parse_expr(vec);
-                    let idx = self.parse_expr(size);
-                    let elem = self.parse_expr(elem);
-                    Expr::VecSet(Box::new(vec), Box::new(idx), Box::new(elem))
+                    let vec = self.parse_expr(vec)?;
+                    let idx = self.parse_expr(size)?;
+                    let elem = self.parse_expr(elem)?;
+                    ExprKind::VecSet(Box::new(vec), Box::new(idx), Box::new(elem))
                 }
                 // (vec-get idx elem)
-                [Sexp::Atom(S(keyword)), es @ ..] if keyword == "vec-get" => {
-                    let [vec, idx] = &es[..] else {
-                        return syntax_error("malformed vec-get");
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "vec-get" => {
+                    let [vec, idx] = es else {
+                        return syntax_error(span, "malformed vec-get");
                     };
-                    let vec = self.parse_expr(vec);
-                    let idx = self.parse_expr(idx);
-                    Expr::VecGet(Box::new(vec), Box::new(idx))
+                    let vec = self.parse_expr(vec)?;
+                    let idx = self.parse_expr(idx)?;
+                    ExprKind::VecGet(Box::new(vec), Box::new(idx))
                 }
                 // (vec-len vec)
-                [Sexp::Atom(S(keyword)), es @ ..] if keyword == "vec-len" => {
-                    let [vec] = &es[..] else {
-                        return syntax_error("malformed vec-len");
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "vec-len" => {
+                    let [vec] = es else {
+                        return syntax_error(span, "malformed vec-len");
                     };
-                    let vec = self.parse_expr(vec);
-                    Expr::VecLen(Box::new(vec))
+                    let vec = self.parse_expr(vec)?;
+                    ExprKind::VecLen(Box::new(vec))
                 }
                 // Block
-                [Sexp::Atom(S(keyword)), es @ ..] if keyword == "block" => {
-                    let es: Vec<_> = es.iter().map(|e| self.parse_expr(e)).collect();
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "block" => {
+                    let es = self.parse_block(es)?;
                     if !es.is_empty() {
-                        Expr::Block(es)
+                        ExprKind::Block(es)
                     } else {
-                        syntax_error("blocks must contain at least one expression")
+                        return syntax_error(span, "blocks must contain at least one expression");
                     }
                 }
 
-                // (let <bindings> <expr>)
-                [Sexp::Atom(S(keyword)), es @ ..] if keyword == "let" => {
-                    let [e1, e2] = &es[..] else {
-                        return syntax_error("malformed let");
+                // (let <bindings> <fun>* <expr>)
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "let" => {
+                    let [e1, funs @ .., e2] = es else {
+                        return syntax_error(span, "malformed let");
                     };
+                    if !funs.iter().all(|e| is_form(e, "fun")) {
+                        return syntax_error(span, "malformed let");
+                    }
                     match e1 {
-                        Sexp::List(bindings) => {
+                        Sexp::List(bindings, _) => {
                             if bindings.is_empty() {
-                                return syntax_error("empty bindings");
+                                return syntax_error(e1.span(), "empty bindings");
                             }
-                            let bindings: Vec<_> =
-                                bindings.iter().map(|e| self.parse_binding(e)).collect();
-                            let body = self.parse_expr(e2);
-                            Expr::Let(bindings, Box::new(body))
+                            let bindings = bindings
+                                .iter()
+                                .map(|e| self.parse_binding(e))
+                                .collect::<Result<_, _>>()?;
+                            let body = self.parse_local_funs(funs, e2)?;
+                            ExprKind::Let(bindings, Box::new(body))
                         }
-                        _ => syntax_error("invalid let expr"),
+                        _ => return syntax_error(e1.span(), "invalid let expr"),
+                    }
+                }
+
+                // (lambda (<param>*) <expr>)
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "lambda" => {
+                    let [Sexp::List(params, _), body] = es else {
+                        return syntax_error(span, "malformed lambda");
+                    };
+                    let params = params
+                        .iter()
+                        .map(|e| self.parse_param(e))
+                        .collect::<Result<_, _>>()?;
+                    let body = self.parse_expr(body)?;
+                    ExprKind::Lambda(params, Box::new(body))
+                }
+
+                // (try <expr> (catch <name> <expr>))
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "try" => {
+                    let [body, Sexp::List(catch, _)] = es else {
+                        return syntax_error(span, "malformed try");
+                    };
+                    let [Sexp::Atom(S(catch_kw), _), name, handler] = &catch[..] else {
+                        return syntax_error(span, "malformed catch");
+                    };
+                    if catch_kw != "catch" {
+                        return syntax_error(span, "malformed catch");
+                    }
+                    let body = self.parse_expr(body)?;
+                    let name = self.parse_param(name)?;
+                    let handler = self.parse_expr(handler)?;
+                    ExprKind::Try(Box::new(body), name, Box::new(handler))
+                }
+
+                // (match <expr> (<pattern> <expr>)+)
+                [Sexp::Atom(S(keyword), _), e, arms @ ..] if keyword == "match" => {
+                    if arms.is_empty() {
+                        return syntax_error(span, "malformed match");
+                    }
+                    let e = self.parse_expr(e)?;
+                    let arms = arms
+                        .iter()
+                        .map(|arm| match arm {
+                            Sexp::List(es, span) if es.len() == 2 => Ok(MatchArm {
+                                pattern: self.parse_pattern(&es[0])?,
+                                body: self.parse_expr(&es[1])?,
+                                span: *span,
+                            }),
+                            _ => syntax_error(arm.span(), "malformed match arm"),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    ExprKind::Match(Box::new(e), arms)
+                }
+
+                // (make-dict [<expr>]), (dict-get <expr> <expr> <expr>), ...
+                [Sexp::Atom(S(keyword), _), es @ ..]
+                    if DictOp::ALL.iter().any(|op| op.name() == keyword) =>
+                {
+                    let op = *DictOp::ALL.iter().find(|op| op.name() == keyword).unwrap();
+                    let (min, max) = op.arity();
+                    if es.len() < min || es.len() > max {
+                        return syntax_error(span, format!("malformed {keyword}"));
+                    }
+                    ExprKind::Dict(op, self.parse_exprs(es)?)
+                }
+
+                // (vec-push! <expr> <expr>), (vec-slice <expr> <expr> <expr>), ...
+                [Sexp::Atom(S(keyword), _), es @ ..]
+                    if VecOp::ALL.iter().any(|op| op.name() == keyword) =>
+                {
+                    let op = *VecOp::ALL.iter().find(|op| op.name() == keyword).unwrap();
+                    if es.len() != op.arity() {
+                        return syntax_error(span, format!("malformed {keyword}"));
                     }
+                    ExprKind::VecOp(op, self.parse_exprs(es)?)
+                }
+
+                // (read-int), (display <expr>), ...
+                [Sexp::Atom(S(keyword), _), es @ ..]
+                    if IoOp::ALL.iter().any(|op| op.name() == keyword) =>
+                {
+                    let op = *IoOp::ALL.iter().find(|op| op.name() == keyword).unwrap();
+                    if es.len() != op.arity() {
+                        return syntax_error(span, format!("malformed {keyword}"));
+                    }
+                    ExprKind::Io(op, self.parse_exprs(es)?)
                 }
 
                 // set! <name> <expr> => Set
-                [Sexp::Atom(S(keyword)), Sexp::Atom(S(id)), e] if keyword == "set!" => {
-                    let e = self.parse_expr(e);
-                    Expr::Set(Symbol::new(id), Box::new(e))
+                [Sexp::Atom(S(keyword), _), Sexp::Atom(S(id), _), e] if keyword == "set!" => {
+                    let e = self.parse_expr(e)?;
+                    ExprKind::Set(Symbol::new(id), Box::new(e))
                 }
 
                 // if <expr> <expr> <expr> => If
-                [Sexp::Atom(S(keyword)), es @ ..] if keyword == "if" => {
-                    let [e1, e2, e3] = &es[..] else {
-                        syntax_error("malformed if")
+                [Sexp::Atom(S(keyword), _), es @ ..] if keyword == "if" => {
+                    let [e1, e2, e3] = es else {
+                        return syntax_error(span, "malformed if");
                     };
-                    let e1 = self.parse_expr(e1);
-                    let e2 = self.parse_expr(e2);
-                    let e3 = self.parse_expr(e3);
+                    let e1 = self.parse_expr(e1)?;
+                    let e2 = self.parse_expr(e2)?;
+                    let e3 = self.parse_expr(e3)?;
 
-                    Expr::If(Box::new(e1), Box::new(e2), Box::new(e3))
+                    ExprKind::If(Box::new(e1), Box::new(e2), Box::new(e3))
                 }
 
-                [Sexp::Atom(S(keyword)), es @ ..]
+                [Sexp::Atom(S(keyword), _), es @ ..]
                     if matches!(
                         &keyword[..],
-                        "loop" | "break" | "add1" | "sub1" | "isnum" | "isbool" | "isvec" | "print"
+                        "loop"
+                            | "break"
+                            | "add1"
+                            | "sub1"
+                            | "isnum"
+                            | "isfloat"
+                            | "isbool"
+                            | "isvec"
+                            | "print"
+                            | "bitwise-not"
+                            | "abs"
+                            | "error-code"
+                            | "raise"
                     ) =>
                 {
                     let [e] = es else {
-                        return syntax_error("expected a single expression after keyword");
+                        return syntax_error(span, "expected a single expression after keyword");
                     };
-                    let e_expr = self.parse_expr(e);
+                    let e_expr = self.parse_expr(e)?;
 
                     match keyword.as_str() {
-                        "loop" => Expr::Loop(Box::new(e_expr)),
-                        "break" => Expr::Break(Box::new(e_expr)),
-                        "print" => Expr::UnOp(Op1::Print, Box::new(e_expr)),
-                        "add1" => Expr::UnOp(Op1::Add1, Box::new(e_expr)),
-                        "sub1" => Expr::UnOp(Op1::Sub1, Box::new(e_expr)),
-                        "isnum" => Expr::UnOp(Op1::IsNum, Box::new(e_expr)),
-                        "isbool" => Expr::UnOp(Op1::IsBool, Box::new(e_expr)),
-                        "isvec" => Expr::UnOp(Op1::IsVec, Box::new(e_expr)),
+                        "loop" => ExprKind::Loop(Box::new(e_expr)),
+                        "break" => ExprKind::Break(Box::new(e_expr)),
+                        "print" => ExprKind::UnOp(Op1::Print, Box::new(e_expr)),
+                        "add1" => ExprKind::UnOp(Op1::Add1, Box::new(e_expr)),
+                        "sub1" => ExprKind::UnOp(Op1::Sub1, Box::new(e_expr)),
+                        "isnum" => ExprKind::UnOp(Op1::IsNum, Box::new(e_expr)),
+                        "isfloat" => ExprKind::UnOp(Op1::IsFloat, Box::new(e_expr)),
+                        "isbool" => ExprKind::UnOp(Op1::IsBool, Box::new(e_expr)),
+                        "isvec" => ExprKind::UnOp(Op1::IsVec, Box::new(e_expr)),
+                        "bitwise-not" => ExprKind::UnOp(Op1::BitNot, Box::new(e_expr)),
+                        "abs" => ExprKind::UnOp(Op1::Abs, Box::new(e_expr)),
+                        "error-code" => ExprKind::UnOp(Op1::ErrorCode, Box::new(e_expr)),
+                        "raise" => ExprKind::Raise(Box::new(e_expr)),
                         _ => unreachable!(),
                     }
                 }
 
-                [Sexp::Atom(S(op)), es @ ..]
+                [Sexp::Atom(S(op), _), es @ ..]
                     if matches!(
                         op.as_str(),
-                        "+" | "-" | "*" | "/" | ">" | "<" | ">=" | "<=" | "="
+                        "+" | "-"
+                            | "*"
+                            | "/"
+                            | ">"
+                            | "<"
+                            | ">="
+                            | "<="
+                            | "="
+                            | "equal?"
+                            | "mod"
+                            | "remainder"
+                            | "bitwise-and"
+                            | "bitwise-or"
+                            | "bitwise-xor"
+                            | "arithmetic-shift"
+                            | "min"
+                            | "max"
                     ) =>
                 {
                     let [e1, e2] = es else {
-                        return syntax_error("expected two expressions after operator");
+                        return syntax_error(span, "expected two expressions after operator");
                     };
                     let expr_op = match op.as_str() {
                         "+" => Op2::Plus,
@@ -200,74 +374,277 @@ impl Parser {
                         ">=" => Op2::GreaterEqual,
                         "<=" => Op2::LessEqual,
                         "=" => Op2::Equal,
+                        "equal?" => Op2::StructEqual,
+                        "mod" => Op2::Mod,
+                        "remainder" => Op2::Remainder,
+                        "bitwise-and" => Op2::BitAnd,
+                        "bitwise-or" => Op2::BitOr,
+                        "bitwise-xor" => Op2::BitXor,
+                        "arithmetic-shift" => Op2::ArithShift,
+                        "min" => Op2::Min,
+                        "max" => Op2::Max,
                         _ => unreachable!(),
                     };
 
-                    let e1_instrs = self.parse_expr(e1);
-                    let e2_instrs = self.parse_expr(e2);
+                    let e1_instrs = self.parse_expr(e1)?;
+                    let e2_instrs = self.parse_expr(e2)?;
 
-                    Expr::BinOp(expr_op, Box::new(e1_instrs), Box::new(e2_instrs))
+                    ExprKind::BinOp(expr_op, Box::new(e1_instrs), Box::new(e2_instrs))
                 }
 
                 [func, args @ ..] => {
-                    let func = self.parse_identifier(func);
-                    let exprs: Vec<_> = args.iter().map(|e| self.parse_expr(e)).collect();
-                    Expr::Call(Symbol::new(func), exprs)
+                    let func = match func {
+                        Sexp::Atom(..) => {
+                            Expr::new(ExprKind::Var(self.parse_identifier(func)?), func.span())
+                        }
+                        Sexp::List(..) => self.parse_expr(func)?,
+                    };
+                    ExprKind::Call(Box::new(func), self.parse_exprs(args)?)
                 }
-                _ => syntax_error("unexpected s-expr"),
+                _ => return syntax_error(span, "unexpected s-expr"),
             },
+        };
+        Ok(Expr::new(kind, span))
+    }
+
+    fn parse_exprs(&self, es: &[Sexp]) -> Result<Vec<Expr>, CompileError> {
+        es.iter().map(|e| self.parse_expr(e)).collect()
+    }
+
+    /// Parses the expressions of a `block`. A run of `fun` forms defines local functions for the
+    /// rest of the block, which becomes the body of a single [`ExprKind::LocalFuns`].
+    fn parse_block(&self, es: &[Sexp]) -> Result<Vec<Expr>, CompileError> {
+        let mut items = vec![];
+        for (i, e) in es.iter().enumerate() {
+            if is_form(e, "fun") {
+                let count = es[i..].iter().take_while(|e| is_form(e, "fun")).count();
+                let (funs, rest) = es[i..].split_at(count);
+                let body = match rest {
+                    [] => {
+                        return syntax_error(
+                            funs[count - 1].span(),
+                            "local functions must be followed by an expression",
+                        )
+                    }
+                    [body] => self.parse_expr(body)?,
+                    _ => {
+                        let body = self.parse_block(rest)?;
+                        let span = Span::new(body[0].span.start, body[body.len() - 1].span.end);
+                        Expr::new(ExprKind::Block(body), span)
+                    }
+                };
+                items.push(self.local_funs(funs, body)?);
+                break;
+            }
+            items.push(self.parse_expr(e)?);
+        }
+        Ok(items)
+    }
 
-            _ => syntax_error("unexpected s-expr"),
+    /// Parses the body of a `let` with the local functions `funs` defined in it.
+    fn parse_local_funs(&self, funs: &[Sexp], body: &Sexp) -> Result<Expr, CompileError> {
+        if is_form(body, "fun") {
+            return syntax_error(
+                body.span(),
+                "local functions must be followed by an expression",
+            );
         }
+        let body = self.parse_expr(body)?;
+        self.local_funs(funs, body)
     }
 
-    fn parse_binding(&self, e: &Sexp) -> (Symbol, Expr) {
-        let Sexp::List(es) = e else {
-            return syntax_error("expected a list")
+    fn local_funs(&self, funs: &[Sexp], body: Expr) -> Result<Expr, CompileError> {
+        let Some(first) = funs.first() else {
+            return Ok(body);
         };
-        if let [name, expr] = &es[..] {
-            (self.parse_identifier(name), self.parse_expr(expr))
-        } else {
-            syntax_error("malformed binding")
-        }
+        let span = Span::new(first.span().start, body.span.end);
+        let funs = funs
+            .iter()
+            .map(|e| self.parse_func(e))
+            .collect::<Result<_, _>>()?;
+        Ok(Expr::new(ExprKind::LocalFuns(funs, Box::new(body)), span))
     }
 
-    fn parse_func(&self, e: &Sexp) -> FunDecl {
-        let Sexp::List(es) = e else {
-            return syntax_error("expected a list");
+    fn parse_binding(&self, e: &Sexp) -> Result<(Binding, Expr), CompileError> {
+        let Sexp::List(es, _) = e else {
+            return syntax_error(e.span(), "expected a list");
         };
         match &es[..] {
-            [Sexp::Atom(S(keyword)), Sexp::List(es), body] if keyword == "fun" => {
-                let [name, params @ ..] = &es[..] else {
-                    return syntax_error("missing function name");
+            [name, expr] => Ok((self.parse_param(name)?, self.parse_expr(expr)?)),
+            // (name : type expr)
+            [name, Sexp::Atom(S(colon), _), ty, expr] if colon == ":" => {
+                let binding = Binding {
+                    ty: Some(self.parse_type(ty)?),
+                    ..self.parse_param(name)?
                 };
-                let params = params.iter().map(|e| self.parse_identifier(e)).collect();
-                let body = self.parse_expr(body);
-                FunDecl {
-                    name: Symbol::new(name),
-                    params,
-                    body,
+                Ok((binding, self.parse_expr(expr)?))
+            }
+            _ => syntax_error(e.span(), "malformed binding"),
+        }
+    }
+
+    fn parse_func(&self, e: &Sexp) -> Result<FunDecl, CompileError> {
+        let Sexp::List(es, span) = e else {
+            return syntax_error(e.span(), "expected a list");
+        };
+        let (sig, ret, body) = match &es[..] {
+            [Sexp::Atom(S(keyword), _), sig, body] if keyword == "fun" => (sig, None, body),
+            // (fun (name param*) : type body)
+            [Sexp::Atom(S(keyword), _), sig, Sexp::Atom(S(colon), _), ret, body]
+                if keyword == "fun" && colon == ":" =>
+            {
+                (sig, Some(self.parse_type(ret)?), body)
+            }
+            _ => return syntax_error(*span, "malformed function"),
+        };
+        let Sexp::List(es, sig_span) = sig else {
+            return syntax_error(*span, "malformed function");
+        };
+        let [name, params @ ..] = &es[..] else {
+            return syntax_error(*sig_span, "missing function name");
+        };
+        let (params, rest) = match params {
+            [params @ .., Sexp::Atom(S(dot), _), rest] if dot == "." => {
+                (params, Some(self.parse_param(rest)?))
+            }
+            _ => (params, None),
+        };
+        let params = params
+            .iter()
+            .map(|e| self.parse_typed_param(e))
+            .collect::<Result<_, _>>()?;
+        let body = self.parse_expr(body)?;
+        Ok(FunDecl {
+            name: self.parse_identifier(name)?,
+            params,
+            rest,
+            ret,
+            body,
+            span: *span,
+        })
+    }
+
+    fn parse_struct(&self, e: &Sexp) -> Result<StructDecl, CompileError> {
+        let Sexp::List(es, span) = e else {
+            return syntax_error(e.span(), "expected a list");
+        };
+        let [_, name, Sexp::List(fields, _)] = &es[..] else {
+            return syntax_error(*span, "malformed struct");
+        };
+        Ok(StructDecl {
+            name: self.parse_identifier(name)?,
+            fields: fields
+                .iter()
+                .map(|e| self.parse_param(e))
+                .collect::<Result<_, _>>()?,
+            span: *span,
+        })
+    }
+
+    fn parse_define(&self, e: &Sexp) -> Result<GlobalDecl, CompileError> {
+        let Sexp::List(es, span) = e else {
+            return syntax_error(e.span(), "expected a list");
+        };
+        let [_, name, init] = &es[..] else {
+            return syntax_error(*span, "malformed define");
+        };
+        Ok(GlobalDecl {
+            name: self.parse_identifier(name)?,
+            init: self.parse_expr(init)?,
+            span: *span,
+        })
+    }
+
+    fn parse_pattern(&self, e: &Sexp) -> Result<Pattern, CompileError> {
+        match e {
+            &Sexp::Atom(I(n), span) => {
+                if (-4611686018427387904..4611686018427387904).contains(&n) {
+                    Ok(Pattern::Number(n))
+                } else {
+                    Err(CompileError::IntegerOverflow { span })
                 }
             }
-            _ => syntax_error("malformed function"),
+            Sexp::Atom(S(id), _) => match id.as_str() {
+                "_" => Ok(Pattern::Wildcard),
+                "true" => Ok(Pattern::Boolean(true)),
+                "false" => Ok(Pattern::Boolean(false)),
+                "nil" => Ok(Pattern::Nil),
+                _ => Ok(Pattern::Var(self.parse_param(e)?)),
+            },
+            // [<pattern>*], read as (vec <pattern>*)
+            Sexp::List(es, _) if is_form(e, "vec") => Ok(Pattern::Vec(
+                es[1..]
+                    .iter()
+                    .map(|e| self.parse_pattern(e))
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => syntax_error(e.span(), "invalid pattern"),
         }
     }
 
-    fn parse_identifier(&self, e: &Sexp) -> Symbol {
-        let Sexp::Atom(S(s)) = e  else {
-            return syntax_error("expected an identifier");
+    fn parse_param(&self, e: &Sexp) -> Result<Binding, CompileError> {
+        Ok(Binding {
+            name: self.parse_identifier(e)?,
+            ty: None,
+            span: e.span(),
+        })
+    }
+
+    /// Parses a function parameter, which may be annotated as `(name : type)`.
+    fn parse_typed_param(&self, e: &Sexp) -> Result<Binding, CompileError> {
+        match e {
+            Sexp::List(es, span) => match &es[..] {
+                [name, Sexp::Atom(S(colon), _), ty] if colon == ":" => Ok(Binding {
+                    ty: Some(self.parse_type(ty)?),
+                    ..self.parse_param(name)?
+                }),
+                _ => syntax_error(*span, "malformed parameter"),
+            },
+            _ => self.parse_param(e),
+        }
+    }
+
+    fn parse_type(&self, e: &Sexp) -> Result<Type, CompileError> {
+        match e {
+            Sexp::Atom(S(id), _) => match id.as_str() {
+                "int" => Ok(Type::Int),
+                "float" => Ok(Type::Float),
+                "bool" => Ok(Type::Bool),
+                "nil" => Ok(Type::Nil),
+                "any" => Ok(Type::Any),
+                _ => syntax_error(e.span(), format!("unknown type `{id}`")),
+            },
+            // (vec <type>)
+            Sexp::List(es, _) if is_form(e, "vec") && es.len() == 2 => {
+                Ok(Type::vec(self.parse_type(&es[1])?))
+            }
+            _ => syntax_error(e.span(), "invalid type"),
+        }
+    }
+
+    fn parse_identifier(&self, e: &Sexp) -> Result<Symbol, CompileError> {
+        let Sexp::Atom(S(s), span) = e else {
+            return syntax_error(e.span(), "expected an identifier");
         };
 
         if is_keyword(s) {
-            syntax_error(format!("cannot use keyword `{s}` as identifier"))
+            syntax_error(*span, format!("cannot use keyword `{s}` as identifier"))
         } else if self.id_regex.is_match(s) {
-            Symbol::new(s)
+            Ok(Symbol::new(s))
         } else {
-            syntax_error("invalid identifier")
+            syntax_error(*span, "invalid identifier")
         }
     }
 }
 
+fn is_form(e: &Sexp, keyword: &str) -> bool {
+    matches!(e, Sexp::List(es, _) if matches!(es.first(), Some(Sexp::Atom(S(kw), _)) if kw == keyword))
+}
+
+fn is_decl_form(e: &Sexp) -> bool {
+    is_form(e, "fun") || is_form(e, "struct") || is_form(e, "define")
+}
+
 fn is_keyword(s: &str) -> bool {
     matches!(
         s,
@@ -276,6 +653,7 @@ fn is_keyword(s: &str) -> bool {
             | "add1"
             | "sub1"
             | "isnum"
+            | "isfloat"
             | "isbool"
             | "isvec"
             | "print"
@@ -284,6 +662,25 @@ fn is_keyword(s: &str) -> bool {
             | "input"
             | "nil"
             | "fun"
+            | "lambda"
+            | "struct"
+            | "define"
+            | "equal?"
+            | "mod"
+            | "remainder"
+            | "bitwise-and"
+            | "bitwise-or"
+            | "bitwise-xor"
+            | "bitwise-not"
+            | "arithmetic-shift"
+            | "abs"
+            | "min"
+            | "max"
+            | "raise"
+            | "try"
+            | "catch"
+            | "match"
+            | "error-code"
             | "make-vec"
             | "vec"
             | "vec-set!"
@@ -291,9 +688,12 @@ fn is_keyword(s: &str) -> bool {
             | "vec-len"
             | "snek-printstack"
             | "gc"
-    )
+            | "args"
+    ) || DictOp::ALL.iter().any(|op| op.name() == s)
+        || VecOp::ALL.iter().any(|op| op.name() == s)
+        || IoOp::ALL.iter().any(|op| op.name() == s)
 }
 
-fn syntax_error<T>(note: impl ToString) -> T {
-    panic!("Invalid syntax: {}", note.to_string())
+fn syntax_error<T>(span: Span, note: impl ToString) -> Result<T, CompileError> {
+    Err(CompileError::malformed(span, note))
 }
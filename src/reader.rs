@@ -0,0 +1,315 @@
+use crate::{diagnostic::Location, error::CompileError, syntax::Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Atom {
+    I(i64),
+    F(f64),
+    S(String),
+}
+
+/// An s-expression annotated with the span of source it was read from.
+#[derive(Debug, Clone)]
+pub enum Sexp {
+    Atom(Atom, Span),
+    List(Vec<Sexp>, Span),
+}
+
+impl Sexp {
+    pub fn span(&self) -> Span {
+        match self {
+            Sexp::Atom(_, span) | Sexp::List(_, span) => *span,
+        }
+    }
+}
+
+/// A `;` or `#| |#` comment, kept so the formatter can put it back.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+/// Reads every top-level form in `src`. When a form can't be read, the error is recorded and
+/// reading resumes at the next line that starts a top-level `(fun`, `(struct` or `(define`, so the
+/// forms after it are still available to the parser.
+///
+/// Besides plain s-expressions the reader understands `;` line comments, nestable `#| |#` block
+/// comments, `[a b c]` as sugar for `(vec a b c)`, `0x`/`0b` integer literals, `_` digit
+/// separators and decimal float literals like `1.5` and `-2.0e-3`.
+pub fn read(src: &str) -> (Vec<Sexp>, Vec<CompileError>) {
+    Reader::new(src).read_forms()
+}
+
+/// Returns every comment in `src`, in source order.
+pub fn comments(src: &str) -> Vec<Comment> {
+    let mut reader = Reader::new(src);
+    reader.read_forms();
+    reader.comments
+}
+
+struct Reader<'a> {
+    src: &'a str,
+    pos: usize,
+    comments: Vec<Comment>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(src: &'a str) -> Reader<'a> {
+        Reader {
+            src,
+            pos: 0,
+            comments: vec![],
+        }
+    }
+
+    fn read_forms(&mut self) -> (Vec<Sexp>, Vec<CompileError>) {
+        let mut forms = vec![];
+        let mut errors = vec![];
+        loop {
+            if let Err(err) = self.skip_whitespace() {
+                errors.push(err);
+                return (forms, errors);
+            }
+            if self.peek().is_none() {
+                return (forms, errors);
+            }
+            let start = self.pos;
+            match self.read_sexp() {
+                Ok(form) => forms.push(form),
+                Err(err) => {
+                    errors.push(err);
+                    let rest = &self.src[start + 1..];
+                    let next = ["\n(fun", "\n(struct", "\n(define"]
+                        .iter()
+                        .filter_map(|decl| rest.find(decl))
+                        .min();
+                    self.pos = match next {
+                        Some(i) => start + 1 + i + 1,
+                        None => self.src.len(),
+                    };
+                }
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        let found = self.src[self.pos..].starts_with(s);
+        if found {
+            self.pos += s.len();
+        }
+        found
+    }
+
+    /// Skips whitespace, `;` line comments and `#| |#` block comments.
+    fn skip_whitespace(&mut self) -> Result<(), CompileError> {
+        while let Some(c) = self.peek() {
+            let start = self.pos;
+            if c == ';' {
+                while !matches!(self.peek(), Some('\n') | None) {
+                    self.bump();
+                }
+                self.push_comment(start);
+            } else if self.src[self.pos..].starts_with("#|") {
+                self.skip_block_comment()?;
+                self.push_comment(start);
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn push_comment(&mut self, start: usize) {
+        let text = self.src[start..self.pos].trim_end();
+        self.comments.push(Comment {
+            text: text.to_string(),
+            span: Span::new(start, start + text.len()),
+        });
+    }
+
+    /// Skips a block comment starting at `#|`. Block comments nest, so a commented-out region may
+    /// itself contain block comments.
+    fn skip_block_comment(&mut self) -> Result<(), CompileError> {
+        let mut opened = vec![self.pos];
+        self.pos += 2;
+        while let Some(&start) = opened.last() {
+            if self.eat("|#") {
+                opened.pop();
+            } else if self.src[self.pos..].starts_with("#|") {
+                opened.push(self.pos);
+                self.pos += 2;
+            } else if self.bump().is_none() {
+                return Err(CompileError::malformed(
+                    Span::new(start, start + 2),
+                    "unterminated block comment",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_sexp(&mut self) -> Result<Sexp, CompileError> {
+        let start = self.pos;
+        match self.peek() {
+            Some('(') => self.read_list(vec![]),
+            Some('[') => {
+                let vec = Sexp::Atom(Atom::S("vec".to_string()), Span::new(start, start + 1));
+                self.read_list(vec![vec])
+            }
+            Some(c @ (')' | ']')) => {
+                self.bump();
+                Err(CompileError::malformed(
+                    Span::new(start, self.pos),
+                    format!("unexpected `{c}`"),
+                ))
+            }
+            Some('"') => self.read_quoted_atom(),
+            Some(_) => self.read_atom(),
+            None => Err(CompileError::malformed(
+                Span::new(start, start),
+                "unexpected end of input",
+            )),
+        }
+    }
+
+    /// Reads a list opened by `(` or `[` at the current position, appending its elements to `es`.
+    /// Unbalanced delimiters are reported at the offending character: an unclosed list at its
+    /// opening delimiter, and a mismatched closer at the closer itself.
+    fn read_list(&mut self, mut es: Vec<Sexp>) -> Result<Sexp, CompileError> {
+        let start = self.pos;
+        let open = self.bump().expect("read_list called at end of input");
+        let close = if open == '[' { ']' } else { ')' };
+        loop {
+            self.skip_whitespace()?;
+            match self.peek() {
+                Some(c) if c == close => {
+                    self.bump();
+                    return Ok(Sexp::List(es, Span::new(start, self.pos)));
+                }
+                Some(c @ (')' | ']')) => {
+                    let loc = Location::of(self.src, start);
+                    return Err(CompileError::malformed(
+                        Span::new(self.pos, self.pos + 1),
+                        format!(
+                            "mismatched `{c}`, expected `{close}` to close the `{open}` at {}:{}",
+                            loc.line, loc.col
+                        ),
+                    ));
+                }
+                Some(_) => es.push(self.read_sexp()?),
+                None => {
+                    return Err(CompileError::malformed(
+                        Span::new(start, start + 1),
+                        format!("unclosed `{open}`"),
+                    ))
+                }
+            }
+        }
+    }
+    fn read_quoted_atom(&mut self) -> Result<Sexp, CompileError> {
+        let start = self.pos;
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(Sexp::Atom(Atom::S(s), Span::new(start, self.pos))),
+                Some('\\') => match self.bump() {
+                    Some(c @ ('"' | '\\')) => s.push(c),
+                    Some(c) => {
+                        s.push('\\');
+                        s.push(c);
+                    }
+                    None => break,
+                },
+                Some(c) => s.push(c),
+                None => break,
+            }
+        }
+        Err(CompileError::malformed(
+            Span::new(start, self.pos),
+            "unterminated string literal",
+        ))
+    }
+
+    fn read_atom(&mut self) -> Result<Sexp, CompileError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | ';') {
+                break;
+            }
+            self.bump();
+        }
+        let span = Span::new(start, self.pos);
+        let text = &self.src[start..self.pos];
+        if let Some(x) = read_float(text) {
+            return Ok(Sexp::Atom(Atom::F(x), span));
+        }
+        match read_int(text, span) {
+            Some(n) => Ok(Sexp::Atom(Atom::I(n?), span)),
+            None => Ok(Sexp::Atom(Atom::S(text.to_string()), span)),
+        }
+    }
+}
+
+/// Reads `text` as an integer literal: an optional sign, then decimal digits or `0x`/`0b` followed
+/// by hex or binary digits, with single `_` separators allowed between digits. Returns `None` when
+/// `text` isn't number-like at all (doesn't start with a digit after the sign), so it is read as a
+/// symbol instead.
+fn read_int(text: &str, span: Span) -> Option<Result<i64, CompileError>> {
+    let (neg, body) = match text.strip_prefix('-') {
+        Some(body) => (true, body),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    if !body.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let (radix, digits) = if let Some(digits) = body.strip_prefix("0x") {
+        (16, digits)
+    } else if let Some(digits) = body.strip_prefix("0b") {
+        (2, digits)
+    } else {
+        (10, body)
+    };
+    let well_formed = digits
+        .split('_')
+        .all(|group| !group.is_empty() && group.chars().all(|c| c.is_digit(radix)));
+    if !well_formed {
+        return Some(Err(CompileError::malformed(
+            span,
+            format!("invalid integer literal `{text}`"),
+        )));
+    }
+    let digits = format!("{}{}", if neg { "-" } else { "" }, digits.replace('_', ""));
+    Some(i64::from_str_radix(&digits, radix).map_err(|_| CompileError::IntegerOverflow { span }))
+}
+
+/// Reads `text` as a float literal: an optional sign, decimal digits, a `.` followed by more
+/// digits, and an optional exponent such as `e-3`. Returns `None` for anything else, which is then
+/// read as an integer or a symbol.
+fn read_float(text: &str) -> Option<f64> {
+    let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    let body = text.strip_prefix(['-', '+']).unwrap_or(text);
+    let (mantissa, exp) = match body.split_once(['e', 'E']) {
+        Some((mantissa, exp)) => (mantissa, Some(exp)),
+        None => (body, None),
+    };
+    let (int, frac) = mantissa.split_once('.')?;
+    let exp_ok = exp.is_none_or(|exp| digits(exp.strip_prefix(['-', '+']).unwrap_or(exp)));
+    if digits(int) && digits(frac) && exp_ok {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
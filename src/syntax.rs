@@ -3,25 +3,97 @@ use std::fmt;
 #[derive(Copy, Clone, Hash, PartialEq, Eq)]
 pub struct Symbol(&'static str);
 
-#[derive(Debug)]
+/// A half-open range of byte offsets `[start, end)` into the source file.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone)]
 pub struct Prog {
+    pub structs: Vec<StructDecl>,
     pub funs: Vec<FunDecl>,
+    pub globals: Vec<GlobalDecl>,
     pub main: Expr,
 }
 
-#[derive(Debug)]
+/// A `(struct name (field ...))` declaration. A struct's type id is its index in
+/// [`Prog::structs`].
+#[derive(Debug, Clone)]
+pub struct StructDecl {
+    pub name: Symbol,
+    pub fields: Vec<Binding>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
 pub struct FunDecl {
     pub name: Symbol,
-    pub params: Vec<Symbol>,
+    pub params: Vec<Binding>,
+    /// The parameter after a `.` in `(fun (f a b . rest) ...)`, which receives any arguments past
+    /// `params` packed into a vector, or nil if there are none.
+    pub rest: Option<Binding>,
+    /// The `: type` after the signature, if any.
+    pub ret: Option<Type>,
     pub body: Expr,
+    pub span: Span,
+}
+
+/// A `(define name expr)` global variable. Globals are initialized in order before the main
+/// expression, and any function can read and assign them.
+#[derive(Debug, Clone)]
+pub struct GlobalDecl {
+    pub name: Symbol,
+    pub init: Expr,
+    pub span: Span,
+}
+
+impl FunDecl {
+    /// The parameters in the order they are passed, with the rest parameter last.
+    pub fn all_params(&self) -> Vec<Binding> {
+        self.params.iter().chain(&self.rest).copied().collect()
+    }
 }
 
-#[derive(Debug)]
-pub enum Expr {
+/// A name introduced by a `let` or a function parameter list.
+#[derive(Debug, Copy, Clone)]
+pub struct Binding {
+    pub name: Symbol,
+    /// The annotation in `(x : type)`, if any.
+    pub ty: Option<Type>,
+    pub span: Span,
+}
+
+/// A type, as written in an annotation and checked by [`crate::typeck`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Type {
+    /// A fixnum or a bignum.
+    Int,
+    Float,
+    Bool,
+    Nil,
+    /// A vector, never nil, whose elements all have the given type.
+    Vec(&'static Type),
+    /// Any value, only checked when it is used.
+    Any,
+    /// The type of expressions that never produce a value, like `break`. It can't be written.
+    Never,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     Number(i64),
+    Float(f64),
     Boolean(bool),
     Var(Symbol),
-    Let(Vec<(Symbol, Expr)>, Box<Expr>),
+    Let(Vec<(Binding, Expr)>, Box<Expr>),
     UnOp(Op1, Box<Expr>),
     BinOp(Op2, Box<Expr>, Box<Expr>),
     If(Box<Expr>, Box<Expr>, Box<Expr>),
@@ -34,22 +106,222 @@ pub enum Expr {
     VecGet(Box<Expr>, Box<Expr>),
     VecLen(Box<Expr>),
     Block(Vec<Expr>),
-    Call(Symbol, Vec<Expr>),
+    /// A call through any expression. Calls whose head names a top-level function that isn't
+    /// shadowed by a local are compiled as direct calls.
+    Call(Box<Expr>, Vec<Expr>),
+    Lambda(Vec<Binding>, Box<Expr>),
+    /// Only found in the functions generated for a `struct` declaration.
+    Record(RecordOp, Vec<Expr>),
+    /// Unwinds to the innermost enclosing `try`, passing it the value.
+    Raise(Box<Expr>),
+    /// `(try body (catch e handler))`: the value of `body`, or if it raises, of `handler` with the
+    /// raised value bound to `e`.
+    Try(Box<Expr>, Binding, Box<Expr>),
+    Dict(DictOp, Vec<Expr>),
+    VecOp(VecOp, Vec<Expr>),
+    Io(IoOp, Vec<Expr>),
+    /// `(match e (pattern body) ...)`: the body of the first arm whose pattern matches the value of
+    /// `e`, with the pattern's variables bound.
+    Match(Box<Expr>, Vec<MatchArm>),
+    /// Functions defined by `fun` forms inside a `let` or `block`, which can call each other and
+    /// see the variables around them, and the expression they are visible in. They are lifted to
+    /// top-level functions by [`crate::lift`] before type checking.
+    LocalFuns(Vec<FunDecl>, Box<Expr>),
     Input,
+    /// `(args)`: the command-line arguments after the heap size, as a vector.
+    Args,
     Nil,
     PrintStack,
     PrintHeap,
     Gc,
 }
 
+/// A `(pattern body)` arm of a `match`.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Expr,
+    pub span: Span,
+}
+
+/// A pattern in a `match` arm.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// `_`, which matches anything.
+    Wildcard,
+    /// A name, which matches anything and is bound to it.
+    Var(Binding),
+    /// An integer, `true`, `false` or `nil`, which matches only itself.
+    Number(i64),
+    Boolean(bool),
+    Nil,
+    /// `[p ...]`, which matches a vector with exactly one element for each pattern, if they match.
+    Vec(Vec<Pattern>),
+}
+
+/// An operation on records of the struct with the given type id.
+#[derive(Debug, Copy, Clone)]
+pub enum RecordOp {
+    /// Builds a record from its field values.
+    New(usize),
+    /// Whether the value is a record of this type.
+    Is(usize),
+    /// Reads the field at the given index of a record.
+    Get(usize, usize),
+    /// Writes the second operand to the field at the given index of the record, returning the
+    /// record.
+    Set(usize, usize),
+}
+
+/// An operation on dictionaries, whose first operand is the dictionary except for `New`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DictOp {
+    /// `(make-dict)`, or `(make-dict by-structure)` where a true operand compares vector keys
+    /// structurally instead of by identity.
+    New,
+    /// `(dict-set! d k v)`, returning the dictionary.
+    Set,
+    /// `(dict-get d k default)`.
+    Get,
+    /// `(dict-has? d k)`.
+    Has,
+    /// `(dict-remove! d k)`, returning the dictionary.
+    Remove,
+    /// `(dict-keys d)`, a new vector of the keys in no particular order.
+    Keys,
+}
+
+impl DictOp {
+    pub const ALL: [DictOp; 6] = [
+        DictOp::New,
+        DictOp::Set,
+        DictOp::Get,
+        DictOp::Has,
+        DictOp::Remove,
+        DictOp::Keys,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DictOp::New => "make-dict",
+            DictOp::Set => "dict-set!",
+            DictOp::Get => "dict-get",
+            DictOp::Has => "dict-has?",
+            DictOp::Remove => "dict-remove!",
+            DictOp::Keys => "dict-keys",
+        }
+    }
+
+    /// The number of operands the operation takes: `(min, max)`.
+    pub fn arity(self) -> (usize, usize) {
+        match self {
+            DictOp::New => (0, 1),
+            DictOp::Set | DictOp::Get => (3, 3),
+            DictOp::Has | DictOp::Remove => (2, 2),
+            DictOp::Keys => (1, 1),
+        }
+    }
+}
+
+/// An operation on vectors beyond creating, indexing and measuring them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VecOp {
+    /// `(vec-push! v x)`, returning the vector.
+    Push,
+    /// `(vec-pop! v)`, returning the removed element.
+    Pop,
+    /// `(vec-slice v start end)`, a new vector of the elements from `start` up to `end`.
+    Slice,
+    /// `(vec-copy! dst dst-start src src-start n)`, returning `dst`.
+    Copy,
+    /// `(vec-append a b)`, a new vector of the elements of both.
+    Append,
+}
+
+impl VecOp {
+    pub const ALL: [VecOp; 5] = [
+        VecOp::Push,
+        VecOp::Pop,
+        VecOp::Slice,
+        VecOp::Copy,
+        VecOp::Append,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VecOp::Push => "vec-push!",
+            VecOp::Pop => "vec-pop!",
+            VecOp::Slice => "vec-slice",
+            VecOp::Copy => "vec-copy!",
+            VecOp::Append => "vec-append",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            VecOp::Pop => 1,
+            VecOp::Push | VecOp::Append => 2,
+            VecOp::Slice => 3,
+            VecOp::Copy => 5,
+        }
+    }
+}
+
+/// Console input and output, read from stdin and written to stdout a line or a value at a time.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IoOp {
+    /// `(read-int)`, the integer on the next line of stdin.
+    ReadInt,
+    /// `(read-value)`, the value on the next line of stdin, written like the program's input.
+    ReadValue,
+    /// `(eof?)`, whether stdin has nothing left to read.
+    IsEof,
+    /// `(display v)`, printing `v` without a newline and returning it.
+    Display,
+    /// `(newline)`, printing a newline and returning nil.
+    Newline,
+}
+
+impl IoOp {
+    pub const ALL: [IoOp; 5] = [
+        IoOp::ReadInt,
+        IoOp::ReadValue,
+        IoOp::IsEof,
+        IoOp::Display,
+        IoOp::Newline,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IoOp::ReadInt => "read-int",
+            IoOp::ReadValue => "read-value",
+            IoOp::IsEof => "eof?",
+            IoOp::Display => "display",
+            IoOp::Newline => "newline",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            IoOp::Display => 1,
+            IoOp::ReadInt | IoOp::ReadValue | IoOp::IsEof | IoOp::Newline => 0,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum Op1 {
     Add1,
     Sub1,
     IsNum,
+    IsFloat,
     IsBool,
     IsVec,
     Print,
+    BitNot,
+    Abs,
+    /// The code of a caught built-in runtime error, as in `ErrCode` in runtime/start.rs.
+    ErrorCode,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -59,10 +331,323 @@ pub enum Op2 {
     Times,
     Divide,
     Equal,
+    /// Deep equality: vectors and records of the same type are equal if their fields are.
+    StructEqual,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
+    /// The remainder with the sign of the divisor.
+    Mod,
+    /// The remainder with the sign of the dividend, as left by `idiv`.
+    Remainder,
+    BitAnd,
+    BitOr,
+    BitXor,
+    /// Shifts the first operand left by the second, or right if it is negative.
+    ArithShift,
+    Min,
+    Max,
+}
+
+impl Pattern {
+    /// The variables the pattern binds, in order, each with the path of vector indices that leads
+    /// from the matched value to the value it is bound to.
+    pub fn bindings(&self) -> Vec<(Binding, Vec<usize>)> {
+        fn walk(pat: &Pattern, path: &mut Vec<usize>, out: &mut Vec<(Binding, Vec<usize>)>) {
+            match pat {
+                Pattern::Var(binding) => out.push((*binding, path.clone())),
+                Pattern::Vec(pats) => {
+                    for (i, pat) in pats.iter().enumerate() {
+                        path.push(i);
+                        walk(pat, path, out);
+                        path.pop();
+                    }
+                }
+                Pattern::Wildcard | Pattern::Number(_) | Pattern::Boolean(_) | Pattern::Nil => {}
+            }
+        }
+        let mut out = vec![];
+        walk(self, &mut vec![], &mut out);
+        out
+    }
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+impl Prog {
+    /// The functions generated for every struct: the constructor `point`, the predicate `point?`,
+    /// and for each field the accessor `point-x` and the setter `set-point-x!`.
+    pub fn struct_funs(&self) -> Vec<FunDecl> {
+        let mut funs = vec![];
+        for (id, decl) in self.structs.iter().enumerate() {
+            let span = decl.span;
+            let var = |x: Symbol| Expr::new(ExprKind::Var(x), span);
+            let param = |name: &str| Binding {
+                name: Symbol::new(name),
+                ty: None,
+                span,
+            };
+            let mut fun = |name: String, params: Vec<Binding>, op: RecordOp| {
+                let args = params.iter().map(|param| var(param.name)).collect();
+                funs.push(FunDecl {
+                    name: Symbol::new(name),
+                    params,
+                    rest: None,
+                    ret: None,
+                    body: Expr::new(ExprKind::Record(op, args), span),
+                    span,
+                });
+            };
+
+            fun(
+                decl.name.to_string(),
+                decl.fields.clone(),
+                RecordOp::New(id),
+            );
+            fun(
+                format!("{}?", decl.name),
+                vec![param("v")],
+                RecordOp::Is(id),
+            );
+            for (i, field) in decl.fields.iter().enumerate() {
+                let (record, val) = (param("record"), param("val"));
+                fun(
+                    format!("{}-{}", decl.name, field.name),
+                    vec![record],
+                    RecordOp::Get(id, i),
+                );
+                fun(
+                    format!("set-{}-{}!", decl.name, field.name),
+                    vec![record, val],
+                    RecordOp::Set(id, i),
+                );
+            }
+        }
+        funs
+    }
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Expr {
+        Expr { kind, span }
+    }
+
+    /// Calls `f` on each direct subexpression, in evaluation order. Passes that care about the
+    /// variables a `let` or `lambda` binds have to handle those forms themselves.
+    pub fn for_each_child<'a>(&'a self, mut f: impl FnMut(&'a Expr)) {
+        match &self.kind {
+            ExprKind::Number(_)
+            | ExprKind::Float(_)
+            | ExprKind::Boolean(_)
+            | ExprKind::Var(_)
+            | ExprKind::Input
+            | ExprKind::Args
+            | ExprKind::Nil
+            | ExprKind::PrintStack
+            | ExprKind::PrintHeap
+            | ExprKind::Gc => {}
+            ExprKind::Let(bindings, body) => {
+                bindings.iter().for_each(|(_, rhs)| f(rhs));
+                f(body);
+            }
+            ExprKind::UnOp(_, e)
+            | ExprKind::Loop(e)
+            | ExprKind::Break(e)
+            | ExprKind::Set(_, e)
+            | ExprKind::VecLen(e)
+            | ExprKind::Raise(e)
+            | ExprKind::Lambda(_, e) => f(e),
+            ExprKind::BinOp(_, e1, e2) | ExprKind::MakeVec(e1, e2) | ExprKind::VecGet(e1, e2) => {
+                f(e1);
+                f(e2);
+            }
+            ExprKind::If(e1, e2, e3) | ExprKind::VecSet(e1, e2, e3) => {
+                f(e1);
+                f(e2);
+                f(e3);
+            }
+            ExprKind::Try(body, _, handler) => {
+                f(body);
+                f(handler);
+            }
+            ExprKind::Vec(es)
+            | ExprKind::Block(es)
+            | ExprKind::Record(_, es)
+            | ExprKind::Dict(_, es)
+            | ExprKind::VecOp(_, es)
+            | ExprKind::Io(_, es) => es.iter().for_each(f),
+            ExprKind::Call(fun, args) => {
+                f(fun);
+                args.iter().for_each(f);
+            }
+            ExprKind::Match(e, arms) => {
+                f(e);
+                arms.iter().for_each(|arm| f(&arm.body));
+            }
+            ExprKind::LocalFuns(funs, body) => {
+                funs.iter().for_each(|fun| f(&fun.body));
+                f(body);
+            }
+        }
+    }
+
+    /// Adds the variables `self` uses but doesn't bind to `out`, in order of first use. Top-level
+    /// functions used by name count as variables too.
+    pub fn free_vars(&self, bound: &im::HashSet<Symbol>, out: &mut Vec<Symbol>) {
+        match &self.kind {
+            ExprKind::Var(x) | ExprKind::Set(x, _) if !bound.contains(x) && !out.contains(x) => {
+                out.push(*x)
+            }
+            _ => {}
+        }
+        match &self.kind {
+            ExprKind::Let(bindings, body) => {
+                let mut bound = bound.clone();
+                for (binding, rhs) in bindings {
+                    rhs.free_vars(&bound, out);
+                    bound.insert(binding.name);
+                }
+                body.free_vars(&bound, out);
+            }
+            ExprKind::Try(body, binding, handler) => {
+                body.free_vars(bound, out);
+                handler.free_vars(&bound.update(binding.name), out);
+            }
+            ExprKind::Match(e, arms) => {
+                e.free_vars(bound, out);
+                for arm in arms {
+                    let bound = arm
+                        .pattern
+                        .bindings()
+                        .iter()
+                        .fold(bound.clone(), |bound, (binding, _)| {
+                            bound.update(binding.name)
+                        });
+                    arm.body.free_vars(&bound, out);
+                }
+            }
+            ExprKind::Lambda(params, body) => {
+                let bound = params
+                    .iter()
+                    .fold(bound.clone(), |bound, param| bound.update(param.name));
+                body.free_vars(&bound, out);
+            }
+            ExprKind::LocalFuns(funs, body) => {
+                let bound = funs
+                    .iter()
+                    .fold(bound.clone(), |bound, fun| bound.update(fun.name));
+                for fun in funs {
+                    let bound = fun
+                        .all_params()
+                        .iter()
+                        .fold(bound.clone(), |bound, param| bound.update(param.name));
+                    fun.body.free_vars(&bound, out);
+                }
+                body.free_vars(&bound, out);
+            }
+            _ => self.for_each_child(|e| e.free_vars(bound, out)),
+        }
+    }
+
+    /// Like [`Expr::for_each_child`], but lets `f` modify the subexpressions.
+    pub fn for_each_child_mut(&mut self, mut f: impl FnMut(&mut Expr)) {
+        match &mut self.kind {
+            ExprKind::Number(_)
+            | ExprKind::Float(_)
+            | ExprKind::Boolean(_)
+            | ExprKind::Var(_)
+            | ExprKind::Input
+            | ExprKind::Args
+            | ExprKind::Nil
+            | ExprKind::PrintStack
+            | ExprKind::PrintHeap
+            | ExprKind::Gc => {}
+            ExprKind::Let(bindings, body) => {
+                bindings.iter_mut().for_each(|(_, rhs)| f(rhs));
+                f(body);
+            }
+            ExprKind::UnOp(_, e)
+            | ExprKind::Loop(e)
+            | ExprKind::Break(e)
+            | ExprKind::Set(_, e)
+            | ExprKind::VecLen(e)
+            | ExprKind::Raise(e)
+            | ExprKind::Lambda(_, e) => f(e),
+            ExprKind::BinOp(_, e1, e2) | ExprKind::MakeVec(e1, e2) | ExprKind::VecGet(e1, e2) => {
+                f(e1);
+                f(e2);
+            }
+            ExprKind::If(e1, e2, e3) | ExprKind::VecSet(e1, e2, e3) => {
+                f(e1);
+                f(e2);
+                f(e3);
+            }
+            ExprKind::Try(body, _, handler) => {
+                f(body);
+                f(handler);
+            }
+            ExprKind::Vec(es)
+            | ExprKind::Block(es)
+            | ExprKind::Record(_, es)
+            | ExprKind::Dict(_, es)
+            | ExprKind::VecOp(_, es)
+            | ExprKind::Io(_, es) => es.iter_mut().for_each(f),
+            ExprKind::Call(fun, args) => {
+                f(fun);
+                args.iter_mut().for_each(f);
+            }
+            ExprKind::Match(e, arms) => {
+                f(e);
+                arms.iter_mut().for_each(|arm| f(&mut arm.body));
+            }
+            ExprKind::LocalFuns(funs, body) => {
+                funs.iter_mut().for_each(|fun| f(&mut fun.body));
+                f(body);
+            }
+        }
+    }
+}
+
+impl Type {
+    /// `(vec elem)`. Like symbols, types are leaked so that they can be copied around.
+    pub fn vec(elem: Type) -> Type {
+        Type::Vec(Box::leak(Box::new(elem)))
+    }
+
+    /// The element type of a vector type, or `any` for every other type.
+    pub fn elem(self) -> Type {
+        match self {
+            Type::Vec(elem) => *elem,
+            _ => Type::Any,
+        }
+    }
+
+    pub fn is_vec(self) -> bool {
+        matches!(self, Type::Vec(_))
+    }
+
+    pub fn is_number(self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Nil => write!(f, "nil"),
+            Type::Vec(elem) => write!(f, "(vec {elem})"),
+            Type::Any => write!(f, "any"),
+            Type::Never => write!(f, "never"),
+        }
+    }
 }
 
 impl Symbol {
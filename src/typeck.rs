@@ -0,0 +1,537 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::CompileError,
+    syntax::{
+        DictOp, Expr, ExprKind, FunDecl, IoOp, Op1, Op2, Prog, RecordOp, Span, Symbol, Type,
+        VecOp,
+    },
+};
+
+/// The static types of a program's expressions, as found by [`check`].
+#[derive(Default)]
+pub struct Types {
+    types: HashMap<*const Expr, Type>,
+    /// The expressions whose value has to be checked at run time to have a type, because it is
+    /// only known to be `any` where that type is required.
+    casts: HashMap<*const Expr, Type>,
+    /// The expressions known to produce a fixnum, found by [`crate::infer`].
+    fixnums: HashSet<*const Expr>,
+}
+
+impl Types {
+    /// The static type of `e`, `any` if nothing is known about its value.
+    pub fn of(&self, e: &Expr) -> Type {
+        self.types
+            .get(&(e as *const _))
+            .copied()
+            .unwrap_or(Type::Any)
+    }
+
+    /// The type the value of `e` has to be checked against once it is computed, if any.
+    pub fn cast(&self, e: &Expr) -> Option<Type> {
+        self.casts.get(&(e as *const _)).copied()
+    }
+
+    /// Whether `e` is known to produce a fixnum, so it needn't be checked where one is required.
+    pub fn is_fixnum(&self, e: &Expr) -> bool {
+        self.fixnums.contains(&(e as *const _))
+    }
+
+    pub(crate) fn new(
+        types: HashMap<*const Expr, Type>,
+        casts: HashMap<*const Expr, Type>,
+        fixnums: HashSet<*const Expr>,
+    ) -> Types {
+        Types {
+            types,
+            casts,
+            fixnums,
+        }
+    }
+
+    /// Adds what another analysis of the same program found: its types where they say more than
+    /// these, and all of its casts and fixnums.
+    pub fn refine(&mut self, other: Types) {
+        for (e, ty) in other.types {
+            let known = self.types.entry(e).or_insert(Type::Any);
+            if matches!(*known, Type::Any | Type::Vec(Type::Any)) && ty != Type::Any {
+                *known = ty;
+            }
+        }
+        self.casts.extend(other.casts);
+        self.fixnums.extend(other.fixnums);
+    }
+}
+
+/// Checks the program against its type annotations, reporting every error found sorted by
+/// position. Typing is gradual: anything without an annotation is `any`, which fits everywhere,
+/// and an `any` value used where a type is required is checked when the program runs.
+///
+/// A value that doesn't fit an annotation is always an error. Misuses of built-in operations, like
+/// adding a `bool`, are only reported in functions with annotations; everywhere else they still
+/// raise at run time, so unannotated programs behave as before.
+pub fn check(prg: &Prog, struct_funs: &[FunDecl]) -> Result<Types, Vec<CompileError>> {
+    let mut checker = TypeChecker {
+        funs: struct_funs
+            .iter()
+            .chain(&prg.funs)
+            .map(|fun| (fun.name, fun))
+            .collect(),
+        types: Types::default(),
+        errors: vec![],
+        strict: false,
+        loops: vec![],
+    };
+    for fun in struct_funs.iter().chain(&prg.funs) {
+        checker.check_fun(fun);
+    }
+    checker.strict = false;
+    for global in &prg.globals {
+        checker.infer(&Env::default(), &global.init);
+    }
+    checker.infer(&Env::default(), &prg.main);
+
+    if checker.errors.is_empty() {
+        Ok(checker.types)
+    } else {
+        checker.errors.sort_by_key(|err| err.span().start);
+        Err(checker.errors)
+    }
+}
+
+type Env = im::HashMap<Symbol, Type>;
+
+struct TypeChecker<'a> {
+    funs: HashMap<Symbol, &'a FunDecl>,
+    types: Types,
+    errors: Vec<CompileError>,
+    /// Whether misuses of built-in operations are reported, which is only done in functions with
+    /// annotations.
+    strict: bool,
+    /// For each enclosing loop, the join of the types of the `break`s seen so far.
+    loops: Vec<Type>,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn check_fun(&mut self, fun: &FunDecl) {
+        let env = fun
+            .all_params()
+            .iter()
+            .map(|param| (param.name, param.ty.unwrap_or(Type::Any)))
+            .collect();
+        self.strict = fun.ret.is_some() || fun.params.iter().any(|param| param.ty.is_some());
+        self.infer(&env, &fun.body);
+        if let Some(ret) = fun.ret {
+            self.coerce(&fun.body, ret);
+        }
+    }
+
+    /// Finds the type of `e` and of all its subexpressions.
+    fn infer(&mut self, env: &Env, e: &Expr) -> Type {
+        let ty = match &e.kind {
+            ExprKind::Number(_) => Type::Int,
+            ExprKind::Float(_) => Type::Float,
+            ExprKind::Boolean(_) => Type::Bool,
+            ExprKind::Nil => Type::Nil,
+            ExprKind::Input => Type::Any,
+            ExprKind::Args => Type::Vec(&Type::Any),
+            ExprKind::LocalFuns(..) => unreachable!("local functions are lifted before typing"),
+            ExprKind::PrintStack | ExprKind::PrintHeap | ExprKind::Gc => Type::Int,
+            // A name that isn't a variable is a top-level function used as a value.
+            ExprKind::Var(x) => env.get(x).copied().unwrap_or(Type::Any),
+            ExprKind::Let(bindings, body) => {
+                let mut env = env.clone();
+                for (i, (binding, rhs)) in bindings.iter().enumerate() {
+                    let found = self.infer(&env, rhs);
+                    let ty = match binding.ty {
+                        Some(ty) => {
+                            self.coerce(rhs, ty);
+                            ty
+                        }
+                        // A variable that is never assigned keeps the type of its value.
+                        None => {
+                            let scope = bindings[i + 1..].iter().map(|(_, rhs)| rhs);
+                            if found == Type::Never
+                                || is_assigned(binding.name, scope.chain([&**body]))
+                            {
+                                Type::Any
+                            } else {
+                                found
+                            }
+                        }
+                    };
+                    env = env.update(binding.name, ty);
+                }
+                self.infer(&env, body)
+            }
+            ExprKind::UnOp(op, e) => match op {
+                Op1::Add1 | Op1::Sub1 => match self.operand(env, e, "a number", Type::is_number) {
+                    ty @ (Type::Int | Type::Float) => ty,
+                    _ => Type::Any,
+                },
+                Op1::BitNot | Op1::Abs => {
+                    self.operand(env, e, "int", is_int);
+                    Type::Int
+                }
+                Op1::Print => self.infer(env, e),
+                Op1::IsNum | Op1::IsFloat | Op1::IsBool | Op1::IsVec => {
+                    self.infer(env, e);
+                    Type::Bool
+                }
+                Op1::ErrorCode => {
+                    self.infer(env, e);
+                    Type::Int
+                }
+            },
+            ExprKind::BinOp(op, e1, e2) => self.infer_bin_op(env, *op, e1, e2),
+            ExprKind::If(e1, e2, e3) => {
+                self.infer(env, e1);
+                let t2 = self.infer(env, e2);
+                let t3 = self.infer(env, e3);
+                join(t2, t3)
+            }
+            ExprKind::Loop(body) => {
+                self.loops.push(Type::Never);
+                self.infer(env, body);
+                self.loops.pop().unwrap()
+            }
+            ExprKind::Break(e) => {
+                let ty = self.infer(env, e);
+                if let Some(loop_ty) = self.loops.last_mut() {
+                    *loop_ty = join(*loop_ty, ty);
+                }
+                Type::Never
+            }
+            ExprKind::Raise(e) => {
+                self.infer(env, e);
+                Type::Never
+            }
+            ExprKind::Set(x, rhs) => {
+                let ty = env.get(x).copied().unwrap_or(Type::Any);
+                let found = self.infer(env, rhs);
+                self.coerce(rhs, ty);
+                match ty {
+                    Type::Any => found,
+                    _ => ty,
+                }
+            }
+            ExprKind::Block(es) => es.iter().fold(Type::Any, |_, e| self.infer(env, e)),
+            ExprKind::Call(fun, args) => {
+                let direct = match &fun.kind {
+                    ExprKind::Var(f) if !env.contains_key(f) => self.funs.get(f).copied(),
+                    _ => None,
+                };
+                match direct {
+                    Some(decl) => {
+                        for (i, arg) in args.iter().enumerate() {
+                            self.infer(env, arg);
+                            if let Some(ty) = decl.params.get(i).and_then(|param| param.ty) {
+                                self.coerce(arg, ty);
+                            }
+                        }
+                        decl.ret.unwrap_or(Type::Any)
+                    }
+                    None => {
+                        self.operand(env, fun, "a function", |_| false);
+                        for arg in args {
+                            self.infer(env, arg);
+                        }
+                        Type::Any
+                    }
+                }
+            }
+            ExprKind::Lambda(params, body) => {
+                let env = params
+                    .iter()
+                    .fold(env.clone(), |env, param| env.update(param.name, Type::Any));
+                let loops = std::mem::take(&mut self.loops);
+                self.infer(&env, body);
+                self.loops = loops;
+                Type::Any
+            }
+            ExprKind::Record(op, es) => {
+                for e in es {
+                    self.infer(env, e);
+                }
+                match op {
+                    RecordOp::Is(_) => Type::Bool,
+                    RecordOp::New(_) | RecordOp::Get(..) | RecordOp::Set(..) => Type::Any,
+                }
+            }
+            ExprKind::Try(body, binding, handler) => {
+                let t1 = self.infer(env, body);
+                let t2 = self.infer(&env.update(binding.name, Type::Any), handler);
+                join(t1, t2)
+            }
+            ExprKind::Dict(op, es) => {
+                for e in es {
+                    self.infer(env, e);
+                }
+                match op {
+                    DictOp::Has => Type::Bool,
+                    DictOp::Keys => Type::vec(Type::Any),
+                    DictOp::New | DictOp::Set | DictOp::Get | DictOp::Remove => Type::Any,
+                }
+            }
+            ExprKind::VecOp(op, es) => self.infer_vec_op(env, e, *op, es),
+            ExprKind::Io(op, es) => {
+                let tys: Vec<Type> = es.iter().map(|e| self.infer(env, e)).collect();
+                match (op, tys.as_slice()) {
+                    (IoOp::ReadInt, []) => Type::Int,
+                    (IoOp::IsEof, []) => Type::Bool,
+                    (IoOp::Display, [ty]) => *ty,
+                    (IoOp::Newline, []) => Type::Nil,
+                    _ => Type::Any,
+                }
+            }
+            ExprKind::MakeVec(size, elem) => {
+                self.operand(env, size, "int", is_int);
+                self.infer(env, elem);
+                Type::vec(Type::Any)
+            }
+            ExprKind::Vec(es) => {
+                for e in es {
+                    self.infer(env, e);
+                }
+                Type::vec(Type::Any)
+            }
+            ExprKind::VecSet(vec, idx, elem) => {
+                let vec = self.operand(env, vec, "a vector", Type::is_vec);
+                self.operand(env, idx, "int", is_int);
+                self.infer(env, elem);
+                self.check_elem(elem, vec.elem());
+                Type::vec(vec.elem())
+            }
+            ExprKind::VecGet(vec, idx) => {
+                let vec = self.operand(env, vec, "a vector", Type::is_vec);
+                self.operand(env, idx, "int", is_int);
+                self.read_elem(e, vec.elem())
+            }
+            ExprKind::VecLen(vec) => {
+                self.operand(env, vec, "a vector", Type::is_vec);
+                Type::Int
+            }
+            ExprKind::Match(e, arms) => {
+                self.infer(env, e);
+                arms.iter().fold(Type::Never, |ty, arm| {
+                    let env = arm
+                        .pattern
+                        .bindings()
+                        .iter()
+                        .fold(env.clone(), |env, (binding, _)| {
+                            env.update(binding.name, Type::Any)
+                        });
+                    join(ty, self.infer(&env, &arm.body))
+                })
+            }
+        };
+        self.types.types.insert(e, ty);
+        ty
+    }
+
+    fn infer_bin_op(&mut self, env: &Env, op: Op2, e1: &Expr, e2: &Expr) -> Type {
+        match op {
+            Op2::Plus | Op2::Minus | Op2::Times | Op2::Divide => {
+                let t1 = self.operand(env, e1, "a number", Type::is_number);
+                let t2 = self.operand(env, e2, "a number", Type::is_number);
+                match (t1, t2) {
+                    (Type::Int, Type::Int) => Type::Int,
+                    (Type::Float, Type::Int | Type::Float) | (Type::Int, Type::Float) => {
+                        Type::Float
+                    }
+                    _ => Type::Any,
+                }
+            }
+            Op2::Greater | Op2::GreaterEqual | Op2::Less | Op2::LessEqual => {
+                self.operand(env, e1, "a number", Type::is_number);
+                self.operand(env, e2, "a number", Type::is_number);
+                Type::Bool
+            }
+            Op2::Mod
+            | Op2::Remainder
+            | Op2::BitAnd
+            | Op2::BitOr
+            | Op2::BitXor
+            | Op2::ArithShift
+            | Op2::Min
+            | Op2::Max => {
+                self.operand(env, e1, "int", is_int);
+                self.operand(env, e2, "int", is_int);
+                Type::Int
+            }
+            Op2::Equal => {
+                let t1 = self.infer(env, e1);
+                let t2 = self.infer(env, e2);
+                if self.strict && known(t1) && known(t2) && tag_class(t1) != tag_class(t2) {
+                    self.mismatch(format!("a value comparable with {t1}"), t2, e2.span);
+                }
+                Type::Bool
+            }
+            Op2::StructEqual => {
+                self.infer(env, e1);
+                self.infer(env, e2);
+                Type::Bool
+            }
+        }
+    }
+
+    /// The type of a vector operation. Its vector operands are the ones named `vec`, `dst` and
+    /// `src` in [`VecOp`].
+    fn infer_vec_op(&mut self, env: &Env, e: &Expr, op: VecOp, es: &[Expr]) -> Type {
+        match (op, es) {
+            (VecOp::Push, [vec, elem]) => {
+                let vec = self.operand(env, vec, "a vector", Type::is_vec);
+                self.infer(env, elem);
+                self.check_elem(elem, vec.elem());
+                Type::vec(vec.elem())
+            }
+            (VecOp::Pop, [vec]) => {
+                let vec = self.operand(env, vec, "a vector", Type::is_vec);
+                self.read_elem(e, vec.elem())
+            }
+            (VecOp::Slice, [vec, start, end]) => {
+                let vec = self.operand(env, vec, "a vector", Type::is_vec);
+                self.operand(env, start, "int", is_int);
+                self.operand(env, end, "int", is_int);
+                Type::vec(vec.elem())
+            }
+            (VecOp::Copy, [dst, dst_start, src, src_start, n]) => {
+                let dst_ty = self.operand(env, dst, "a vector", Type::is_vec);
+                self.operand(env, dst_start, "int", is_int);
+                let src_ty = self.operand(env, src, "a vector", Type::is_vec);
+                self.operand(env, src_start, "int", is_int);
+                self.operand(env, n, "int", is_int);
+                if !consistent(src_ty.elem(), dst_ty.elem()) {
+                    self.mismatch(Type::vec(dst_ty.elem()), src_ty, src.span);
+                }
+                Type::vec(dst_ty.elem())
+            }
+            (VecOp::Append, [a, b]) => {
+                let a = self.operand(env, a, "a vector", Type::is_vec);
+                let b = self.operand(env, b, "a vector", Type::is_vec);
+                Type::vec(join(a.elem(), b.elem()))
+            }
+            _ => unreachable!("wrong number of operands to {}", op.name()),
+        }
+    }
+
+    /// Finds the type of the operand `e` of a built-in operation, reporting it in strict mode if it
+    /// can't be `ok`.
+    fn operand(&mut self, env: &Env, e: &Expr, expected: &str, ok: fn(Type) -> bool) -> Type {
+        let found = self.infer(env, e);
+        if self.strict && known(found) && !ok(found) {
+            self.mismatch(expected, found, e.span);
+        }
+        found
+    }
+
+    /// The type of `e`, which reads an element of a vector with elements of type `elem`. Elements
+    /// can be written through any alias of the vector, so they are checked as they are read.
+    fn read_elem(&mut self, e: &Expr, elem: Type) -> Type {
+        if elem != Type::Any {
+            self.types.casts.insert(e, elem);
+        }
+        elem
+    }
+
+    /// Reports `e`, which is stored in a vector with elements of type `elem`, if it can't have
+    /// that type.
+    fn check_elem(&mut self, e: &Expr, elem: Type) {
+        let found = self.types.of(e);
+        if !consistent(found, elem) {
+            self.mismatch(elem, found, e.span);
+        }
+    }
+
+    /// Makes sure `e` produces a value of type `expected`: an error if it can't, and a check at
+    /// run time if its type is only known to be `any`. The branches of an `if` and the like are
+    /// coerced on their own, so that a call in tail position that already has the right type stays
+    /// a tail call.
+    fn coerce(&mut self, e: &Expr, expected: Type) {
+        let found = self.types.of(e);
+        if fits(found, expected) {
+            return;
+        }
+        let branches: Vec<&Expr> = match &e.kind {
+            ExprKind::If(_, e2, e3) => vec![e2, e3],
+            ExprKind::Let(_, body) => vec![body],
+            ExprKind::Block(es) => vec![&es[es.len() - 1]],
+            ExprKind::Try(body, _, handler) => vec![body, handler],
+            ExprKind::Match(_, arms) => arms.iter().map(|arm| &arm.body).collect(),
+            _ if found == Type::Any => {
+                self.types.casts.insert(e, expected);
+                return;
+            }
+            _ => {
+                self.mismatch(expected, found, e.span);
+                return;
+            }
+        };
+        for branch in branches {
+            self.coerce(branch, expected);
+        }
+        self.types.types.insert(e, expected);
+    }
+
+    fn mismatch(&mut self, expected: impl ToString, found: Type, span: Span) {
+        self.errors.push(CompileError::TypeMismatch {
+            expected: expected.to_string(),
+            found,
+            span,
+        });
+    }
+}
+
+/// Whether a value of type `a` may have type `b`.
+fn consistent(a: Type, b: Type) -> bool {
+    match (a, b) {
+        (Type::Any | Type::Never, _) | (_, Type::Any | Type::Never) => true,
+        (Type::Vec(a), Type::Vec(b)) => consistent(*a, *b),
+        _ => a == b,
+    }
+}
+
+/// Whether a value of type `found` has type `expected` without checking it at run time. Vectors
+/// only need to be vectors, since their elements are checked as they are read.
+fn fits(found: Type, expected: Type) -> bool {
+    expected == Type::Any || (found != Type::Any && consistent(found, expected))
+}
+
+/// The type of a value that has either type.
+fn join(a: Type, b: Type) -> Type {
+    match (a, b) {
+        (Type::Never, ty) | (ty, Type::Never) => ty,
+        _ if a == b => a,
+        (Type::Vec(a), Type::Vec(b)) => Type::vec(join(*a, *b)),
+        _ => Type::Any,
+    }
+}
+
+/// Whether anything is known about a value of type `ty`.
+fn known(ty: Type) -> bool {
+    !matches!(ty, Type::Any | Type::Never)
+}
+
+fn is_int(ty: Type) -> bool {
+    ty == Type::Int
+}
+
+/// Values can only be compared with `=` if they are both numbers, both booleans, or both vectors
+/// or nil, which share a tag.
+fn tag_class(ty: Type) -> u8 {
+    match ty {
+        Type::Int | Type::Float => 0,
+        Type::Bool => 1,
+        _ => 2,
+    }
+}
+
+/// Whether `x` is assigned with `set!` anywhere in `scope`.
+pub(crate) fn is_assigned<'a>(x: Symbol, scope: impl IntoIterator<Item = &'a Expr>) -> bool {
+    scope.into_iter().any(|e| {
+        let mut assigned = matches!(&e.kind, ExprKind::Set(y, _) if *y == x);
+        e.for_each_child(|e| assigned |= is_assigned(x, [e]));
+        assigned
+    })
+}
@@ -34,7 +34,7 @@ success_tests! {
         name: merge_sort,
         file: "merge_sort.snek",
         input: "1000",
-        expected: "89"
+        expected: "[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74, 75, 76, 77, 78, 79, 80, 81, 82, 83, 84, 85, 86, 87, 88, 89, 90, 91, 92, 93, 94, 95, 96, 97, 98, 99, 100, 101, 102, 103, 104, 105, 106, 107, 108, 109, 110, 111, 112, 113, 114, 115, 116, 117, 118, 119, 120, 121, 122, 123, 124, 125, 126, 127, 128, 129, 130, 131, 132, 133, 134, 135, 136, 137, 138, 139, 140, 141, 142, 143, 144, 145, 146, 147, 148, 149, 150, 151, 152, 153, 154, 155, 156, 157, 158, 159, 160, 161, 162, 163, 164, 165, 166, 167, 168, 169, 170, 171, 172, 173, 174, 175, 176, 177, 178, 179, 180, 181, 182, 183, 184, 185, 186, 187, 188, 189, 190, 191, 192, 193, 194, 195, 196, 197, 198, 199, 200, 201, 202, 203, 204, 205, 206, 207, 208, 209, 210, 211, 212, 213, 214, 215, 216, 217, 218, 219, 220, 221, 222, 223, 224, 225, 226, 227, 228, 229, 230, 231, 232, 233, 234, 235, 236, 237, 238, 239, 240, 241, 242, 243, 244, 245, 246, 247, 248, 249, 250, 251, 252, 253, 254, 255, 256, 257, 258, 259, 260, 261, 262, 263, 264, 265, 266, 267, 268, 269, 270, 271, 272, 273, 274, 275, 276, 277, 278, 279, 280, 281, 282, 283, 284, 285, 286, 287, 288, 289, 290, 291, 292, 293, 294, 295, 296, 297, 298, 299, 300, 301, 302, 303, 304, 305, 306, 307, 308, 309, 310, 311, 312, 313, 314, 315, 316, 317, 318, 319, 320, 321, 322, 323, 324, 325, 326, 327, 328, 329, 330, 331, 332, 333, 334, 335, 336, 337, 338, 339, 340, 341, 342, 343, 344, 345, 346, 347, 348, 349, 350, 351, 352, 353, 354, 355, 356, 357, 358, 359, 360, 361, 362, 363, 364, 365, 366, 367, 368, 369, 370, 371, 372, 373, 374, 375, 376, 377, 378, 379, 380, 381, 382, 383, 384, 385, 386, 387, 388, 389, 390, 391, 392, 393, 394, 395, 396, 397, 398, 399, 400, 401, 402, 403, 404, 405, 406, 407, 408, 409, 410, 411, 412, 413, 414, 415, 416, 417, 418, 419, 420, 421, 422, 423, 424, 425, 426, 427, 428, 429, 430, 431, 432, 433, 434, 435, 436, 437, 438, 439, 440, 441, 442, 443, 444, 445, 446, 447, 448, 449, 450, 451, 452, 453, 454, 455, 456, 457, 458, 459, 460, 461, 462, 463, 464, 465, 466, 467, 468, 469, 470, 471, 472, 473, 474, 475, 476, 477, 478, 479, 480, 481, 482, 483, 484, 485, 486, 487, 488, 489, 490, 491, 492, 493, 494, 495, 496, 497, 498, 499, 500, 501, 502, 503, 504, 505, 506, 507, 508, 509, 510, 511, 512, 513, 514, 515, 516, 517, 518, 519, 520, 521, 522, 523, 524, 525, 526, 527, 528, 529, 530, 531, 532, 533, 534, 535, 536, 537, 538, 539, 540, 541, 542, 543, 544, 545, 546, 547, 548, 549, 550, 551, 552, 553, 554, 555, 556, 557, 558, 559, 560, 561, 562, 563, 564, 565, 566, 567, 568, 569, 570, 571, 572, 573, 574, 575, 576, 577, 578, 579, 580, 581, 582, 583, 584, 585, 586, 587, 588, 589, 590, 591, 592, 593, 594, 595, 596, 597, 598, 599, 600, 601, 602, 603, 604, 605, 606, 607, 608, 609, 610, 611, 612, 613, 614, 615, 616, 617, 618, 619, 620, 621, 622, 623, 624, 625, 626, 627, 628, 629, 630, 631, 632, 633, 634, 635, 636, 637, 638, 639, 640, 641, 642, 643, 644, 645, 646, 647, 648, 649, 650, 651, 652, 653, 654, 655, 656, 657, 658, 659, 660, 661, 662, 663, 664, 665, 666, 667, 668, 669, 670, 671, 672, 673, 674, 675, 676, 677, 678, 679, 680, 681, 682, 683, 684, 685, 686, 687, 688, 689, 690, 691, 692, 693, 694, 695, 696, 697, 698, 699, 700, 701, 702, 703, 704, 705, 706, 707, 708, 709, 710, 711, 712, 713, 714, 715, 716, 717, 718, 719, 720, 721, 722, 723, 724, 725, 726, 727, 728, 729, 730, 731, 732, 733, 734, 735, 736, 737, 738, 739, 740, 741, 742, 743, 744, 745, 746, 747, 748, 749, 750, 751, 752, 753, 754, 755, 756, 757, 758, 759, 760, 761, 762, 763, 764, 765, 766, 767, 768, 769, 770, 771, 772, 773, 774, 775, 776, 777, 778, 779, 780, 781, 782, 783, 784, 785, 786, 787, 788, 789, 790, 791, 792, 793, 794, 795, 796, 797, 798, 799, 800, 801, 802, 803, 804, 805, 806, 807, 808, 809, 810, 811, 812, 813, 814, 815, 816, 817, 818, 819, 820, 821, 822, 823, 824, 825, 826, 827, 828, 829, 830, 831, 832, 833, 834, 835, 836, 837, 838, 839, 840, 841, 842, 843, 844, 845, 846, 847, 848, 849, 850, 851, 852, 853, 854, 855, 856, 857, 858, 859, 860, 861, 862, 863, 864, 865, 866, 867, 868, 869, 870, 871, 872, 873, 874, 875, 876, 877, 878, 879, 880, 881, 882, 883, 884, 885, 886, 887, 888, 889, 890, 891, 892, 893, 894, 895, 896, 897, 898, 899, 900, 901, 902, 903, 904, 905, 906, 907, 908, 909, 910, 911, 912, 913, 914, 915, 916, 917, 918, 919, 920, 921, 922, 923, 924, 925, 926, 927, 928, 929, 930, 931, 932, 933, 934, 935, 936, 937, 938, 939, 940, 941, 942, 943, 944, 945, 946, 947, 948, 949, 950, 951, 952, 953, 954, 955, 956, 957, 958, 959, 960, 961, 962, 963, 964, 965, 966, 967, 968, 969, 970, 971, 972, 973, 974, 975, 976, 977, 978, 979, 980, 981, 982, 983, 984, 985, 986, 987, 988, 989, 990, 991, 992, 993, 994, 995, 996, 997, 998, 999, 1000]"
     },
     {
         name: insertion_sort,
@@ -63,6 +63,213 @@ success_tests! {
         input: "128",
         expected: "89"
     },
+    {
+        name: reader_syntax,
+        file: "reader_syntax.snek",
+        expected: "1000\n[[16, 10, 1000, 1], []]\n26",
+    },
+    {
+        name: closures,
+        file: "closures.snek",
+        expected: "7\n13\n3\n[1, [4, [5, nil]]]\n[5, [4, [1, nil]]]\n<function>",
+    },
+    {
+        name: tail_calls,
+        file: "tail_calls.snek",
+        input: "1000000",
+        heap_size: 20,
+        expected: "1000000\ntrue\n42\n3\ntrue\n[1, 2]",
+    },
+    {
+        name: variadic,
+        file: "variadic.snek",
+        input: "1000",
+        heap_size: 20,
+        expected: "0\n10\n[1, nil]\n[1, [2, [3, nil]]]\n[1, 1]",
+    },
+    {
+        name: records,
+        file: "records.snek",
+        input: "20",
+        heap_size: 200,
+        expected: "#point{x: 1, y: 11}\ntrue\nfalse\nfalse\ntrue\nfalse\n#point{x: #point{...}, y: 11}\n#node{key: 2, left: #node{key: 1, left: nil, right: nil}, right: nil}",
+    },
+    {
+        name: similar_names,
+        file: "similar_names.snek",
+        expected: "[2, 10, true]",
+    },
+    {
+        name: equal,
+        file: "equal.snek",
+        expected: "true\nfalse\nfalse\ntrue\nfalse\ntrue\nfalse\nfalse\ntrue\nfalse\ntrue\nfalse\ntrue",
+    },
+    {
+        name: bignum,
+        file: "bignum.snek",
+        input: "30",
+        heap_size: 40,
+        expected: "265252859812191058636308480000000\n870\n-265252859812191058636308480000000\ntrue\ntrue\nfalse\ntrue\n4611686018427387904\n4611686018427387903\ntrue\n530505719624382117272616960000200\n100",
+    },
+    {
+        name: bignum_input,
+        file: "bignum_input.snek",
+        input: "100000000000000000000",
+        expected: "100000000000000000000\ntrue\n-100000000000000000000",
+    },
+    {
+        name: floats,
+        file: "floats.snek",
+        input: "0.25",
+        heap_size: 30,
+        expected: "3.75\n4.5\n-0.5\n0.25\n3\n2.5\ntrue\ntrue\ntrue\ntrue\ntrue\nfalse\nfalse\ntrue\n2.5\n9.223372036854776e18\ntrue\ninf\n-2e-6\n50.0\n0.5",
+    },
+    {
+        name: int_ops,
+        file: "int_ops.snek",
+        input: "1",
+        expected: "[1, 2, -2, 0]\n[1, -1, 1]\n[8, 14, 6, -6]\n[6, 0]\n[48, 3, -4]\n[0, 0, -1]\n[4, 4, -2, 3]\n2305843009213693952",
+    },
+    {
+        name: int_ops_bignum_result,
+        file: "int_ops.snek",
+        input: "2",
+        expected: "[1, 2, -2, 0]\n[1, -1, 1]\n[8, 14, 6, -6]\n[6, 0]\n[48, 3, -4]\n[0, 0, -1]\n[4, 4, -2, 3]\n4611686018427387904",
+    },
+    {
+        name: bignum_int_ops,
+        file: "bignum_int_ops.snek",
+        input: "100",
+        expected: "[18446744073709551612, 1, 0, -5, 2]\n[1180591620717411303424, 2305843009213693951, -1]\n[18446744073709551612, 18446744073709551613, 0, -18446744073709551613]\n[4611686018427387904, 18446744073709551612]\n[1180591620717411303424, 4611686018427387904]\n1267650600228229401496703205376",
+    },
+    {
+        name: bignum_int_ops_inferred,
+        file: "bignum_int_ops.snek",
+        args: ["--infer-types"],
+        input: "100",
+        expected: "[18446744073709551612, 1, 0, -5, 2]\n[1180591620717411303424, 2305843009213693951, -1]\n[18446744073709551612, 18446744073709551613, 0, -18446744073709551613]\n[4611686018427387904, 18446744073709551612]\n[1180591620717411303424, 4611686018427387904]\n1267650600228229401496703205376",
+    },
+    {
+        name: divide,
+        file: "divide.snek",
+        input: "5",
+        expected: "4611686018427387904\n1\n2",
+    },
+    {
+        name: exceptions,
+        file: "exceptions.snek",
+        input: "5",
+        heap_size: 40,
+        expected: "3\n1\n41\n10\n8\n#error{invalid argument}\ntrue",
+    },
+    {
+        name: dict,
+        file: "dict.snek",
+        heap_size: 2200,
+        expected: "[10, 20, 30]\n[40, 50, 0]\nfalse\n[11, false, 4]\n60\n#dict{[1, [2, 3]]: 60}\n[104, 51]\n[99, false]\n5071\n50",
+    },
+    {
+        name: vec_ops,
+        file: "vec_ops.snek",
+        heap_size: 120,
+        expected: "[[0, 1, 2, 3, 4, 5, 6, 7, 8, 9], 10, 1]\n[9, 8, 8, false]\ntrue\n[[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11], [7, 8]]\n[[2, 3, 4], [], [1, 2, 3, 4, 5, 6, 7, 8]]\n[1, 2, 1, 2, 3, 6]\n[2, 3, 6, 2, 3, 6]\n[3, 6]\n25",
+    },
+    {
+        name: match_expr,
+        file: "match.snek",
+        heap_size: 420,
+        expected: "5050\n[0, 1, 2, 3]\n[7, 400, 12]\n[7, 5, 6]\n[2, [3, [5, [8, nil]]]]\n#error{no match: [1, 2, 3]}\n10\n1",
+    },
+    {
+        name: typed,
+        file: "typed.snek",
+        heap_size: 100,
+        expected: "10\n15511210043330985984000000\n5\n[1.0, 3.0]\n120\n30",
+    },
+    {
+        name: typed_cast,
+        file: "typed_cast.snek",
+        input: "21",
+        expected: "42",
+    },
+    {
+        name: infer_types,
+        file: "infer.snek",
+        args: ["--infer-types"],
+        input: "41",
+        expected: "true\n[5, 7]\ntrue\n30\n[0.5, 1.5]\n4\n11\n42",
+    },
+    {
+        name: infer_types_fallback,
+        file: "infer_fallback.snek",
+        args: ["--infer-types"],
+        warning: "infer_fallback.snek:3:35: warning: type inference failed, compiling with dynamic checks: this would need a type that contains itself",
+        expected: "3",
+    },
+    {
+        name: break_in_try,
+        file: "break_in_try.snek",
+        expected: "1\n3\n42",
+    },
+    {
+        name: local_funs,
+        file: "local_funs.snek",
+        heap_size: 100,
+        expected: "[0, 1, 2, 3]\n[false, true]\n30\n3\n33\n30\n[10]\n6\n25\n-10",
+    },
+    {
+        name: local_funs_inferred,
+        file: "local_funs.snek",
+        args: ["--infer-types"],
+        heap_size: 100,
+        expected: "[0, 1, 2, 3]\n[false, true]\n30\n3\n33\n30\n[10]\n6\n25\n-10",
+    },
+    {
+        name: globals,
+        file: "globals.snek",
+        heap_size: 40,
+        expected: "[1, 2, 3]\n[1, 2, 3, 1, 2, 3]\n2\n9\n4\n5\n5",
+    },
+    {
+        name: globals_inferred,
+        file: "globals.snek",
+        args: ["--infer-types"],
+        heap_size: 40,
+        expected: "[1, 2, 3]\n[1, 2, 3, 1, 2, 3]\n2\n9\n4\n5\n5",
+    },
+    {
+        name: input_in_func,
+        file: "input_in_func.snek",
+        input: "10",
+        expected: "15\n1010",
+    },
+    {
+        name: structured_input,
+        file: "structured_input.snek",
+        input: "[1, [2, 3], nil, true]",
+        heap_size: 60,
+        argv: ["7", "[8,99999999999999999999]"],
+        expected: "[1, [2, 3], nil, true]\n[7, [8, 99999999999999999999]]\n[1, [2, 3], nil, true, 4]\n100000000000000000024",
+    },
+    {
+        name: console_io,
+        file: "console_io.snek",
+        heap_size: 30,
+        stdin: "3\n1\n-2\n 99999999999999999999 \n[1, [2, nil]]\n[]\n[3.5, [true, [4]]]\n",
+        expected: "[0, 1][1, -1][2, 99999999999999999998]\n[1, [2, nil]]\n[]\n[3.5, [true, [4]]]\n[3.5, [true, [4]]]",
+    },
+    {
+        name: read_catch,
+        file: "read_catch.snek",
+        stdin: "two\n[1,\n7\n",
+        expected: "11\n11\n7\n11\n11",
+    },
+    {
+        name: closure_gc,
+        file: "closure_gc.snek",
+        heap_size: 150,
+        expected: "[0, 8]",
+    },
 }
 
 runtime_error_tests! {
@@ -85,7 +292,267 @@ runtime_error_tests! {
         file: "vec_get.snek",
         input: "5",
         expected: "",
-    }
+    },
+    {
+        name: call_non_function,
+        file: "call_non_function.snek",
+        expected: "invalid argument",
+    },
+    {
+        name: record_wrong_type,
+        file: "record_wrong_type.snek",
+        expected: "wrong record type",
+    },
+    {
+        name: shift_overflow,
+        file: "bignum_int_ops.snek",
+        input: "100000000000",
+        expected: "overflow",
+    },
+    {
+        name: min_bool,
+        file: "min_bool.snek",
+        expected: "invalid argument",
+    },
+    {
+        name: divide_by_zero,
+        file: "divide.snek",
+        input: "0",
+        expected: "division by zero",
+    },
+    {
+        name: divide_bignum_by_zero,
+        file: "divide_bignum.snek",
+        input: "0",
+        expected: "division by zero",
+    },
+    {
+        name: mod_by_zero,
+        file: "mod_zero.snek",
+        input: "0",
+        expected: "division by zero",
+    },
+    {
+        name: closure_wrong_arity,
+        file: "closure_wrong_arity.snek",
+        expected: "wrong number of arguments",
+    },
+    {
+        name: dict_bad_key,
+        file: "dict_bad_key.snek",
+        expected: "invalid argument",
+    },
+    {
+        name: vec_pop_empty,
+        file: "vec_pop_empty.snek",
+        expected: "index out of bounds",
+    },
+    {
+        name: vec_pop_slack_overflow,
+        file: "vec_pop_slack.snek",
+        input: "16777217",
+        heap_size: 16777300,
+        expected: "overflow",
+    },
+    {
+        name: vec_copy_bounds,
+        file: "vec_copy_bounds.snek",
+        expected: "index out of bounds",
+    },
+    {
+        name: match_no_match,
+        file: "match_no_match.snek",
+        expected: "no match: [1, [2, 3]]",
+    },
+    {
+        name: typed_cast_fail,
+        file: "typed_cast.snek",
+        input: "true",
+        expected: "invalid argument",
+    },
+    {
+        name: infer_types_cast_fail,
+        file: "infer.snek",
+        args: ["--infer-types"],
+        input: "true",
+        expected: "invalid argument",
+    },
+    {
+        name: infer_input_in_fun,
+        file: "infer_input_in_fun.snek",
+        args: ["--infer-types"],
+        input: "12345",
+        warning: "infer_input_in_fun.snek:1:10: warning: type inference failed, compiling with dynamic checks: this is only known when the program runs, but is used as (vec _)",
+        expected: "invalid argument",
+    },
+    {
+        name: infer_catch_in_fun,
+        file: "infer_catch_in_fun.snek",
+        args: ["--infer-types"],
+        warning: "infer_catch_in_fun.snek:1:35: warning: type inference failed, compiling with dynamic checks: this is only known when the program runs, but is used as (vec _)",
+        expected: "invalid argument",
+    },
+    {
+        name: infer_read_in_fun,
+        file: "infer_read_in_fun.snek",
+        args: ["--infer-types"],
+        stdin: "12345\n",
+        warning: "infer_read_in_fun.snek:1:10: warning: type inference failed, compiling with dynamic checks: this is only known when the program runs, but is used as (vec _)",
+        expected: "invalid argument",
+    },
+    {
+        name: infer_rest_in_fun,
+        file: "infer_rest_in_fun.snek",
+        args: ["--infer-types"],
+        warning: "infer_rest_in_fun.snek:1:17: warning: type inference failed, compiling with dynamic checks: this is only known when the program runs, but is used as (vec _)",
+        expected: "invalid argument",
+    },
+    {
+        name: uncaught_raise,
+        file: "uncaught_raise.snek",
+        expected: "uncaught exception: [3, 4]",
+    },
+    {
+        name: reraise_builtin_error,
+        file: "reraise.snek",
+        input: "7",
+        expected: "division by zero",
+    },
+    {
+        name: malformed_input,
+        file: "structured_input.snek",
+        input: "[1, 2",
+        expected: "invalid input: unclosed `[` in `[1, 2`",
+    },
+    {
+        name: invalid_input_value,
+        file: "structured_input.snek",
+        input: "[1, yes]",
+        expected: "invalid input: `yes` is not a value",
+    },
+    {
+        name: read_past_eof,
+        file: "console_io.snek",
+        stdin: "2\n1\n",
+        expected: "invalid input",
+    },
+    {
+        name: read_int_not_int,
+        file: "console_io.snek",
+        stdin: "1\ntwo\n",
+        expected: "invalid input",
+    },
 }
 
-static_error_tests! {}
+static_error_tests! {
+    {
+        name: unbound_id_location,
+        file: "unbound_id.snek",
+        expected: "unbound_id.snek:1:1: error[E0005]: unbound variable identifier x",
+    },
+    {
+        name: duplicate_binding_location,
+        file: "duplicate_binding.snek",
+        expected: "duplicate_binding.snek:1:15: error[E0003]: duplicate binding x",
+    },
+    {
+        name: match_duplicate_binding,
+        file: "match_duplicate_binding.snek",
+        expected: "match_duplicate_binding.snek:1:18: error[E0003]: duplicate binding x",
+    },
+    {
+        name: typed_mismatch,
+        file: "typed_mismatch.snek",
+        expected: "typed_mismatch.snek:3:9: error[E0011]: mismatched types: expected int, found bool",
+    },
+    {
+        name: typed_builtin,
+        file: "typed_builtin.snek",
+        expected: "typed_builtin.snek:1:32: error[E0011]: mismatched types: expected a number, found bool",
+    },
+    {
+        name: bad_func_arity_location,
+        file: "bad_func_arity.snek",
+        expected: "bad_func_arity.snek:3:8: error[E0007]: function test takes 3 arguments but 2 were supplied",
+    },
+    {
+        name: malformed_let_nested_location,
+        file: "malformed_let_nested.snek",
+        expected: "malformed_let_nested.snek:7:19: error[E0001]: Invalid syntax: expected a list",
+    },
+    {
+        name: break_outside_loop,
+        file: "break_outside_loop.snek",
+        expected: "break_outside_loop.snek:4:5: error[E0008]: break outside loop",
+    },
+    {
+        name: number_bounds_fail,
+        file: "number_bounds_fail.snek",
+        expected: "error[E0002]: Invalid syntax: integer literal overflow",
+    },
+    {
+        name: many_parse_errors,
+        file: "many_parse_errors.snek",
+        expected: "aborting due to 3 previous errors",
+    },
+    {
+        name: many_parse_errors_recovers_after_unclosed,
+        file: "many_parse_errors.snek",
+        expected: "many_parse_errors.snek:8:3: error[E0001]: Invalid syntax: malformed if",
+    },
+    {
+        name: parse_errors_recover_at_define,
+        file: "parse_errors_define.snek",
+        expected: "parse_errors_define.snek:4:15: error[E0001]: Invalid syntax: malformed if",
+    },
+    {
+        name: many_semantic_errors,
+        file: "many_semantic_errors.snek",
+        expected: "aborting due to 6 previous errors",
+    },
+    {
+        name: mismatched_bracket,
+        file: "mismatched_bracket.snek",
+        expected: "mismatched_bracket.snek:1:16: error[E0001]: Invalid syntax: mismatched `)`, expected `]` to close the `[` at 1:10",
+    },
+    {
+        name: variadic_arity,
+        file: "variadic_arity.snek",
+        expected: "variadic_arity.snek:3:1: error[E0007]: function f takes at least 2 arguments but 1 were supplied",
+    },
+    {
+        name: variadic_as_value,
+        file: "variadic_as_value.snek",
+        expected: "variadic_as_value.snek:3:10: error[E0010]: function f takes a rest parameter and cannot be used as a value",
+    },
+    {
+        name: assign_captured,
+        file: "assign_captured.snek",
+        expected: "assign_captured.snek:2:15: error[E0012]: cannot assign to n, which the local function captures",
+    },
+    {
+        name: global_order,
+        file: "global_order.snek",
+        expected: "global_order.snek:1:11: error[E0005]: unbound variable identifier b",
+    },
+    {
+        name: global_read_early,
+        file: "global_read_early.snek",
+        expected: "global_read_early.snek:3:15: error[E0013]: global late may be read through f before it is defined",
+    },
+    {
+        name: unclosed_paren,
+        file: "unclosed_paren.snek",
+        expected: "unclosed_paren.snek:4:1: error[E0001]: Invalid syntax: unclosed `(`",
+    },
+    {
+        name: bad_hex_literal,
+        file: "bad_hex_literal.snek",
+        expected: "bad_hex_literal.snek:1:4: error[E0001]: Invalid syntax: invalid integer literal `0x1G`",
+    },
+    {
+        name: unterminated_comment,
+        file: "unterminated_comment.snek",
+        expected: "unterminated_comment.snek:2:3: error[E0001]: Invalid syntax: unterminated block comment",
+    },
+}
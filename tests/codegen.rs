@@ -0,0 +1,56 @@
+use forest_flame::{
+    compiler::{self, Options},
+    parser,
+};
+
+/// The sequence `check_is_vec` emits to check that `%rax` holds a vector.
+const VEC_CHECK: &str = "  and r11, 7\n  cmp r11, 1\n  jne invalid_argument\n";
+/// The sequence `check_is_num` emits to check that a vector index in `%rdi` is a fixnum.
+const INDEX_CHECK: &str = "  test rdi, 1\n  jnz invalid_argument\n";
+
+fn compile(src: &str, infer_types: bool) -> String {
+    let prog = parser::parse(src).unwrap();
+    let (asm, warnings) = compiler::compile_with(&prog, Options { infer_types }).unwrap();
+    assert!(warnings.is_empty(), "unexpected warnings: {warnings:?}");
+    asm
+}
+
+#[test]
+fn inferred_types_leave_out_tag_checks() {
+    let src = "
+(fun (first v) (vec-get v 0))
+
+(first [1 2])
+";
+    let checked = compile(src, false);
+    assert!(checked.contains(VEC_CHECK) && checked.contains(INDEX_CHECK));
+    let inferred = compile(src, true);
+    assert!(!inferred.contains(VEC_CHECK), "{inferred}");
+    assert!(!inferred.contains(INDEX_CHECK), "{inferred}");
+}
+
+#[test]
+fn inferred_ints_leave_out_the_float_path() {
+    let src = "
+(fun (count-up i n)
+  (if (= i n) i (count-up (+ i 1) n)))
+
+(count-up 0 10)
+";
+    assert!(compile(src, false).contains("arith_float_"));
+    let inferred = compile(src, true);
+    assert!(!inferred.contains("arith_float_"), "{inferred}");
+}
+
+#[test]
+fn runtime_values_read_in_functions_stay_checked() {
+    for src in [
+        "(fun (f) input)\n(+ (f) 1)",
+        "(fun (f) (try (raise 77) (catch e e)))\n(+ (f) 1)",
+        "(fun (f) (read-value))\n(+ (f) 1)",
+    ] {
+        // The read is cast to an int, since `+` leaves out its own checks on ints.
+        let inferred = compile(src, true);
+        assert!(inferred.contains("check_type_finish_"), "{src}\n{inferred}");
+    }
+}
@@ -0,0 +1,112 @@
+use std::{fs, path::Path, process::Command};
+
+use forest_flame::{formatter, parser};
+
+/// The parsed program's debug output with every span removed, so programs can be compared by
+/// structure alone.
+fn shape(src: &str) -> String {
+    let prog = format!("{:?}", parser::parse(src).unwrap());
+    let mut out = String::new();
+    let mut rest = &prog[..];
+    while let Some(i) = rest.find("Span {") {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + rest[i..].find('}').unwrap() + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn snek_files() -> impl Iterator<Item = (String, String)> {
+    fs::read_dir("tests").unwrap().filter_map(|entry| {
+        let path = entry.unwrap().path();
+        if path.extension()? != "snek" {
+            return None;
+        }
+        let src = fs::read_to_string(&path).unwrap();
+        parser::parse(&src).ok()?;
+        Some((path.display().to_string(), src))
+    })
+}
+
+#[test]
+fn round_trips_every_test_program() {
+    for (path, src) in snek_files() {
+        let formatted = formatter::format(&src).unwrap();
+        assert_eq!(shape(&src), shape(&formatted), "{path} changed meaning");
+        assert_eq!(
+            formatter::format(&formatted).unwrap(),
+            formatted,
+            "{path} is not stable under formatting"
+        );
+        for line in formatted.lines() {
+            assert!(line.len() <= 80 || !line.starts_with(' '), "{path}: {line}");
+        }
+    }
+}
+
+#[test]
+fn keeps_comments_in_place() {
+    let src = "; adds one\n(fun (f x) (+ x 1)) ; trailing\n\n#| main |#\n(block (print (f 1)) ; after print\n ; before last\n (f 2)\n ; dangling\n)\n; the end\n";
+    let expected = "\
+; adds one
+(fun (f x) (+ x 1)) ; trailing
+
+#| main |#
+(block
+  (print (f 1)) ; after print
+  ; before last
+  (f 2)
+  ; dangling
+  )
+; the end
+";
+    assert_eq!(formatter::format(src).unwrap(), expected);
+}
+
+#[test]
+fn breaks_long_forms() {
+    let src = "(let ((a 1) (b 2)) (if (< a b) (some-long-function-name a b a b a b) (another-function b a b a b)))";
+    let expected = "\
+(let ((a 1) (b 2))
+  (if (< a b)
+      (some-long-function-name a b a b a b)
+      (another-function b a b a b)))
+";
+    assert_eq!(formatter::format(src).unwrap(), expected);
+}
+
+#[test]
+fn writes_vectors_with_brackets() {
+    let src = "(let ((v (vec 1 [2 3]))) (match v ([a _] (vec a (vec-get v 1) (some-long-function-name a a a a a a a a a a a a a a a)))))";
+    let expected = "\
+(let ((v [1 [2 3]]))
+  (match v
+    ([a _]
+      [a
+       (vec-get v 1)
+       (some-long-function-name a a a a a a a a a a a a a a a)])))
+";
+    assert_eq!(formatter::format(src).unwrap(), expected);
+}
+
+#[test]
+fn check_and_in_place_modes() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let file = dir.join("fmt_modes.snek");
+    fs::write(&file, "(block  (print 1)\n2)").unwrap();
+    let fmt = |flag: &str| {
+        Command::new(env!("CARGO_BIN_EXE_forest-flame"))
+            .args(["fmt", flag])
+            .arg(&file)
+            .status()
+            .unwrap()
+    };
+
+    assert_eq!(fmt("--check").code(), Some(1));
+    assert!(fmt("--in-place").success());
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        "(block\n  (print 1)\n  2)\n"
+    );
+    assert!(fmt("--check").success());
+}
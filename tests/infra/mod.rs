@@ -1,6 +1,7 @@
 use std::{
+    io::Write,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
 };
 
 #[derive(Debug)]
@@ -36,8 +37,12 @@ macro_rules! tests {
             {
                 name: $name:ident,
                 file: $file:literal,
+                $(args: [$($arg:literal),* $(,)?],)?
                 $(input: $input:literal,)?
                 $(heap_size: $heap_size:literal,)?
+                $(argv: [$($argv:literal),* $(,)?],)?
+                $(stdin: $stdin:literal,)?
+                $(warning: $warning:literal,)?
                 expected: $expected:literal $(,)?
                 $(" $(tt:$tt)* ")?
             }
@@ -47,53 +52,79 @@ macro_rules! tests {
         $(
             #[test]
             fn $name() {
+                #[allow(unused_assignments, unused_mut)]
+                let mut args: &[&str] = &[];
+                $(args = &[$($arg),*];)?
                 #[allow(unused_assignments, unused_mut)]
                 let mut input = None;
                 $(input = Some($input);)?
                 #[allow(unused_assignments, unused_mut)]
                 let mut heap_size = None;
                 $(heap_size = Some($heap_size);)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut argv: &[&str] = &[];
+                $(argv = &[$($argv),*];)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut stdin = None;
+                $(stdin = Some($stdin);)?
+                #[allow(unused_assignments, unused_mut)]
+                let mut warning = None;
+                $(warning = Some($warning);)?
                 let kind = $crate::infra::TestKind::$kind;
-                $crate::infra::run_test(stringify!($name), $subdir, $file, input, heap_size, $expected, kind);
+                let test = $crate::infra::Test {
+                    name: stringify!($name),
+                    subdir: $subdir,
+                    file: $file,
+                    args,
+                    input,
+                    heap_size,
+                    argv,
+                    stdin,
+                    warning,
+                };
+                $crate::infra::run_test(test, $expected, kind);
             }
         )*
     };
 }
 
-pub(crate) fn run_test(
-    name: &str,
-    subdir: Option<&str>,
-    file: &str,
-    input: Option<&str>,
-    heap_size: Option<usize>,
-    expected: &str,
-    kind: TestKind,
-) {
+/// How to compile and run one test program.
+pub(crate) struct Test<'a> {
+    pub name: &'a str,
+    pub subdir: Option<&'a str>,
+    pub file: &'a str,
+    /// Flags passed to the compiler before the file names.
+    pub args: &'a [&'a str],
+    pub input: Option<&'a str>,
+    pub heap_size: Option<usize>,
+    /// Arguments passed to the program after the input and heap size.
+    pub argv: &'a [&'a str],
+    /// What the program reads from stdin. Without it, stdin is empty.
+    pub stdin: Option<&'a str>,
+    /// A warning the compiler must print, if any.
+    pub warning: Option<&'a str>,
+}
+
+pub(crate) fn run_test(test: Test, expected: &str, kind: TestKind) {
     let mut path = PathBuf::new();
     path.push("tests");
-    if let Some(subdir) = subdir {
+    if let Some(subdir) = test.subdir {
         path.push(subdir);
     }
-    path.push(file);
+    path.push(test.file);
 
     match kind {
-        TestKind::Success => run_success_test(name, &path, expected, input, heap_size),
-        TestKind::RuntimeError => run_runtime_error_test(name, &path, expected, input, heap_size),
-        TestKind::StaticError => run_static_error_test(name, &path, expected),
+        TestKind::Success => run_success_test(&test, &path, expected),
+        TestKind::RuntimeError => run_runtime_error_test(&test, &path, expected),
+        TestKind::StaticError => run_static_error_test(&test, &path, expected),
     }
 }
 
-fn run_success_test(
-    name: &str,
-    file: &Path,
-    expected: &str,
-    input: Option<&str>,
-    heap_size: Option<usize>,
-) {
-    if let Err(err) = compile(name, file) {
+fn run_success_test(test: &Test, file: &Path, expected: &str) {
+    if let Err(err) = compile(test, file) {
         panic!("expected a successful compilation, but got an error: `{err}`");
     }
-    match run(name, input, heap_size) {
+    match run(test) {
         Err(err) => {
             panic!("expected a successful execution, but got an error: `{err}`");
         }
@@ -103,17 +134,11 @@ fn run_success_test(
     }
 }
 
-fn run_runtime_error_test(
-    name: &str,
-    file: &Path,
-    expected: &str,
-    input: Option<&str>,
-    heap_size: Option<usize>,
-) {
-    if let Err(err) = compile(name, file) {
+fn run_runtime_error_test(test: &Test, file: &Path, expected: &str) {
+    if let Err(err) = compile(test, file) {
         panic!("expected a successful compilation, but got an error: `{err}`");
     }
-    match run(name, input, heap_size) {
+    match run(test) {
         Ok(out) => {
             panic!("expected a runtime error, but program executed succesfully - expected error: `{expected}`, output: `{out}`");
         }
@@ -121,8 +146,8 @@ fn run_runtime_error_test(
     }
 }
 
-fn run_static_error_test(name: &str, file: &Path, expected: &str) {
-    match compile(name, file) {
+fn run_static_error_test(test: &Test, file: &Path, expected: &str) {
+    match compile(test, file) {
         Ok(()) => {
             panic!(
                 "expected a static error, but compilation succeeded - expected error: `{expected}`"
@@ -132,21 +157,26 @@ fn run_static_error_test(name: &str, file: &Path, expected: &str) {
     }
 }
 
-fn compile(name: &str, file: &Path) -> Result<(), String> {
+fn compile(test: &Test, file: &Path) -> Result<(), String> {
     // Run the compiler
     let compiler: PathBuf = ["target", "debug", env!("CARGO_PKG_NAME")].iter().collect();
     let output = Command::new(&compiler)
+        .args(test.args)
         .arg(file)
-        .arg(&mk_path(name, Ext::Asm))
+        .arg(&mk_path(test.name, Ext::Asm))
         .output()
         .expect("could not run the compiler");
+    let stderr = String::from_utf8(output.stderr).unwrap();
     if !output.status.success() {
-        return Err(String::from_utf8(output.stderr).unwrap());
+        return Err(stderr);
+    }
+    if let Some(warning) = test.warning {
+        check_error_msg(&stderr, warning);
     }
 
     // Assemble and link
     let output = Command::new("make")
-        .arg(&mk_path(name, Ext::Run))
+        .arg(&mk_path(test.name, Ext::Run))
         .output()
         .expect("could not run make");
     assert!(output.status.success(), "linking failed");
@@ -154,15 +184,34 @@ fn compile(name: &str, file: &Path) -> Result<(), String> {
     Ok(())
 }
 
-fn run(name: &str, input: Option<&str>, heap_size: Option<usize>) -> Result<String, String> {
-    let mut cmd = Command::new(&mk_path(name, Ext::Run));
-    if let Some(input) = input {
-        cmd.arg(input);
+fn run(test: &Test) -> Result<String, String> {
+    let mut cmd = Command::new(&mk_path(test.name, Ext::Run));
+    // The runtime reads its arguments by position, so any before one given get their defaults.
+    if test.input.is_some() || test.heap_size.is_some() || !test.argv.is_empty() {
+        cmd.arg(test.input.unwrap_or("false"));
     }
-    if let Some(heap_size) = heap_size {
-        cmd.arg(heap_size.to_string());
+    if test.heap_size.is_some() || !test.argv.is_empty() {
+        cmd.arg(test.heap_size.unwrap_or(10000).to_string());
     }
-    let output = cmd.output().unwrap();
+    cmd.args(test.argv);
+    let output = match test.stdin {
+        Some(stdin) => {
+            let mut child = cmd
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(stdin.as_bytes())
+                .unwrap();
+            child.wait_with_output().unwrap()
+        }
+        None => cmd.output().unwrap(),
+    };
     if output.status.success() {
         Ok(String::from_utf8(output.stdout).unwrap().trim().to_string())
     } else {